@@ -0,0 +1,256 @@
+#[cfg(feature = "gix")]
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use clap::ValueEnum;
+use git2::{Oid, Repository, Sort};
+use crate::error::{Error, Result};
+
+// ********************************************************
+// ********************************************************
+/// Implementation of the read-only git operations on tags and history (`--git-backend`).
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum GitBackendKind {
+    /// libgit2 via the git2 crate
+    #[default]
+    Git2,
+    /// gitoxide, only available if built with the `gix` feature
+    Gix,
+}
+
+static GIT_BACKEND: OnceLock<GitBackendKind> = OnceLock::new();
+/// Selects the backend for the whole run. Fails if gix is selected but was not compiled in.
+pub fn set_git_backend(kind: GitBackendKind) -> Result<()> {
+    if kind == GitBackendKind::Gix && !cfg!(feature = "gix") {
+        return Err(Error::Usage("The gix backend is not available, build with '--features gix' to use it".to_string()));
+    }
+    let _ = GIT_BACKEND.set(kind);
+    Ok(())
+}
+
+/// A commit found by [GitBackend::commits_between].
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalkedCommit {
+    pub id: Oid,
+    pub message: String,
+    pub parent_count: usize,
+}
+
+/// Read-only operations which go over all tags or the whole history and are therefore the
+/// expensive ones on large repositories. Commits, tags and pushes are always done with git2.
+pub trait GitBackend {
+    /// Names of all tags, without `refs/tags/`.
+    fn tag_names(&self) -> Result<Vec<String>>;
+    /// Commits reachable from `to` but not from `from` (all commits if None), newest first.
+    fn commits_between(&self, from: Option<Oid>, to: Oid) -> Result<Vec<WalkedCommit>>;
+    /// Number of commits reachable from HEAD, like 'git rev-list --count HEAD'.
+    fn count_commits(&self) -> Result<u64>;
+}
+
+/// Opens the backend selected with [set_git_backend] (git2 by default) for the repository.
+pub fn open_backend(repo: &Repository) -> Result<Box<dyn GitBackend + '_>> {
+    match GIT_BACKEND.get().copied().unwrap_or_default() {
+        GitBackendKind::Git2 => Ok(Box::new(Git2Backend(repo))),
+        #[cfg(feature = "gix")]
+        GitBackendKind::Gix => Ok(Box::new(GixBackend::open(repo)?)),
+        #[cfg(not(feature = "gix"))]
+        GitBackendKind::Gix => unreachable!("gix backend is rejected by set_git_backend"),
+    }
+}
+
+// ********************************************************
+// ********************************************************
+pub struct Git2Backend<'r>(pub &'r Repository);
+impl GitBackend for Git2Backend<'_> {
+    fn tag_names(&self) -> Result<Vec<String>> {
+        let tns = self.0.tag_names(None).map_err(|e| Error::git("Could not read git tags", e))?;
+        Ok(tns.iter().flatten().map(String::from).collect())
+    }
+
+    fn commits_between(&self, from: Option<Oid>, to: Oid) -> Result<Vec<WalkedCommit>> {
+        let repo = self.0;
+        let mut walk = repo.revwalk().map_err(|e| Error::git("Could not walk git history", e))?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).map_err(|e| Error::git("Could not walk git history", e))?;
+        walk.push(to).map_err(|e| Error::git("Could not walk git history", e))?;
+        if let Some(from) = from {
+            walk.hide(from).map_err(|e| Error::git("Could not walk git history", e))?;
+        }
+        walk.map(|oid| {
+            let oid = oid.map_err(|e| Error::git("Could not walk git history", e))?;
+            let commit = repo.find_commit(oid).map_err(|e| Error::git(format!("Could not find commit {}", oid), e))?;
+            Ok(WalkedCommit { id: oid, message: commit.message().unwrap_or("").to_string(), parent_count: commit.parent_count() })
+        }).collect()
+    }
+
+    fn count_commits(&self) -> Result<u64> {
+        let mut walk = self.0.revwalk().map_err(|e| Error::git("Could not walk the history", e))?;
+        walk.push_head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+        Ok(walk.count() as u64)
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(feature = "gix")]
+pub struct GixBackend(gix::Repository);
+#[cfg(feature = "gix")]
+impl GixBackend {
+    /// Opens the repository of git2 a second time with gitoxide.
+    pub fn open(repo: &Repository) -> Result<Self> {
+        gix::open(repo.path()).map(GixBackend)
+            .map_err(|e| Error::Repository(format!("Could not open {} with gix: {}", repo.path().display(), e)))
+    }
+
+    fn oid(id: gix::ObjectId) -> Oid {
+        Oid::from_bytes(id.as_bytes()).expect("gix and git2 both use sha1 object ids")
+    }
+    fn object_id(oid: Oid) -> gix::ObjectId {
+        gix::ObjectId::from_bytes_or_panic(oid.as_bytes())
+    }
+    fn walk(&self, from: Option<Oid>, to: gix::ObjectId) -> Result<gix::revision::Walk<'_>> {
+        use gix::revision::walk::Sorting;
+        self.0.rev_walk([to]).with_hidden(from.map(Self::object_id))
+            .sorting(Sorting::ByCommitTime(Default::default())).all()
+            .map_err(|e| Error::Repository(format!("Could not walk git history: {}", e)))
+    }
+}
+#[cfg(feature = "gix")]
+impl GitBackend for GixBackend {
+    fn tag_names(&self) -> Result<Vec<String>> {
+        let error = |e: &dyn std::fmt::Display| Error::Repository(format!("Could not read git tags: {}", e));
+        let references = self.0.references().map_err(|e| error(&e))?;
+        let tags = references.tags().map_err(|e| error(&e))?;
+        tags.map(|r| r.map(|r| r.name().shorten().to_string()).map_err(|e| error(&e))).collect()
+    }
+
+    fn commits_between(&self, from: Option<Oid>, to: Oid) -> Result<Vec<WalkedCommit>> {
+        let mut commits = HashMap::new();
+        for info in self.walk(from, Self::object_id(to))? {
+            let info = info.map_err(|e| Error::Repository(format!("Could not walk git history: {}", e)))?;
+            let error = |e: &dyn std::fmt::Display| Error::Repository(format!("Could not find commit {}: {}", info.id, e));
+            let commit = info.object().map_err(|e| error(&e))?;
+            let time = commit.time().map_err(|e| error(&e))?.seconds;
+            let walked = WalkedCommit { id: Self::oid(info.id), message: commit.message_raw_sloppy().to_string(),
+                                        parent_count: info.parent_ids.len() };
+            commits.insert(info.id, (walked, time, info.parent_ids.to_vec()));
+        }
+        Ok(sort_topological(commits))
+    }
+
+    fn count_commits(&self) -> Result<u64> {
+        let head = self.0.head_id().map_err(|e| Error::Repository(format!("Could not resolve HEAD: {}", e)))?;
+        let mut count = 0;
+        for info in self.walk(None, head.detach())? {
+            info.map_err(|e| Error::Repository(format!("Could not walk the history: {}", e)))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Orders the commits like the revwalk of git2 with `TOPOLOGICAL | TIME`: a commit comes after
+/// all of its children, of the commits whose children are done the one with the newest commit
+/// time is next. A plain sort by time differs from it if the clocks of the committers were skewed.
+#[cfg(feature = "gix")]
+fn sort_topological(mut commits: HashMap<gix::ObjectId, (WalkedCommit, i64, Vec<gix::ObjectId>)>) -> Vec<WalkedCommit> {
+    use std::collections::BinaryHeap;
+    let mut children: HashMap<gix::ObjectId, usize> = commits.keys().map(|id| (*id, 0)).collect();
+    for (_, _, parents) in commits.values() {
+        for p in parents {
+            if let Some(n) = children.get_mut(p) { *n += 1; }
+        }
+    }
+    // ties of the time are ordered by id, so the result does not depend on the hash map
+    let mut ready: BinaryHeap<(i64, gix::ObjectId)> = children.iter().filter(|(_, n)| **n == 0)
+        .map(|(id, _)| (commits[id].1, *id)).collect();
+    let mut sorted = Vec::with_capacity(commits.len());
+    while let Some((_, id)) = ready.pop() {
+        let (walked, _, parents) = commits.remove(&id).expect("every ready commit is in the set");
+        for p in parents {
+            if let Some(n) = children.get_mut(&p) {
+                *n -= 1;
+                if *n == 0 { ready.push((commits[&p].1, p)); }
+            }
+        }
+        sorted.push(walked);
+    }
+    sorted
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_backend {
+    use super::*;
+    use git2::Signature;
+
+    fn init_repo(dir: &std::path::Path) -> (Repository, Vec<Oid>) {
+        let repo = Repository::init(dir).unwrap();
+        let mut commits = vec![];
+        {
+            let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+            for (i, message) in ["first", "feat: second\n\nbody\n", "fix: third"].iter().enumerate() {
+                let sig = Signature::new("dev", "dev@example.com", &git2::Time::new(1700000000 + i as i64 * 60, 0)).unwrap();
+                let parents: Vec<_> = commits.last().map(|p| repo.find_commit(*p).unwrap()).into_iter().collect();
+                commits.push(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap());
+            }
+            repo.tag_lightweight("v0.1.0", &repo.find_object(commits[0], None).unwrap(), false).unwrap();
+            let sig = Signature::now("dev", "dev@example.com").unwrap();
+            repo.tag("v0.2.0", &repo.find_object(commits[1], None).unwrap(), &sig, "Version 0.2.0", false).unwrap();
+        }
+        (repo, commits)
+    }
+
+    fn check_backend(backend: &dyn GitBackend, commits: &[Oid]) {
+        let mut tags = backend.tag_names().unwrap();
+        tags.sort();
+        assert_eq!(tags, vec!["v0.1.0", "v0.2.0"]);
+        assert_eq!(backend.count_commits().unwrap(), 3);
+        let walked = backend.commits_between(Some(commits[0]), commits[2]).unwrap();
+        assert_eq!(walked.iter().map(|c| c.id).collect::<Vec<_>>(), vec![commits[2], commits[1]]);
+        assert_eq!((walked[1].message.as_str(), walked[1].parent_count), ("feat: second\n\nbody\n", 1));
+        assert_eq!(backend.commits_between(None, commits[2]).unwrap().len(), 3);
+    }
+
+    /// Merge of two branches whose commits have older timestamps than their common parent, as with
+    /// a skewed clock. Returns the ids of the base, the newer and the older branch commit and the merge.
+    fn init_skewed_merge(repo: &Repository) -> [Oid; 4] {
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let commit = |message: &str, time: i64, parents: &[Oid]| {
+            let sig = Signature::new("dev", "dev@example.com", &git2::Time::new(time, 0)).unwrap();
+            let parents: Vec<_> = parents.iter().map(|p| repo.find_commit(*p).unwrap()).collect();
+            repo.commit(None, &sig, &sig, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+        };
+        let base = commit("base", 1700000050, &[]);
+        let newer = commit("newer", 1700000010, &[base]);
+        let older = commit("older", 1700000005, &[base]);
+        [base, newer, older, commit("merge", 1700000020, &[newer, older])]
+    }
+
+    fn check_skewed_order(backend: &dyn GitBackend, [base, newer, older, merge]: [Oid; 4]) {
+        let walked = backend.commits_between(None, merge).unwrap();
+        assert_eq!(walked.iter().map(|c| c.id).collect::<Vec<_>>(), vec![merge, newer, older, base]);
+        assert_eq!(walked[0].parent_count, 2);
+    }
+
+    #[test]
+    fn test_git2_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, commits) = init_repo(dir.path());
+        check_backend(&Git2Backend(&repo), &commits);
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        check_skewed_order(&Git2Backend(&repo), init_skewed_merge(&repo));
+    }
+
+    #[cfg(feature = "gix")]
+    #[test]
+    fn test_gix_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, commits) = init_repo(dir.path());
+        check_backend(&GixBackend::open(&repo).unwrap(), &commits);
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let merge = init_skewed_merge(&repo);
+        check_skewed_order(&GixBackend::open(&repo).unwrap(), merge);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use git2::Repository;
+use toml_edit::{value, DocumentMut, Item, Table};
+use crate::backend::open_backend;
+use crate::error::{Error, Result};
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// Where the build number of a release comes from.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum BuildNumberSource {
+    /// Counter in `[package.metadata.cgvs]` (`[workspace.metadata.cgvs]` of a virtual root),
+    /// incremented and written with every release
+    Metadata,
+    /// Number of commits of the release commit, like 'git rev-list --count'
+    Commits,
+}
+
+fn metadata_table(toml: &DocumentMut) -> Option<&Item> {
+    let base = match manifest_version(toml) {
+        ManifestVersion::Workspace(_) | ManifestVersion::Virtual => toml.get("workspace")?,
+        _ => toml.get("package")?,
+    };
+    base.get("metadata")?.get("cgvs")
+}
+
+/// Build number stored in the metadata of the cargo.toml.
+pub fn metadata_build(toml: &DocumentMut) -> Result<Option<u64>> {
+    let Some(build) = metadata_table(toml).and_then(|t| t.get("build")) else { return Ok(None); };
+    build.as_integer().and_then(|b| u64::try_from(b).ok()).map(Some)
+        .ok_or_else(|| Error::Manifest(format!("metadata.cgvs.build has to be a non-negative integer, found '{}'", build.to_string().trim())))
+}
+
+/// Writes the build number to the metadata of the cargo.toml. Members inheriting the version are
+/// left untouched, the counter is kept in their workspace root.
+pub fn set_metadata_build(toml: &mut DocumentMut, build: u64) {
+    let base = match manifest_version(toml) {
+        ManifestVersion::Workspace(_) => "workspace",
+        ManifestVersion::Package(_) => "package",
+        _ => return,
+    };
+    let mut metadata = &mut toml[base];
+    for key in ["metadata", "cgvs"] {
+        // missing tables are added implicitly, so only a [package.metadata.cgvs] header is written
+        if let Some(table) = metadata.as_table_mut() && !table.contains_key(key) {
+            let mut new = Table::new();
+            new.set_implicit(true);
+            table.insert(key, Item::Table(new));
+        }
+        metadata = &mut metadata[key];
+    }
+    metadata["build"] = value(build as i64);
+}
+
+/// Build number of the release: the highest counter of the cargo.toml files plus one, or the number
+/// of commits HEAD will have after the release commit (the same as HEAD when it is amended).
+pub fn next_build_number(source: BuildNumberSource, repo: &Repository, cargo_content: &HashMap<PathBuf, (Version, DocumentMut)>,
+                         amend: bool) -> Result<u64> {
+    match source {
+        BuildNumberSource::Metadata => {
+            let mut highest = 0;
+            for (fname, (_, toml)) in cargo_content {
+                let build = metadata_build(toml).map_err(|e| Error::Manifest(format!("{}: {}", fname.display(), e)))?;
+                highest = highest.max(build.unwrap_or(0));
+            }
+            Ok(highest + 1)
+        },
+        BuildNumberSource::Commits => {
+            let count = open_backend(repo)?.count_commits()?;
+            Ok(if amend { count } else { count + 1 })
+        },
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_build_number {
+    use super::*;
+
+    #[test]
+    fn test_metadata_build() {
+        let mut toml = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n\n[dependencies]\n".parse::<DocumentMut>().unwrap();
+        assert_eq!(metadata_build(&toml).unwrap(), None);
+        set_metadata_build(&mut toml, 7);
+        assert_eq!(metadata_build(&toml).unwrap(), Some(7));
+        assert!(toml.to_string().contains("version = \"0.1.0\"\n\n[package.metadata.cgvs]\nbuild = 7\n\n[dependencies]"));
+
+        let mut root = "[workspace]\nmembers = [\"a\"]\n\n[workspace.package]\nversion = \"0.1.0\"\n\n[workspace.metadata.cgvs]\nbuild = 41\n"
+            .parse::<DocumentMut>().unwrap();
+        assert_eq!(metadata_build(&root).unwrap(), Some(41));
+        set_metadata_build(&mut root, 42);
+        assert!(root.to_string().ends_with("[workspace.metadata.cgvs]\nbuild = 42\n"));
+
+        let invalid = "[package]\nversion = \"0.1.0\"\nmetadata = { cgvs = { build = \"x\" } }\n".parse::<DocumentMut>().unwrap();
+        assert_eq!(metadata_build(&invalid).unwrap_err().exit_code(), 4);
+        let mut inline = invalid;
+        set_metadata_build(&mut inline, 3);
+        assert_eq!(metadata_build(&inline).unwrap(), Some(3));
+    }
+}
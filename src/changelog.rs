@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::PathBuf;
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::write_file_atomic;
+
+static HEADER: &str = "# Changelog\n";
+
+// ********************************************************
+// ********************************************************
+/// Inserts the section of a new version into the changelog content. The section is placed before
+/// the first existing version section (a line starting with `## `), so a header text is kept on top.
+/// A new changelog starts with a `# Changelog` header.
+pub fn insert_section(existing: Option<&str>, section: &str) -> String {
+    let section = format!("{}\n", section.trim_end());
+    let Some(existing) = existing.filter(|e| !e.trim().is_empty()) else {
+        return format!("{HEADER}\n{section}");
+    };
+
+    let mut offset = 0;
+    for line in existing.split_inclusive('\n') {
+        if line.starts_with("## ") {
+            return format!("{}{}\n{}", &existing[..offset], section, &existing[offset..]);
+        }
+        offset += line.len();
+    }
+    let separator = if existing.ends_with("\n\n") { "" } else if existing.ends_with('\n') { "\n" } else { "\n\n" };
+    format!("{existing}{separator}{section}")
+}
+
+/// Adds the section to the changelog file, creating it if it does not exist. The file is backed up
+/// in the transaction before it is written.
+pub fn update_changelog(path: &PathBuf, section: &str, transaction: &mut Transaction) -> Result<()> {
+    let existing = if path.exists() {
+        Some(fs::read_to_string(path).map_err(|e| Error::io(format!("Could not read changelog '{}'", path.display()), e))?)
+    } else { None };
+    transaction.backup_file(path)?;
+    write_file_atomic(path, insert_section(existing.as_deref(), section).as_bytes())
+        .map_err(|e| Error::io(format!("Failed to write to '{}'", path.display()), e))?;
+    tracing::info!("Updated changelog: {}", path.display());
+    Ok(())
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_changelog {
+    use super::*;
+
+    #[test]
+    fn test_insert_section() {
+        let section = "## 1.1.0\n\n### Features\n- x (1234567)\n";
+        assert_eq!(insert_section(None, section), format!("# Changelog\n\n{section}"));
+
+        let existing = "# Changelog\n\nAll notable changes.\n\n## 1.0.0\n\n- initial\n";
+        assert_eq!(insert_section(Some(existing), section),
+                   format!("# Changelog\n\nAll notable changes.\n\n{section}\n## 1.0.0\n\n- initial\n"));
+        assert_eq!(insert_section(Some("# Changelog\n"), section), format!("# Changelog\n\n{section}"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+use git2::{Oid, Repository};
+use crate::error::{Error, Result};
+use crate::utils::Version;
+
+// ********************************************************
+// ********************************************************
+/// Compares the versions of the cargo.toml files with each other and with the latest version tag.
+/// Returns a description of each inconsistency found.
+pub fn check_consistency(versions: &[(PathBuf, Version)], latest_tag: Option<&(String, Version)>) -> Vec<String> {
+    let mut problems = vec![];
+    let Some((_, first_version)) = versions.first() else {
+        return vec!["No cargo.toml with version found".to_string()];
+    };
+
+    if versions.iter().any(|(_, v)| v != first_version) {
+        problems.push(format!("Versions of cargo.toml files disagree: {}", versions.iter()
+            .map(|(f, v)| format!("{} ({})", f.display(), v)).collect::<Vec<_>>().join(", ")));
+    }
+
+    match latest_tag {
+        None => problems.push("No version tag found".to_string()),
+        Some((tag_name, tag_version)) => {
+            // build metadata like +g1a2b3c4 of --with-sha is only part of the cargo.toml
+            versions.iter().filter(|(_, v)| v.without_build() != tag_version.without_build()).for_each(|(f, v)| {
+                problems.push(format!("Version {} of '{}' does not match latest tag '{}'", v, f.display(), tag_name));
+            });
+        }
+    }
+    problems
+}
+
+/// Verifies that the tag exists and points at the HEAD commit, i.e. that HEAD is the release of the
+/// version in the cargo.toml files.
+pub fn check_tag_at_head(repo: &Repository, tag: &str) -> Result<()> {
+    let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let tagged = repo.revparse_single(&format!("refs/tags/{}", tag)).and_then(|o| o.peel_to_commit())
+        .map_err(|_| Error::TagMismatch(format!("Tag '{}' of the version in the cargo.toml files does not exist", tag)))?;
+    if tagged.id() != head.id() {
+        return Err(Error::TagMismatch(format!("Tag '{}' points at {} and not at HEAD ({})", tag, tagged.id(), head.id())));
+    }
+    Ok(())
+}
+
+/// Verifies that the commit only changes the expected files (paths relative to the repository root)
+/// and that no changed blob is larger than `max_blob_size` bytes. Returns an error listing all
+/// offending files otherwise. The changes are relative to `base` (the amended commit with
+/// `--amend`, the commit before the first crate commit with `--commit-per-crate`) or else to the
+/// parent.
+pub fn verify_release_commit(repo: &Repository, oid: Oid, base: Option<Oid>, expected: &[PathBuf], max_blob_size: u64) -> Result<()> {
+    let commit = repo.find_commit(oid).map_err(|e| Error::git(format!("Could not find commit {}", oid), e))?;
+    let tree = commit.tree().map_err(|e| Error::git(format!("Could not read tree of {}", oid), e))?;
+    let parent_tree = match base {
+        Some(base) => Some(repo.find_commit(base).and_then(|c| c.tree()).map_err(|e| Error::git(format!("Could not read tree of {}", base), e))?),
+        None => commit.parent(0).and_then(|p| p.tree()).ok(),
+    };
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+        .map_err(|e| Error::git(format!("Could not diff commit {}", oid), e))?;
+
+    let mut offending = vec![];
+    for delta in diff.deltas() {
+        let path = delta.new_file().path().or(delta.old_file().path()).unwrap_or(Path::new(""));
+        if !expected.iter().any(|e| e == path) {
+            offending.push(format!("{} (unexpected file)", path.display()));
+            continue;
+        }
+        let size = if delta.new_file().id().is_zero() { 0 }
+            else { repo.find_blob(delta.new_file().id()).map(|b| b.size() as u64).unwrap_or(0) };
+        if size > max_blob_size {
+            offending.push(format!("{} ({} bytes, limit is {})", path.display(), size, max_blob_size));
+        }
+    }
+    if offending.is_empty() { Ok(()) }
+    else { Err(Error::Check(format!("Release commit {} contains unexpected changes:\n - {}", oid, offending.join("\n - ")))) }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_check {
+    use super::*;
+
+    fn v(s: &str) -> Version { Version::try_from(s.to_string()).unwrap() }
+
+    #[test]
+    fn test_check_consistency() {
+        let tag = ("v1.2.0".to_string(), v("1.2.0"));
+        let same = vec![(PathBuf::from("a"), v("1.2.0")), (PathBuf::from("b"), v("1.2.0"))];
+        assert!(check_consistency(&same, Some(&tag)).is_empty());
+        assert_eq!(check_consistency(&same, None).len(), 1);
+
+        let differ = vec![(PathBuf::from("a"), v("1.2.0")), (PathBuf::from("b"), v("1.3.0"))];
+        assert_eq!(check_consistency(&differ, Some(&tag)).len(), 2);
+        assert_eq!(check_consistency(&[], Some(&tag)).len(), 1);
+    }
+
+    #[test]
+    fn test_check_tag_at_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let first = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        repo.tag("v1.0.0", &repo.find_object(first, None).unwrap(), &sig, "Release 1.0.0", false).unwrap();
+        assert!(check_tag_at_head(&repo, "v1.0.0").is_ok());
+        assert_eq!(check_tag_at_head(&repo, "v1.1.0").unwrap_err().exit_code(), 15);
+
+        repo.commit(Some("HEAD"), &sig, &sig, "second", &tree, &[&repo.find_commit(first).unwrap()]).unwrap();
+        assert_eq!(check_tag_at_head(&repo, "v1.0.0").unwrap_err().exit_code(), 15);
+    }
+
+    #[test]
+    fn test_verify_release_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "version = \"0.2.0\"\n").unwrap();
+        std::fs::write(dir.path().join("artifact.bin"), vec![0u8; 64]).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Cargo.toml")).unwrap();
+        index.add_path(Path::new("artifact.bin")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &sig, &sig, "release", &tree, &[]).unwrap();
+
+        let both = [PathBuf::from("Cargo.toml"), PathBuf::from("artifact.bin")];
+        assert!(verify_release_commit(&repo, oid, None, &both, 1024).is_ok());
+        assert!(verify_release_commit(&repo, oid, None, &both, 32).unwrap_err().to_string().contains("artifact.bin (64 bytes"));
+        assert!(verify_release_commit(&repo, oid, None, &both[..1], 1024).unwrap_err().to_string().contains("artifact.bin (unexpected file)"));
+
+        // an amended commit is compared with the commit it replaces
+        std::fs::write(dir.path().join("Cargo.toml"), "version = \"0.3.0\"\n").unwrap();
+        index.add_path(Path::new("Cargo.toml")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let amended = repo.commit(None, &sig, &sig, "release", &tree, &[]).unwrap();
+        assert!(verify_release_commit(&repo, amended, Some(oid), &both[..1], 1024).is_ok());
+    }
+}
@@ -0,0 +1,264 @@
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item};
+use crate::error::{Error, Result};
+use crate::date::{parse_timezone, TimeZone};
+use crate::notify::NotifyKind;
+use crate::sinks::SinkKind;
+use crate::utils::glob_match;
+
+// ********************************************************
+// ********************************************************
+/// Commands run at the respective step of a release, see `hooks::run_hook`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Hooks {
+    /// Before the versions are written
+    pub pre_bump: Option<String>,
+    /// After the versions are written, before the release commit
+    pub pre_commit: Option<String>,
+    /// After the tag was created, before the push
+    pub pre_push: Option<String>,
+    /// After the release was pushed
+    pub post_release: Option<String>,
+}
+
+/// Patterns selecting which files changed by hooks may be staged into the release commit. The
+/// manifests and files written by the tool itself are always committed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommitFiles {
+    /// If not empty, only files matching one of the patterns are committed
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+impl CommitFiles {
+    /// Checks a path relative to the git base path against the patterns, see `glob_match`.
+    pub fn is_eligible(&self, rel_path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, rel_path)))
+            && !self.exclude.iter().any(|p| glob_match(p, rel_path))
+    }
+}
+
+/// Check of the commit messages since the last version tag before a release, see
+/// `notes::lint_commits`.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommitLint {
+    /// Lint every release, not only with `--lint-commits`
+    pub enabled: bool,
+    /// Regex the summary line has to match instead of the conventional commit format, validated
+    /// when the config is parsed
+    pub pattern: Option<String>,
+}
+
+/// Regex replacement in a file done together with the version change, e.g. for versions embedded
+/// in docs or headers, see `replacements::apply_replacements`.
+#[derive(Debug, PartialEq)]
+pub struct Replacement {
+    /// Path relative to the git base path
+    pub file: String,
+    /// Regex, validated when the config is parsed
+    pub search: String,
+    /// Template with `{version}`, `{prev_version}` and `{tag}`, regex groups can be referenced as `$1`
+    pub replace: String,
+}
+
+/// Manifest of another ecosystem the version is written to, see `sinks::write_sinks`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sink {
+    pub kind: SinkKind,
+    /// Path relative to the git base path
+    pub file: String,
+}
+
+/// Message posted after the release was pushed, see `notify::render_payload`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub kind: NotifyKind,
+    pub url: String,
+    /// Template of the payload, the default of the kind if None
+    pub template: Option<String>,
+}
+
+/// Project configuration read from `.cgvs.toml` in the git base path. All settings are optional.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub hooks: Hooks,
+    pub commit: CommitFiles,
+    pub lint: CommitLint,
+    /// Time zone of the release date, see `date::parse_timezone`
+    pub timezone: Option<TimeZone>,
+    /// Branches releases may be cut from, glob patterns are supported
+    pub allowed_branches: Vec<String>,
+    pub replacements: Vec<Replacement>,
+    pub sinks: Vec<Sink>,
+    pub notify: Vec<Notification>,
+}
+
+fn get_string(item: Option<&Item>, key: &str) -> Result<Option<String>> {
+    match item {
+        None => Ok(None),
+        Some(i) => i.as_str().map(|s| Some(s.to_string()))
+            .ok_or_else(|| Error::Usage(format!("Config key '{}' has to be a string", key))),
+    }
+}
+
+fn get_string_list(item: Option<&Item>, key: &str) -> Result<Vec<String>> {
+    let Some(item) = item else { return Ok(vec![]); };
+    let error = || Error::Usage(format!("Config key '{}' has to be a list of strings", key));
+    item.as_array().ok_or_else(error)?.iter().map(|v| v.as_str().map(String::from).ok_or_else(error)).collect()
+}
+
+impl Config {
+    /// Parses the content of a config file.
+    pub fn parse(content: &str) -> Result<Config> {
+        let doc = content.parse::<DocumentMut>().map_err(|e| Error::Usage(format!("Could not parse config: {}", e)))?;
+        let mut config = Config::default();
+        if let Some(hooks) = doc.get("hooks") {
+            let hooks = hooks.as_table_like().ok_or_else(|| Error::Usage("Config key 'hooks' has to be a table".to_string()))?;
+            config.hooks = Hooks {
+                pre_bump: get_string(hooks.get("pre_bump"), "hooks.pre_bump")?,
+                pre_commit: get_string(hooks.get("pre_commit"), "hooks.pre_commit")?,
+                pre_push: get_string(hooks.get("pre_push"), "hooks.pre_push")?,
+                post_release: get_string(hooks.get("post_release"), "hooks.post_release")?,
+            };
+        }
+        if let Some(commit) = doc.get("commit") {
+            let commit = commit.as_table_like().ok_or_else(|| Error::Usage("Config key 'commit' has to be a table".to_string()))?;
+            config.commit = CommitFiles {
+                include: get_string_list(commit.get("include"), "commit.include")?,
+                exclude: get_string_list(commit.get("exclude"), "commit.exclude")?,
+            };
+        }
+        if let Some(lint) = doc.get("lint") {
+            let lint = lint.as_table_like().ok_or_else(|| Error::Usage("Config key 'lint' has to be a table".to_string()))?;
+            let enabled = match lint.get("enabled") {
+                None => false,
+                Some(i) => i.as_bool().ok_or_else(|| Error::Usage("Config key 'lint.enabled' has to be a boolean".to_string()))?,
+            };
+            let pattern = get_string(lint.get("pattern"), "lint.pattern")?;
+            if let Some(pattern) = &pattern {
+                regex::Regex::new(pattern).map_err(|e| Error::Usage(format!("Config key 'lint.pattern' is no valid regex: {}", e)))?;
+            }
+            config.lint = CommitLint { enabled, pattern };
+        }
+        if let Some(replacements) = doc.get("replacements") {
+            let replacements = replacements.as_array_of_tables()
+                .ok_or_else(|| Error::Usage("Config key 'replacements' has to be an array of tables ([[replacements]])".to_string()))?;
+            for (i, r) in replacements.iter().enumerate() {
+                let get = |key: &str| get_string(r.get(key), &format!("replacements[{}].{}", i, key))?
+                    .ok_or_else(|| Error::Usage(format!("Config key 'replacements[{}].{}' is missing", i, key)));
+                let replacement = Replacement { file: get("file")?, search: get("search")?, replace: get("replace")? };
+                regex::Regex::new(&replacement.search)
+                    .map_err(|e| Error::Usage(format!("Config key 'replacements[{}].search' is no valid regex: {}", i, e)))?;
+                config.replacements.push(replacement);
+            }
+        }
+        if let Some(sinks) = doc.get("sinks") {
+            let sinks = sinks.as_array_of_tables()
+                .ok_or_else(|| Error::Usage("Config key 'sinks' has to be an array of tables ([[sinks]])".to_string()))?;
+            for (i, s) in sinks.iter().enumerate() {
+                let file = get_string(s.get("file"), &format!("sinks[{}].file", i))?
+                    .ok_or_else(|| Error::Usage(format!("Config key 'sinks[{}].file' is missing", i)))?;
+                let kind = match get_string(s.get("kind"), &format!("sinks[{}].kind", i))? {
+                    Some(kind) => SinkKind::parse(&kind).ok_or_else(|| Error::Usage(format!(
+                        "Config key 'sinks[{}].kind' has to be one of npm, python, helm or plain", i)))?,
+                    None => SinkKind::from_file_name(&file).ok_or_else(|| Error::Usage(format!(
+                        "Config key 'sinks[{}].kind' is missing and cannot be derived from '{}'", i, file)))?,
+                };
+                config.sinks.push(Sink { kind, file });
+            }
+        }
+        if let Some(notify) = doc.get("notify") {
+            let notify = notify.as_array_of_tables()
+                .ok_or_else(|| Error::Usage("Config key 'notify' has to be an array of tables ([[notify]])".to_string()))?;
+            for (i, n) in notify.iter().enumerate() {
+                let url = get_string(n.get("url"), &format!("notify[{}].url", i))?
+                    .ok_or_else(|| Error::Usage(format!("Config key 'notify[{}].url' is missing", i)))?;
+                let kind = match get_string(n.get("kind"), &format!("notify[{}].kind", i))? {
+                    Some(kind) => NotifyKind::parse(&kind).ok_or_else(|| Error::Usage(format!(
+                        "Config key 'notify[{}].kind' has to be one of webhook or slack", i)))?,
+                    None => NotifyKind::Webhook,
+                };
+                let template = get_string(n.get("template"), &format!("notify[{}].template", i))?;
+                config.notify.push(Notification { kind, url, template });
+            }
+        }
+        config.allowed_branches = get_string_list(doc.get("allowed_branches"), "allowed_branches")?;
+        if let Some(tz) = get_string(doc.get("timezone"), "timezone")? {
+            config.timezone = Some(parse_timezone(&tz).map_err(Error::Usage)?);
+        }
+        Ok(config)
+    }
+
+    /// Reads the config file. A missing file results in the default config.
+    pub fn load(path: &Path) -> Result<Config> {
+        if !path.exists() { return Ok(Config::default()); }
+        let content = fs::read_to_string(path).map_err(|e| Error::io(format!("Could not read config '{}'", path.display()), e))?;
+        tracing::info!("Using config {}", path.display());
+        Config::parse(&content).map_err(|e| Error::Usage(format!("{} in '{}'", e, path.display())))
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_config {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("[hooks]\npre_commit = \"make docs\"\npost_release = \"./notify.sh\"\n").unwrap();
+        assert_eq!(config.hooks, Hooks { pre_commit: Some("make docs".to_string()), post_release: Some("./notify.sh".to_string()),
+            ..Default::default() });
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::parse("timezone = \"+02:00\"\n").unwrap().timezone, Some(TimeZone::Fixed(120)));
+        assert_eq!(Config::parse("allowed_branches = [\"main\", \"release/*\"]\n").unwrap().allowed_branches, vec!["main", "release/*"]);
+        assert_eq!(Config::parse("[hooks]\npre_push = 1\n").unwrap_err().to_string(), "Config key 'hooks.pre_push' has to be a string");
+    }
+    #[test]
+    fn test_commit_files() {
+        let config = Config::parse("[commit]\ninclude = [\"VERSION\", \"docs/**\"]\nexclude = [\"*.log\"]\n").unwrap();
+        assert!(config.commit.is_eligible("VERSION"));
+        assert!(config.commit.is_eligible("docs/api/index.html"));
+        assert!(!config.commit.is_eligible("docs/build.log"));
+        assert!(!config.commit.is_eligible("target/debug/x"));
+        assert!(CommitFiles::default().is_eligible("anything"));
+        assert!(Config::parse("[commit]\ninclude = \"VERSION\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_lint() {
+        let config = Config::parse("[lint]\nenabled = true\npattern = '^[A-Z]+-\\d+ '\n").unwrap();
+        assert_eq!(config.lint, CommitLint { enabled: true, pattern: Some("^[A-Z]+-\\d+ ".to_string()) });
+        assert!(Config::parse("[lint]\nenabled = \"yes\"\n").is_err());
+        assert!(Config::parse("[lint]\npattern = \"(\"\n").unwrap_err().to_string().contains("no valid regex"));
+    }
+
+    #[test]
+    fn test_parse_replacements() {
+        let config = Config::parse("[[replacements]]\nfile = \"README.md\"\nsearch = 'cgvs = \"[^\"]+\"'\nreplace = 'cgvs = \"{version}\"'\n").unwrap();
+        assert_eq!(config.replacements, vec![Replacement { file: "README.md".to_string(), search: "cgvs = \"[^\"]+\"".to_string(),
+            replace: "cgvs = \"{version}\"".to_string() }]);
+        assert_eq!(Config::parse("[[replacements]]\nfile = \"a\"\nsearch = \"x\"\n").unwrap_err().to_string(),
+                   "Config key 'replacements[0].replace' is missing");
+        assert!(Config::parse("[[replacements]]\nfile = \"a\"\nsearch = \"(\"\nreplace = \"\"\n").unwrap_err().to_string()
+            .starts_with("Config key 'replacements[0].search' is no valid regex"));
+        assert!(Config::parse("replacements = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_sinks() {
+        let config = Config::parse("[[sinks]]\nfile = \"js/package.json\"\n[[sinks]]\nfile = \"chart.yml\"\nkind = \"helm\"\n").unwrap();
+        assert_eq!(config.sinks, vec![Sink { kind: SinkKind::PackageJson, file: "js/package.json".to_string() },
+                                      Sink { kind: SinkKind::HelmChart, file: "chart.yml".to_string() }]);
+        assert!(Config::parse("[[sinks]]\nfile = \"version.txt\"\n").unwrap_err().to_string().contains("cannot be derived"));
+        assert!(Config::parse("[[sinks]]\nfile = \"a.json\"\nkind = \"maven\"\n").is_err());
+    }
+    #[test]
+    fn test_parse_notify() {
+        let config = Config::parse("[[notify]]\nurl = \"https://hooks/a\"\n[[notify]]\nurl = \"https://hooks/b\"\nkind = \"slack\"\ntemplate = \"{{ tag }}\"\n").unwrap();
+        assert_eq!(config.notify, vec![Notification { kind: NotifyKind::Webhook, url: "https://hooks/a".to_string(), template: None },
+                                       Notification { kind: NotifyKind::Slack, url: "https://hooks/b".to_string(), template: Some("{{ tag }}".to_string()) }]);
+        assert!(Config::parse("[[notify]]\nkind = \"slack\"\n").unwrap_err().to_string().contains("'notify[0].url' is missing"));
+        assert!(Config::parse("[[notify]]\nurl = \"https://hooks/a\"\nkind = \"teams\"\n").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use regex::Regex;
+use crate::template::TemplateContext;
+
+// ********************************************************
+// ********************************************************
+/// Time zone for the release date. Only fixed offsets are supported as there is no time zone
+/// database; `local` uses the current offset of the system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeZone {
+    Local,
+    Utc,
+    /// Offset east of UTC in minutes
+    Fixed(i32),
+}
+
+/// Parses `local`, `utc` (or `Z`) or an offset like `+02:00`, `-0530` or `+1`.
+pub fn parse_timezone(s: &str) -> Result<TimeZone, String> {
+    match s.to_lowercase().as_str() {
+        "local" => return Ok(TimeZone::Local),
+        "utc" | "z" => return Ok(TimeZone::Utc),
+        _ => (),
+    }
+    let error = || format!("Invalid time zone '{}', expected local, utc or an offset like +02:00", s);
+    let (sign, rest) = match s.chars().next() {
+        Some('+') => (1, &s[1..]),
+        Some('-') => (-1, &s[1..]),
+        _ => return Err(error()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let (hours, minutes): (i32, i32) = (hours.parse().map_err(|_| error())?, minutes.parse().map_err(|_| error())?);
+    if hours > 14 || minutes > 59 { return Err(error()); }
+    Ok(TimeZone::Fixed(sign * (hours * 60 + minutes)))
+}
+
+/// Point in time of the release with the offset of the configured time zone.
+#[derive(Clone, Copy, Debug)]
+pub struct ReleaseTime {
+    pub seconds: i64,
+    pub offset_minutes: i32,
+}
+impl ReleaseTime {
+    pub fn now(tz: TimeZone) -> Self {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        ReleaseTime { seconds, offset_minutes: 0 }.with_timezone(tz)
+    }
+
+    /// The same point in time in another time zone.
+    pub fn with_timezone(self, tz: TimeZone) -> Self {
+        let offset_minutes = match tz {
+            TimeZone::Utc => 0,
+            TimeZone::Fixed(m) => m,
+            // libgit2 determines the local offset the same way as for commit signatures
+            TimeZone::Local => git2::Signature::now("cgvs", "cgvs").map(|s| s.when().offset_minutes()).unwrap_or(0),
+        };
+        ReleaseTime { offset_minutes, ..self }
+    }
+
+    /// Parses a `SOURCE_DATE_EPOCH` (seconds since 1970-01-01 UTC).
+    pub fn from_epoch(s: &str) -> Result<Self, String> {
+        let seconds = s.trim().parse().map_err(|_| format!("Invalid SOURCE_DATE_EPOCH '{}', expected seconds since 1970", s))?;
+        Ok(ReleaseTime { seconds, offset_minutes: 0 })
+    }
+
+    /// Parses an RFC 3339 date like `2024-03-01T00:15:00+01:00` or `2024-02-29T23:15:00Z`.
+    pub fn parse_rfc3339(s: &str) -> Result<Self, String> {
+        let re = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?([Zz]|[+-]\d{2}:\d{2})$").unwrap();
+        let error = || format!("Invalid date '{}', expected RFC 3339 like 2024-03-01T00:15:00+01:00", s);
+        let c = re.captures(s).ok_or_else(error)?;
+        let n = |i: usize| c[i].parse::<i64>().unwrap();
+        let (year, month, day, hour, minute, second) = (n(1), n(2), n(3), n(4), n(5), n(6));
+        let offset_minutes = match parse_timezone(&c[7]).map_err(|_| error())? { TimeZone::Fixed(m) => m, _ => 0 };
+        // days from civil date, see https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+        let time = ReleaseTime { seconds: days * 86400 + hour * 3600 + minute * 60 + second - offset_minutes as i64 * 60, offset_minutes };
+        // rejects e.g. February 30th or 25:00, which would be normalized otherwise
+        if time.civil() != (year, month as u32, day as u32, hour as u32, minute as u32, second as u32) { return Err(error()); }
+        Ok(time)
+    }
+
+    /// Year, month, day, hour, minute and second in the time zone.
+    fn civil(&self) -> (i64, u32, u32, u32, u32, u32) {
+        let local = self.seconds + self.offset_minutes as i64 * 60;
+        let (days, secs) = (local.div_euclid(86400), local.rem_euclid(86400) as u32);
+        // days to civil date, see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+
+    /// Date as `YYYY-MM-DD`
+    pub fn date(&self) -> String {
+        let (y, m, d, ..) = self.civil();
+        format!("{:04}-{:02}-{:02}", y, m, d)
+    }
+
+    /// Date and time in RFC 3339 format, e.g. `2024-03-01T00:15:00+01:00`
+    pub fn datetime(&self) -> String {
+        let (y, mo, d, h, mi, s) = self.civil();
+        let offset = if self.offset_minutes == 0 { "Z".to_string() } else {
+            format!("{}{:02}:{:02}", if self.offset_minutes < 0 { '-' } else { '+' }, self.offset_minutes.abs() / 60, self.offset_minutes.abs() % 60)
+        };
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}", y, mo, d, h, mi, s, offset)
+    }
+
+    /// Adds `date`, `datetime`, `time`, `year`, `month` and `day` to the template context.
+    pub fn add_to(&self, ctx: &mut TemplateContext) {
+        let (y, m, d, h, mi, s) = self.civil();
+        ctx.insert("date", self.date()).insert("datetime", self.datetime())
+            .insert("time", format!("{:02}:{:02}:{:02}", h, mi, s))
+            .insert("year", y).insert("month", format!("{:02}", m)).insert("day", format!("{:02}", d));
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_date {
+    use super::*;
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(parse_timezone("UTC"), Ok(TimeZone::Utc));
+        assert_eq!(parse_timezone("+02:00"), Ok(TimeZone::Fixed(120)));
+        assert_eq!(parse_timezone("-0530"), Ok(TimeZone::Fixed(-330)));
+        assert_eq!(parse_timezone("+1"), Ok(TimeZone::Fixed(60)));
+        assert!(parse_timezone("Europe/Berlin").is_err());
+    }
+    #[test]
+    fn test_date_in_timezone() {
+        // 2023-12-31T23:30:00Z is already the next year in Berlin but not in New York
+        let t = ReleaseTime { seconds: 1704065400, offset_minutes: 0 };
+        assert_eq!(t.datetime(), "2023-12-31T23:30:00Z");
+        assert_eq!(ReleaseTime { offset_minutes: 60, ..t }.datetime(), "2024-01-01T00:30:00+01:00");
+        assert_eq!(ReleaseTime { offset_minutes: -300, ..t }.date(), "2023-12-31");
+        assert_eq!(ReleaseTime { seconds: 951782400, offset_minutes: 0 }.date(), "2000-02-29");
+    }
+    #[test]
+    fn test_parse_rfc3339() {
+        let t = ReleaseTime::parse_rfc3339("2024-01-01T00:30:00+01:00").unwrap();
+        assert_eq!((t.seconds, t.offset_minutes), (1704065400, 60));
+        assert_eq!(ReleaseTime::parse_rfc3339("2023-12-31T23:30:00.123Z").unwrap().seconds, 1704065400);
+        assert_eq!(ReleaseTime::parse_rfc3339("1969-12-31 23:00:00-01:00").unwrap().seconds, 0);
+        assert!(ReleaseTime::parse_rfc3339("2023-02-29T00:00:00Z").is_err());
+        assert!(ReleaseTime::parse_rfc3339("2023-12-31").is_err());
+        assert_eq!(ReleaseTime::from_epoch("1704065400").unwrap().datetime(), "2023-12-31T23:30:00Z");
+        assert!(ReleaseTime::from_epoch("yesterday").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use git2::{Oid, Patch, Repository};
+use crate::error::{Error, Result};
+use crate::json::JsonValue;
+use crate::utils::TagFormat;
+
+// ********************************************************
+// ********************************************************
+/// Lines added and removed in a file between two releases.
+#[derive(Debug, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+impl FileStat {
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object().with("path", self.path.as_str())
+            .with("insertions", self.insertions as i64).with("deletions", self.deletions as i64)
+    }
+}
+
+/// Resolves a version (`1.2.0`) or a tag name (`v1.2.0`) to the version tag and its commit.
+pub fn resolve_version_tag(repo: &Repository, tag_format: &TagFormat, spec: &str) -> Result<(String, Oid)> {
+    let tag = if tag_format.version_part(spec).is_some() { spec.to_string() } else { tag_format.tag(spec) };
+    let commit = repo.revparse_single(&format!("refs/tags/{}", tag)).and_then(|o| o.peel_to_commit())
+        .map_err(|e| Error::git(format!("Could not resolve version tag '{}'", tag), e))?;
+    Ok((tag, commit.id()))
+}
+
+/// Returns the changed files between the two commits with their added and removed lines, like
+/// `git diff --stat`.
+pub fn file_stats(repo: &Repository, from: Oid, to: Oid) -> Result<Vec<FileStat>> {
+    let tree = |oid: Oid| repo.find_commit(oid).and_then(|c| c.tree()).map_err(|e| Error::git(format!("Could not read tree of {}", oid), e));
+    let diff = repo.diff_tree_to_tree(Some(&tree(from)?), Some(&tree(to)?), None)
+        .map_err(|e| Error::git(format!("Could not diff {} and {}", from, to), e))?;
+
+    let mut stats = vec![];
+    for (idx, delta) in diff.deltas().enumerate() {
+        let path = delta.new_file().path().or(delta.old_file().path()).map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        let (_, insertions, deletions) = match Patch::from_diff(&diff, idx).map_err(|e| Error::git("Could not diff file", e))? {
+            Some(patch) => patch.line_stats().map_err(|e| Error::git("Could not diff file", e))?,
+            // binary files have no lines
+            None => (0, 0, 0),
+        };
+        stats.push(FileStat { path, insertions, deletions });
+    }
+    Ok(stats)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_diff {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_resolve_and_file_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let commit = |content: &str, parents: &[Oid]| {
+            std::fs::write(dir.path().join("a.txt"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("a.txt")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parents: Vec<_> = parents.iter().map(|p| repo.find_commit(*p).unwrap()).collect();
+            repo.commit(Some("HEAD"), &sig, &sig, "c", &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+        };
+        let first = commit("a\nb\n", &[]);
+        let second = commit("a\nc\nd\n", &[first]);
+        repo.tag_lightweight("v1.0.0", &repo.find_object(first, None).unwrap(), false).unwrap();
+
+        let format = TagFormat::from_prefix("v");
+        assert_eq!(resolve_version_tag(&repo, &format, "1.0.0").unwrap(), ("v1.0.0".to_string(), first));
+        assert_eq!(resolve_version_tag(&repo, &format, "v1.0.0").unwrap(), ("v1.0.0".to_string(), first));
+        assert!(resolve_version_tag(&repo, &format, "1.1.0").is_err());
+        assert_eq!(file_stats(&repo, first, second).unwrap(), vec![FileStat { path: "a.txt".to_string(), insertions: 2, deletions: 1 }]);
+    }
+}
@@ -0,0 +1,122 @@
+use thiserror::Error;
+
+// ********************************************************
+// ********************************************************
+/// All errors of the tool. Each kind of error results in a distinct exit code, see `exit_code`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid combination of arguments, options or templates
+    #[error("{0}")]
+    Usage(String),
+    /// Project path, cargo.toml or git repository not found
+    #[error("{0}")]
+    Discovery(String),
+    /// Cargo.toml or Cargo.lock has an unexpected content
+    #[error("{0}")]
+    Manifest(String),
+    /// Reading or writing a file failed
+    #[error("{msg}: {source}")]
+    Io { msg: String, #[source] source: std::io::Error },
+    /// A git operation failed
+    #[error("{msg}: {source}")]
+    Git { msg: String, #[source] source: git2::Error },
+    /// The repository is in a state which does not allow the operation
+    #[error("{0}")]
+    Repository(String),
+    /// The working tree has uncommitted changes
+    #[error("There are {0} uncommitted changes - please commit before continuing.")]
+    DirtyTree(usize),
+    /// The version or tag conflicts with an existing one
+    #[error("{0}")]
+    Conflict(String),
+    /// Communication with the git remote failed
+    #[error("{msg}: {source}")]
+    Remote { msg: String, #[source] source: git2::Error },
+    /// A check found inconsistencies
+    #[error("{0}")]
+    Check(String),
+    /// A request to the API of the git hosting service failed
+    #[error("{0}")]
+    Api(String),
+    /// A configured hook command failed
+    #[error("{0}")]
+    Hook(String),
+    /// The branch is not allowed for releases
+    #[error("{0}")]
+    BranchPolicy(String),
+    /// The version of the cargo.toml files is not tagged at HEAD (`check --strict`)
+    #[error("{0}")]
+    TagMismatch(String),
+    /// The remote rejected the push, e.g. because of a protected branch or a server-side hook
+    #[error("{0}")]
+    PushRejected(String),
+    /// The user did not confirm the operation
+    #[error("Aborted by user")]
+    Aborted,
+}
+impl Error {
+    pub fn io(msg: impl Into<String>, source: std::io::Error) -> Self { Error::Io { msg: msg.into(), source } }
+    pub fn git(msg: impl Into<String>, source: git2::Error) -> Self { Error::Git { msg: msg.into(), source } }
+    pub fn remote(msg: impl Into<String>, source: git2::Error) -> Self { Error::Remote { msg: msg.into(), source } }
+
+    /// Exit code of the process for this error. Code 2 is also used by clap for invalid arguments.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::Discovery(_) => 3,
+            Error::Manifest(_) => 4,
+            Error::Io { .. } => 5,
+            Error::Git { .. } | Error::Repository(_) => 6,
+            Error::DirtyTree(_) => 7,
+            Error::Conflict(_) => 8,
+            Error::Remote { .. } => 9,
+            Error::Check(_) => 10,
+            Error::Aborted => 11,
+            Error::Api(_) => 12,
+            Error::Hook(_) => 13,
+            Error::BranchPolicy(_) => 14,
+            Error::TagMismatch(_) => 15,
+            Error::PushRejected(_) => 16,
+        }
+    }
+
+    /// Short machine-readable name of the error kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Usage(_) => "usage",
+            Error::Discovery(_) => "discovery",
+            Error::Manifest(_) => "manifest",
+            Error::Io { .. } => "io",
+            Error::Git { .. } => "git",
+            Error::Repository(_) => "repository",
+            Error::DirtyTree(_) => "dirty_tree",
+            Error::Conflict(_) => "conflict",
+            Error::Remote { .. } => "remote",
+            Error::Check(_) => "check",
+            Error::Aborted => "aborted",
+            Error::Api(_) => "api",
+            Error::Hook(_) => "hook",
+            Error::BranchPolicy(_) => "branch_policy",
+            Error::TagMismatch(_) => "tag_mismatch",
+            Error::PushRejected(_) => "push_rejected",
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_error {
+    use super::*;
+
+    #[test]
+    fn test_error_message_and_exit_code() {
+        let e = Error::git("Could not resolve HEAD", git2::Error::from_str("not found"));
+        assert_eq!(e.to_string(), "Could not resolve HEAD: not found");
+        assert_eq!(e.exit_code(), 6);
+        assert_eq!(Error::DirtyTree(2).to_string(), "There are 2 uncommitted changes - please commit before continuing.");
+        assert_eq!(Error::DirtyTree(2).exit_code(), 7);
+    }
+}
@@ -0,0 +1,79 @@
+use git2::{Oid, Repository};
+use crate::date::ReleaseTime;
+use crate::error::{Error, Result};
+use crate::json::JsonValue;
+use crate::utils::{list_version_tags, TagFormat, Version};
+
+// ********************************************************
+// ********************************************************
+/// A version tag as listed by the history command.
+#[derive(Debug)]
+pub struct Release {
+    pub tag: String,
+    pub version: Version,
+    pub date: ReleaseTime,
+    /// Name and email of the tagger, or of the committer for a lightweight tag
+    pub tagger: String,
+    pub commit: Oid,
+    pub message: String,
+}
+impl Release {
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object().with("tag", self.tag.as_str()).with("version", self.version.to_string())
+            .with("date", self.date.datetime()).with("tagger", self.tagger.as_str())
+            .with("commit", self.commit.to_string()).with("message", self.message.as_str())
+    }
+}
+
+fn signature_text(sig: &git2::Signature) -> String {
+    format!("{} <{}>", sig.name().unwrap_or(""), sig.email().unwrap_or(""))
+}
+
+/// Lists the version tags matching the tag format, highest version first and at most `limit`.
+/// Date, tagger and message are the ones of an annotated tag, or of the commit for a lightweight tag.
+pub fn release_history(repo: &Repository, tag_format: &TagFormat, limit: Option<usize>) -> Result<Vec<Release>> {
+    let mut tags: Vec<(String, Version)> = list_version_tags(repo, tag_format)?.into_iter()
+        .filter_map(|tn| Version::try_from(tag_format.version_part(&tn)?.to_string()).ok().map(|v| (tn, v)))
+        .collect();
+    tags.sort_by(|(_, a), (_, b)| b.cmp(a));
+    tags.truncate(limit.unwrap_or(usize::MAX));
+
+    tags.into_iter().map(|(tag, version)| {
+        let obj = repo.revparse_single(&format!("refs/tags/{}", tag))
+            .map_err(|e| Error::git(format!("Could not resolve tag '{}'", tag), e))?;
+        let commit = obj.peel_to_commit().map_err(|e| Error::git(format!("Tag '{}' does not point to a commit", tag), e))?;
+        let (when, tagger, message) = match obj.as_tag().and_then(|t| t.tagger().map(|s| (s, t.message().unwrap_or("")))) {
+            Some((sig, message)) => (sig.when(), signature_text(&sig), message.trim().to_string()),
+            None => (commit.time(), signature_text(&commit.committer()), commit.message().unwrap_or("").trim().to_string()),
+        };
+        let date = ReleaseTime { seconds: when.seconds(), offset_minutes: when.offset_minutes() };
+        Ok(Release { tag, version, date, tagger, commit: commit.id(), message })
+    }).collect()
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_history {
+    use super::*;
+
+    #[test]
+    fn test_release_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::new("dev", "dev@example.com", &git2::Time::new(1700000000, 60)).unwrap();
+        let first = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let first = repo.find_commit(first).unwrap();
+        let second = repo.commit(Some("HEAD"), &sig, &sig, "second", &tree, &[&first]).unwrap();
+        repo.tag_lightweight("v0.9.0", first.as_object(), false).unwrap();
+        repo.tag("v0.10.0", &repo.find_object(second, None).unwrap(), &sig, "Release 0.10.0\n", false).unwrap();
+
+        let history = release_history(&repo, &TagFormat::from_prefix("v"), None).unwrap();
+        assert_eq!(history.iter().map(|r| r.tag.as_str()).collect::<Vec<_>>(), vec!["v0.10.0", "v0.9.0"]);
+        assert_eq!((history[0].commit, history[0].message.as_str()), (second, "Release 0.10.0"));
+        assert_eq!((history[1].tagger.as_str(), history[1].message.as_str()), ("dev <dev@example.com>", "first"));
+        assert_eq!(history[1].date.datetime(), "2023-11-14T23:13:20+01:00");
+        assert_eq!(release_history(&repo, &TagFormat::from_prefix("v"), Some(1)).unwrap().len(), 1);
+    }
+}
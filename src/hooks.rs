@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use git2::{Repository, Status, StatusOptions};
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::{is_progress_enabled, print_warn, suspend_step, Version, INDENT};
+
+// ********************************************************
+// ********************************************************
+/// Versions of the release passed to the hooks as environment variables.
+pub struct HookEnv<'a> {
+    pub old_version: &'a Version,
+    pub new_version: &'a Version,
+    pub tag: &'a str,
+}
+
+/// Runs the hook command with the shell in the given directory. The hook gets `CGVS_HOOK`,
+/// `CGVS_OLD_VERSION`, `CGVS_NEW_VERSION` and `CGVS_TAG` as environment variables. Its output goes
+/// to stderr if stdout is reserved for machine-readable output.
+pub fn run_hook(name: &str, command: &str, dir: &Path, env: &HookEnv) -> Result<()> {
+    progress!("{INDENT}Running {} hook: {}", name, command);
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let stdout = if is_progress_enabled() { Stdio::inherit() } else { Stdio::from(std::io::stderr()) };
+    let status = suspend_step(|| Command::new(shell).arg(flag).arg(command).current_dir(dir).stdout(stdout)
+        .env("CGVS_HOOK", name)
+        .env("CGVS_OLD_VERSION", env.old_version.to_string())
+        .env("CGVS_NEW_VERSION", env.new_version.to_string())
+        .env("CGVS_TAG", env.tag)
+        .status()).map_err(|e| Error::io(format!("Could not run {} hook '{}'", name, command), e))?;
+    if !status.success() {
+        return Err(Error::Hook(format!("The {} hook '{}' failed ({})", name, command, status)));
+    }
+    Ok(())
+}
+
+/// Returns the changed and untracked files of the working tree, relative to the git base path, with
+/// their status.
+fn worktree_changes(repo: &Repository) -> Result<Vec<(PathBuf, Status)>> {
+    let mut so = StatusOptions::new();
+    so.include_untracked(true).recurse_untracked_dirs(true).exclude_submodules(true);
+    let statuses = repo.statuses(Some(&mut so)).map_err(|e| Error::git("Could not get status of working tree", e))?;
+    Ok(statuses.iter().filter_map(|e| e.path().map(|p| (PathBuf::from(p), e.status()))).collect())
+}
+
+/// Returns the untracked and modified files before the release (the latter only with
+/// `--allow-dirty`), relative to the git base path. They are neither committed nor rolled back.
+pub fn changed_files(repo: &Repository) -> Result<Vec<PathBuf>> {
+    Ok(worktree_changes(repo)?.into_iter().map(|(p, _)| p).collect())
+}
+
+/// Returns the new and modified files of the working tree and records their content of HEAD in
+/// the transaction, so changes done by a hook are rolled back as well. Files which were changed
+/// before are skipped, deleted files are not part of the release commit.
+pub fn record_changes(repo: &Repository, git_base_path: &Path, changed_before: &[PathBuf],
+                      transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    let head_tree = repo.head().and_then(|h| h.peel_to_tree()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+
+    let mut changed = vec![];
+    for (rel_path, status) in worktree_changes(repo)? {
+        if changed_before.contains(&rel_path) { continue; }
+        let path = git_base_path.join(&rel_path);
+        let original = match head_tree.get_path(&rel_path) {
+            Ok(e) => Some(e.to_object(repo).and_then(|o| o.peel_to_blob())
+                .map_err(|e| Error::git(format!("Could not read '{}' of HEAD", rel_path.display()), e))?.content().to_vec()),
+            Err(_) => None,
+        };
+        transaction.record_file(&path, original);
+        if status.intersects(Status::WT_DELETED | Status::INDEX_DELETED) {
+            print_warn(format!("Deleted file '{}' is not part of the release commit", rel_path.display()));
+        } else {
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+/// Runs the hook if configured and returns the files it changed. The changes are recorded in the
+/// transaction also if the hook fails.
+pub fn run_hook_in_transaction(repo: &Repository, git_base_path: &Path, name: &str, command: Option<&str>, env: &HookEnv,
+                               changed_before: &[PathBuf], transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    let Some(command) = command else { return Ok(vec![]); };
+    let result = run_hook(name, command, git_base_path, env);
+    let changed = record_changes(repo, git_base_path, changed_before, transaction)?;
+    result.map(|_| changed)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_hooks {
+    use super::*;
+
+    #[test]
+    fn test_run_hook_env_and_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, new) = (Version::try_from("1.0.0".to_string()).unwrap(), Version::try_from("1.1.0".to_string()).unwrap());
+        let env = HookEnv { old_version: &old, new_version: &new, tag: "v1.1.0" };
+        run_hook("pre_commit", "echo \"$CGVS_HOOK $CGVS_OLD_VERSION $CGVS_NEW_VERSION $CGVS_TAG\" > out.txt", dir.path(), &env).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("out.txt")).unwrap(), "pre_commit 1.0.0 1.1.0 v1.1.0\n");
+        let e = run_hook("pre_push", "exit 3", dir.path(), &env).unwrap_err();
+        assert_eq!(e.exit_code(), 13);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt::Display;
+
+/// Version of the JSON schema of all machine-readable outputs (see `schema.json`). Incremented on
+/// every incompatible change of the outputs.
+pub const SCHEMA_VERSION: i64 = 1;
+/// JSON schema of all machine-readable outputs
+pub const SCHEMA: &str = include_str!("schema.json");
+
+// ********************************************************
+// ********************************************************
+/// Minimal JSON document used for the machine-readable output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Str(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+impl JsonValue {
+    pub fn object() -> Self { JsonValue::Object(vec![]) }
+
+    /// Adds a field to an object, does nothing for other values.
+    pub fn with(mut self, key: &str, value: impl Into<JsonValue>) -> Self {
+        if let JsonValue::Object(fields) = &mut self {
+            fields.push((key.to_string(), value.into()));
+        }
+        self
+    }
+}
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self { JsonValue::Str(value.to_string()) }
+}
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self { JsonValue::Str(value) }
+}
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self { JsonValue::Bool(value) }
+}
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self { JsonValue::Number(value) }
+}
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self { value.map(|v| v.into()).unwrap_or(JsonValue::Null) }
+}
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self { JsonValue::Array(value.into_iter().map(|v| v.into()).collect()) }
+}
+
+/// Starts an output document with the schema version and the status.
+pub fn document(status: &str) -> JsonValue {
+    JsonValue::object().with("schema_version", SCHEMA_VERSION).with("status", status)
+}
+
+/// Content of a json string literal without the quotes, for values inserted into a json template.
+pub fn escape(s: &str) -> String {
+    let quoted = JsonValue::Str(s.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn write_escaped(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(b) => write!(f, "{}", b),
+            JsonValue::Number(n) => write!(f, "{}", n),
+            JsonValue::Str(s) => write_escaped(f, s),
+            JsonValue::Array(values) => {
+                write!(f, "[")?;
+                for (n, v) in values.iter().enumerate() {
+                    if n > 0 { write!(f, ",")?; }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(fields) => {
+                write!(f, "{{")?;
+                for (n, (k, v)) in fields.iter().enumerate() {
+                    if n > 0 { write!(f, ",")?; }
+                    write_escaped(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_json {
+    use super::*;
+
+    #[test]
+    fn test_json_display() {
+        let doc = JsonValue::object()
+            .with("name", "a \"quoted\"\nline")
+            .with("count", 3i64)
+            .with("ok", true)
+            .with("missing", None::<String>)
+            .with("list", vec!["x", "y"]);
+        assert_eq!(doc.to_string(), r#"{"name":"a \"quoted\"\nline","count":3,"ok":true,"missing":null,"list":["x","y"]}"#);
+        assert_eq!(escape("say \"hi\"\n"), r#"say \"hi\"\n"#);
+    }
+    #[test]
+    fn test_schema_version_matches() {
+        assert!(SCHEMA.contains(&format!(r#""schema_version": {{ "const": {} }}"#, SCHEMA_VERSION)));
+        assert!(document("success").to_string().starts_with(&format!(r#"{{"schema_version":{},"#, SCHEMA_VERSION)));
+    }
+}
@@ -0,0 +1,32 @@
+//! Writes the version to one or multiple Cargo.toml files of a project, commits them and creates a
+//! git tag. The `cgvs` binary is a thin command line interface, other tools can embed the release
+//! with [release::Release].
+#[macro_use]
+pub mod utils;
+pub mod backend;
+pub mod build_number;
+pub mod changelog;
+pub mod check;
+pub mod config;
+pub mod date;
+pub mod diff;
+pub mod error;
+pub mod gitlab;
+pub mod history;
+pub mod hooks;
+pub mod json;
+pub mod logging;
+pub mod notes;
+pub mod notify;
+pub mod promote;
+pub mod pull_request;
+pub mod release;
+pub mod release_info;
+pub mod replacements;
+pub mod sinks;
+pub mod stats;
+pub mod submodules;
+pub mod template;
+pub mod transaction;
+pub mod undo;
+pub mod watch;
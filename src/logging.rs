@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use clap::ValueEnum;
+use git2::TraceLevel;
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::filter::{Directive, EnvFilter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{fmt, Layer};
+use tracing_subscriber::prelude::*;
+use crate::error::{Error, Result};
+
+/// Log target of git operations (add, commit, tag, push, fetch)
+pub const GIT: &str = "cgvs::git";
+/// Log target of the search for the project path, cargo.toml files and the git repository
+pub const DISCOVERY: &str = "cgvs::discovery";
+/// Log target of credentials and proxy selection
+pub const AUTH: &str = "cgvs::auth";
+/// Log target of the console output (steps, progress, warnings, errors), only written to the log file
+pub const CONSOLE: &str = "cgvs::console";
+
+// ********************************************************
+// ********************************************************
+/// Subsystem which can be traced with `--trace` independently of `-v`.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Subsystem {
+    Git,
+    Discovery,
+    Auth,
+}
+impl Subsystem {
+    /// Log targets traced for the subsystem. Git includes the trace messages of libgit2.
+    fn targets(&self) -> &'static [&'static str] {
+        match self {
+            Subsystem::Git => &[GIT, "git2"],
+            Subsystem::Discovery => &[DISCOVERY],
+            Subsystem::Auth => &[AUTH],
+        }
+    }
+}
+
+// ********************************************************
+// ********************************************************
+/// Filter of the log on stderr. The level is derived from the number of `-v` flags (or error only
+/// for quiet), the given subsystems are traced and the directives of `RUST_LOG` (e.g.
+/// `info,git2=trace` or `cgvs::git[push]=trace`) are applied last, so they override both. An
+/// invalid directive is an error instead of being ignored.
+pub fn console_filter(verbose: u8, subsystems: &[Subsystem], quiet: bool, rust_log: Option<&str>) -> Result<EnvFilter> {
+    let level = if quiet { LevelFilter::ERROR } else {
+        match verbose { 0 => LevelFilter::WARN, 1 => LevelFilter::INFO, 2 => LevelFilter::DEBUG, _ => LevelFilter::TRACE }
+    };
+    let mut filter = EnvFilter::default().add_directive(level.into());
+    for target in subsystems.iter().flat_map(|s| s.targets()) {
+        filter = filter.add_directive(format!("{}=trace", target).parse().expect("log targets are valid directives"));
+    }
+    for entry in rust_log.unwrap_or("").split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        let directive: Directive = entry.parse().map_err(|e| Error::Usage(format!("Invalid RUST_LOG directive '{}': {}", entry, e)))?;
+        filter = filter.add_directive(directive);
+    }
+    // the console output is printed already, it only goes to the log file
+    Ok(filter.add_directive(format!("{}=off", CONSOLE).parse().expect("log targets are valid directives")))
+}
+
+/// Writes the log to stderr above the spinner of the running step.
+struct ConsoleWriter;
+impl Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        crate::utils::suspend_step(|| std::io::stderr().write(buf))
+    }
+    fn flush(&mut self) -> std::io::Result<()> { std::io::stderr().flush() }
+}
+
+/// Layer of the log file: everything including the console output and the libgit2 traces,
+/// uncolored and with UTC timestamps. Every event is written unbuffered, so the log is complete
+/// even if the process exits with an error.
+fn file_layer<S: Subscriber + for<'a> LookupSpan<'a>>(file: File) -> impl Layer<S> {
+    fmt::layer().with_ansi(false).with_writer(Mutex::new(file)).with_filter(LevelFilter::TRACE)
+}
+
+fn git2_trace(level: TraceLevel, msg: &[u8]) {
+    let msg = String::from_utf8_lossy(msg);
+    match level {
+        TraceLevel::Fatal | TraceLevel::Error => tracing::error!(target: "git2", "{}", msg),
+        TraceLevel::Warn => tracing::warn!(target: "git2", "{}", msg),
+        TraceLevel::Info => tracing::info!(target: "git2", "{}", msg),
+        TraceLevel::Debug => tracing::debug!(target: "git2", "{}", msg),
+        _ => tracing::trace!(target: "git2", "{}", msg),
+    }
+}
+
+/// Installs the subscriber with the log on stderr, see [console_filter], and the log file if
+/// given, which gets everything independent of the console level. Git operations run in spans,
+/// so their libgit2 traces are attributed to them.
+pub fn init(verbose: u8, subsystems: &[Subsystem], quiet: bool, log_file: Option<&Path>) -> Result<()> {
+    let filter = console_filter(verbose, subsystems, quiet, std::env::var("RUST_LOG").ok().as_deref())?;
+    let file = log_file.map(|f| File::create(f)
+        .map_err(|e| Error::io(format!("Could not create log file '{}'", f.display()), e))).transpose()?;
+    let trace_git2 = file.is_some() || filter.max_level_hint().is_some_and(|l| l >= LevelFilter::DEBUG);
+    let console = fmt::layer().without_time().with_ansi(false).with_writer(|| ConsoleWriter).with_filter(filter);
+    let _ = tracing_subscriber::registry().with(console).with(file.map(file_layer)).try_init();
+    if trace_git2 {
+        // libgit2 only emits trace messages if it was built with tracing support
+        let _ = git2::trace_set(TraceLevel::Trace, git2_trace);
+    }
+    Ok(())
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_logging {
+    use super::*;
+
+    #[test]
+    fn test_console_filter() {
+        let filter = console_filter(0, &[], false, Some("debug,git2=trace, cgvs::discovery=off")).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::TRACE));
+        assert_eq!(filter.to_string(), "cgvs::discovery=off,cgvs::console=off,git2=trace,debug");
+        assert_eq!(console_filter(1, &[], true, None).unwrap().max_level_hint(), Some(LevelFilter::ERROR));
+        assert_eq!(console_filter(2, &[], false, Some("warn")).unwrap().to_string(), "cgvs::console=off,warn");
+        assert_eq!(console_filter(0, &[], false, Some("[push]=trace")).unwrap().to_string(), "cgvs::console=off,warn,[push]=trace");
+        let err = console_filter(0, &[], false, Some("info,git2=loud")).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid RUST_LOG directive 'git2=loud'"), "{}", err);
+    }
+    #[test]
+    fn test_subsystem_targets() {
+        let filter = console_filter(0, &[Subsystem::Git], false, None).unwrap();
+        assert_eq!(filter.to_string(), "cgvs::console=off,cgvs::git=trace,git2=trace,warn");
+        assert_eq!(console_filter(0, &[Subsystem::Auth], false, Some("cgvs::auth=info")).unwrap().to_string(),
+                   "cgvs::console=off,cgvs::auth=info,warn");
+    }
+    #[test]
+    fn test_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cgvs.log");
+        let subscriber = tracing_subscriber::registry().with(file_layer(File::create(&file).unwrap()));
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::debug_span!(target: GIT, "push", remote = "origin").entered();
+            tracing::trace!(target: "git2", "negotiation done");
+            tracing::info!(target: CONSOLE, "[1/5] Analysing");
+        });
+        let log = std::fs::read_to_string(&file).unwrap();
+        let lines: Vec<&str> = log.lines().map(|l| l.split_once(' ').unwrap().1.trim_start()).collect();
+        assert_eq!(lines, ["TRACE push{remote=\"origin\"}: git2: negotiation done", "INFO push{remote=\"origin\"}: cgvs::console: [1/5] Analysing"]);
+    }
+}
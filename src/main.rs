@@ -1,15 +1,24 @@
-mod utils;
+use cargo_git_version_setter::{backend, build_number, changelog, check, config, date, diff, gitlab, history, hooks, json, logging, notes, notify,
+                               progress, promote, pull_request, release_info, replacements, sinks, stats, submodules, template, undo, watch};
+use cargo_git_version_setter::utils::*;
+use cargo_git_version_setter::error::{Error, Result};
+use cargo_git_version_setter::release::{checkout_new_branch, commit_files, commit_message, compute_new_version, snapshot_version, tag_head, write_version_str, write_versions, VersionBump};
+use cargo_git_version_setter::transaction::Transaction;
+use cargo_git_version_setter::config::Config;
+use cargo_git_version_setter::date::ReleaseTime;
+use cargo_git_version_setter::json::JsonValue;
+use cargo_git_version_setter::build_number::BuildNumberSource;
 
+use std::collections::HashMap;
 use std::fs;
-use utils::*;
-
 use std::path::{Path, PathBuf};
-use std::fs::write;
+use std::io::IsTerminal;
 use std::process::exit;
-use clap::{Parser, Subcommand,};
-use toml_edit::{value, DocumentMut};
-use git2::{Repository, StatusOptions};
-use git2_credentials::CredentialHandler;
+use std::time::Duration;
+use clap::{CommandFactory, Parser, Subcommand,};
+use dialoguer::{Input, Select};
+use toml_edit::DocumentMut;
+use git2::{Oid, Repository};
 use pathdiff::diff_paths;
 
 
@@ -21,6 +30,29 @@ struct Cli {
     #[arg(short, long)]
     path: Option<PathBuf>,
 
+    /// git directory if it is not the .git of the project, e.g. for --separate-git-dir checkouts
+    #[arg(long)]
+    git_dir: Option<PathBuf>,
+
+    /// Work tree (git base path) for --git-dir, defaults to the project path. Cargo.toml files are searched up to it
+    #[arg(long)]
+    work_tree: Option<PathBuf>,
+
+    /// Select the cargo.toml files by package name instead of --cargo-file-selector (can be given
+    /// multiple times). Subdirectories are scanned
+    #[arg(long, conflicts_with = "cargo_file_selector")]
+    package: Vec<String>,
+
+    /// Glob of cargo.toml files or directories to leave out, e.g. examples/** (can be given multiple
+    /// times). Relative to the git base path
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Cargo.toml to write, instead of searching them from the path (can be given multiple times).
+    /// All of them are written, like with the 'all' selector
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["path", "package", "exclude", "cargo_file_selector", "scan_subdirs"])]
+    manifest_path: Vec<PathBuf>,
+
     /// Select cargo file, if multiple
     #[arg(short, long)]
     cargo_file_selector: Option<CargoFile>,
@@ -33,24 +65,291 @@ struct Cli {
     // #[arg(short, long, default_value_t = true)]
     // do_push: bool,
 
-    /// Turn debugging information on
+    /// Turn debugging information on, can be given multiple times (-v info, -vv debug, -vvv trace).
+    /// Log output can also be filtered with RUST_LOG
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 
-    /// Message when adding the tag to git
+    /// Trace a single subsystem without raising the overall verbosity, can be given multiple times
+    #[arg(long = "trace", value_name = "SUBSYSTEM")]
+    trace_subsystems: Vec<logging::Subsystem>,
+
+    /// Write a full, uncolored and timestamped log of the run (including libgit2 traces) to this
+    /// file, independent of the console verbosity
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Only print errors, e.g. for cron-driven releases
+    #[arg(short, long, default_value_t = false)]
+    quiet: bool,
+
+    /// Message when adding the tag to git. Can contain placeholders like {{ version }} or {{ date }}
     #[arg(short, long)]
     tag_message: Option<String>,
 
+    /// Append the release notes of the commits since the last version tag (grouped by conventional
+    /// commit type, or rendered with --notes-template) to the tag message
+    #[arg(long, default_value_t = false)]
+    notes_in_tag: bool,
+
+    /// Fail the release if a commit since the last version tag does not follow the conventional
+    /// commit format (or the pattern of `[lint]` in the config). Always on with 'enabled = true' there
+    #[arg(long, default_value_t = false)]
+    lint_commits: bool,
+
+    /// Time zone of the release date in templates: local (default), utc or an offset like +02:00
+    #[arg(long, value_parser = date::parse_timezone)]
+    timezone: Option<date::TimeZone>,
+
     /// git remote name to push new commits to. Defaults to 'origin' if not set
     #[arg(short, long)]
     remote: Option<String>,
 
-    /// Prefix for the version tag, defaults to 'v'
+    /// Push to the upstream of the release branch (its configured remote and merge branch) like a
+    /// plain 'git push' instead of to --remote
+    #[arg(long, default_value_t = false, conflicts_with = "remote")]
+    push_upstream: bool,
+
+    /// Prefix for the version tag, defaults to 'v'. Shorthand for `--tag-format '<prefix>{version}'`
     #[arg(short, long)]
     git_prefix_for_tag: Option<String>,
 
+    /// Template for the version tag name, e.g. 'release/{version}' or '{name}-v{version}'. {name} is
+    /// the package name of the selected cargo.toml(s)
+    #[arg(long, value_parser = validate_tag_format, conflicts_with = "git_prefix_for_tag")]
+    tag_format: Option<String>,
+
+    /// Template file (jinja-style) used to render the release notes from the release context
+    #[arg(long)]
+    notes_template: Option<PathBuf>,
+
+    /// Add a section with the release notes of the new version to the changelog file and include it
+    /// in the release commit. The format of the section is given by --notes-template
+    #[arg(long, default_value_t = false)]
+    changelog: bool,
+
+    /// Changelog file, relative to the git base path
+    #[arg(long, default_value = "CHANGELOG.md")]
+    changelog_file: PathBuf,
+
+    /// Write version, tag, released commit and date to the release info file (e.g. for a build.rs or a
+    /// docker build) and include it in the release commit
+    #[arg(long, default_value_t = false)]
+    release_info: bool,
+
+    /// Release info file, relative to the git base path. Written as Rust constants for a '.rs' file,
+    /// as KEY=value lines for a '.env' file and as TOML otherwise
+    #[arg(long, default_value = ".release-info.toml")]
+    release_info_file: PathBuf,
+
+    /// Increment a build number with every release, available as {{ build }} in the tag message: a
+    /// counter in the metadata of the cargo.toml or the number of commits like 'git rev-list --count'
+    #[arg(long, value_enum)]
+    build_number: Option<BuildNumberSource>,
+
+    /// Append the short id of the released commit as build metadata (`+g<sha>`) to the version in the
+    /// cargo.toml files, not to the tag
+    #[arg(long, default_value_t = false)]
+    with_sha: bool,
+
+    /// Write the plain new version to the version file (created if missing) and include it in the
+    /// release commit, e.g. for build scripts
+    #[arg(long, default_value_t = false)]
+    write_version_file: bool,
+
+    /// Version file for --write-version-file, relative to the git base path
+    #[arg(long, default_value = "VERSION")]
+    version_file: PathBuf,
+
+    /// Update the version of the released crates in dependency snippets (my-crate = "1.2") and
+    /// docs.rs/crates.io URLs of the readme and include it in the release commit
+    #[arg(long, default_value_t = false)]
+    update_readme: bool,
+
+    /// Readme file for --update-readme, relative to the git base path
+    #[arg(long, default_value = "README.md")]
+    readme_file: PathBuf,
+
+    /// Include the file in the release commit, e.g. a version header updated by a hook or by a
+    /// script run before. Its uncommitted changes are allowed. Relative to the git base path, can be
+    /// given multiple times
+    #[arg(long)]
+    extra_file: Vec<PathBuf>,
+
+    /// Config file with hooks, relative to the git base path. Not having one is fine
+    #[arg(long, default_value = ".cgvs.toml")]
+    config: PathBuf,
+
+    /// Output format; json prints a single machine-readable document instead of the progress text
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Base version for increments: the version of the cargo.toml files or the highest version tag,
+    /// e.g. if the manifest may have been edited out of band
+    #[arg(long, value_enum, default_value_t = IncrementSource::Manifest)]
+    increment_source: IncrementSource,
+
+    /// Comma separated branches releases may be cut from, e.g. main,release/*. Overrides the config
+    #[arg(long, value_delimiter = ',')]
+    allowed_branches: Vec<String>,
+
+    /// Only warn instead of failing if the branch is behind the remote
+    #[arg(long, default_value_t = false)]
+    allow_behind: bool,
+
+    /// Fail instead of warning if a tag of a higher version than the new one exists, locally or on
+    /// the remote, which usually means releasing from a stale branch
+    #[arg(long, default_value_t = false)]
+    require_newest: bool,
+
+    /// Allow a fixed version which is lower than or equal to the current version of the cargo.toml files
+    #[arg(long, default_value_t = false)]
+    allow_downgrade: bool,
+
+    /// Release even if the working tree has uncommitted changes. They are not part of the release
+    /// commit, changes of the cargo.toml files to write are never allowed
+    #[arg(long, default_value_t = false)]
+    allow_dirty: bool,
+
+    /// Stash uncommitted changes before the release and pop them afterwards, also if the release
+    /// fails, like 'git rebase --autostash'. Untracked files are left in place
+    #[arg(long, default_value_t = false, conflicts_with = "allow_dirty")]
+    autostash: bool,
+
+    /// Only tolerate uncommitted changes of paths matching the glob (relative to the git base path,
+    /// e.g. 'docs/generated/**'). Can be given multiple times
+    #[arg(long, requires = "allow_dirty")]
+    dirty_ignore: Vec<String>,
+
+    /// Fold the version change into the HEAD commit instead of creating a new commit, keeping its
+    /// message and author, and tag the amended commit
+    #[arg(long, default_value_t = false)]
+    amend: bool,
+
+    /// Commit each cargo.toml on its own with the crate name in the message, so the crates have
+    /// their own history and can be cherry-picked. Requires --cargo-file-selector all or workspace
+    #[arg(long, default_value_t = false, conflicts_with = "amend")]
+    commit_per_crate: bool,
+
+    /// Force push the release branch, e.g. after a rebase, but only if the remote branch is still at
+    /// the commit fetched before the release
+    #[arg(long, default_value_t = false)]
+    force_with_lease: bool,
+
+    /// Replace the version tag if it already exists locally or on the remote, e.g. to fix a botched
+    /// release. The remote tag is force pushed, but only if it was not changed since the fetch
+    #[arg(long, default_value_t = false)]
+    force_tag: bool,
+
+    /// What to push to the remote after the release commit and tag were created
+    #[arg(long, value_enum, default_value_t = PushSelection::Both)]
+    push: PushSelection,
+
+    /// Only push the tag, not the branch, e.g. if branch pushes go through pull requests. Shorthand
+    /// for --push tags
+    #[arg(long, default_value_t = false, conflicts_with = "push")]
+    push_tags_only: bool,
+
+    /// Branch to create or update with the release commit and push if HEAD is detached (e.g. in CI)
+    #[arg(long)]
+    branch: Option<String>,
+
+    /// Create the release commit on this new branch (e.g. 'release/{{ tag }}') and push only the branch
+    /// instead of committing to the branch of HEAD, e.g. for a pull request into a protected branch.
+    /// The version is tagged after the merge
+    #[arg(long, conflicts_with_all = ["amend", "branch", "push", "push_tags_only", "push_upstream", "force_with_lease", "force_tag", "gitlab_release"])]
+    release_branch: Option<String>,
+
+    /// Push the release commit to a new branch (--release-branch, default 'release/{{ tag }}') and
+    /// open a pull request (merge request on GitLab) into the branch of HEAD with the release notes
+    /// as description. Needs GITHUB_TOKEN/GH_TOKEN or GITLAB_TOKEN/CI_JOB_TOKEN
+    #[arg(long, default_value_t = false, conflicts_with_all = ["amend", "branch", "push", "push_tags_only", "push_upstream", "force_with_lease", "force_tag", "gitlab_release"])]
+    create_pr: bool,
+
+    /// Private ssh key (e.g. a deploy key) to authenticate with ssh remotes instead of the keys found
+    /// in the ssh agent or ~/.ssh
+    #[arg(long, env = "CGVS_SSH_KEY")]
+    ssh_key: Option<PathBuf>,
+
+    /// Environment variable holding the passphrase of --ssh-key
+    #[arg(long, requires = "ssh_key")]
+    ssh_passphrase_env: Option<String>,
+
+    /// Host GIT_TOKEN is offered to, e.g. git.example.com:8443. Defaults to the host of the remote
+    #[arg(long, env = "CGVS_TOKEN_HOST")]
+    token_host: Option<String>,
+
+    /// User name for the token of GIT_TOKEN or GITHUB_TOKEN used with http(s) remotes.
+    /// Defaults to the user of the remote url or x-access-token
+    #[arg(long, env = "CGVS_TOKEN_USER")]
+    token_user: Option<String>,
+
+    /// Author of the release commit as `Name <email>` instead of user.name and user.email of the git
+    /// config
+    #[arg(long, env = "CGVS_AUTHOR", value_parser = parse_identity)]
+    author: Option<(String, String)>,
+
+    /// Committer of the release commit and tagger of the version tag as `Name <email>`
+    #[arg(long, env = "CGVS_COMMITTER", value_parser = parse_identity)]
+    committer: Option<(String, String)>,
+
+    /// Date of the release commit and tag as RFC 3339 (e.g. 2024-03-01T12:00:00Z) instead of the
+    /// current time, for reproducible builds. Defaults to SOURCE_DATE_EPOCH if set
+    #[arg(long, value_parser = ReleaseTime::parse_rfc3339)]
+    commit_date: Option<ReleaseTime>,
+
+    /// Use the GitHub Actions bot as author and committer unless given with --author or --committer
+    #[arg(long, default_value_t = false)]
+    bot: bool,
+
+    /// Push with the git executable instead of libgit2, using the credential helpers, ssh config and
+    /// proxies of the user
+    #[arg(long, default_value_t = false)]
+    push_via_cli: bool,
+
+    /// Implementation for reading tags and history. gix is faster on large repositories and only
+    /// available if built with the 'gix' feature
+    #[arg(long, value_enum, env = "CGVS_GIT_BACKEND", default_value_t = backend::GitBackendKind::Git2)]
+    git_backend: backend::GitBackendKind,
+
+    /// Retries of a push failing with a network error, with exponential backoff (1s, 2s, 4s, ...)
+    #[arg(long, default_value_t = 2)]
+    push_retries: u32,
+
+    /// Seconds all push attempts together may take before the release fails and is rolled back
+    #[arg(long, default_value_t = 300)]
+    push_timeout: u64,
+
+    /// Create a GitLab release for the new tag with the release notes as description.
+    /// Authenticates with GITLAB_TOKEN or CI_JOB_TOKEN, the project is derived from the remote url
+    #[arg(long, default_value_t = false)]
+    gitlab_release: bool,
+
+    /// Maximum size in bytes of a file changed by the release commit, checked before pushing
+    #[arg(long, default_value_t = 1024 * 1024)]
+    max_blob_size: u64,
+
+    /// Keep written files, commit and tag if a later step fails instead of rolling them back
+    #[arg(long, default_value_t = false)]
+    keep_on_error: bool,
+
+    /// Release the submodules with a Cargo.toml first (commit and tag in the submodule, pushed to
+    /// its remote) and commit the updated submodule pointers with the release commit
+    #[arg(long, default_value_t = false)]
+    submodules: bool,
+
+    /// Do not ask for confirmation before modifying anything, e.g. in automation
+    #[arg(short, long, default_value_t = false, overrides_with = "yes")]
+    yes: bool,
+
+    /// Append an anonymized usage record (command, outcome and duration of each step) as a JSON line
+    /// to this file. Nothing is sent over the network
+    #[arg(long, env = "CGVS_STATS_FILE")]
+    stats_file: Option<PathBuf>,
+
+    /// Without a command, the next version is picked interactively
     #[command(subcommand)]
-    change_type: VersionChangeType,
+    change_type: Option<VersionChangeType>,
 }
 
 #[derive(Subcommand, PartialEq, Debug)]
@@ -64,294 +363,1331 @@ enum VersionChangeType {
     /// the lower version parts are set to zero
     Increment {
         // #[arg(short, long)]
-        vtype: IncrementVersionPart,
+        vtype: IncrementSelector,
     },
+    /// Derive the version part to increment from the conventional commits since the last version tag:
+    /// major for breaking changes, minor for features, patch for fixes
+    Auto,
     /// Only show versions from cargo and git and exit afterward.
-    OnlyShow
+    OnlyShow,
+    /// Show the release notes the next release would generate and exit afterward.
+    PreviewNotes {
+        vtype: IncrementVersionPart,
+        /// Only include commits touching the directory of the selected cargo.toml (following renames)
+        #[arg(long, default_value_t = false)]
+        only_crate: bool,
+    },
+    /// Revert the last release: delete the latest version tag and drop or revert its version commit
+    Undo {
+        /// Always create a revert commit instead of dropping the version commit
+        #[arg(long, default_value_t = false)]
+        revert: bool,
+        /// Also delete the tag on the remote and push the reverted branch
+        #[arg(long, default_value_t = false)]
+        delete_remote: bool,
+    },
+    /// Check that the versions of the cargo.toml files agree with each other and with the latest
+    /// version tag. Exits with a non-zero code otherwise.
+    #[command(visible_alias = "verify")]
+    Check {
+        /// For CI: also require a clean tree, an allowed branch and the version tag at HEAD, each
+        /// failing with its own exit code
+        #[arg(long, default_value_t = false)]
+        strict: bool,
+    },
+    /// Only print the next version to stdout and exit, without any other output or modification
+    NextVersion {
+        #[command(subcommand)]
+        bump: VersionBump,
+    },
+    /// Only print the version of the highest version tag to stdout and exit, e.g. the last released
+    /// version for a pipeline
+    GetLatest {
+        /// Print the whole tag name instead of the version, e.g. v1.2.3
+        #[arg(long, default_value_t = false)]
+        with_prefix: bool,
+    },
+    /// List the version tags with date, tagger, commit and message, highest version first
+    History {
+        /// Only list the given number of releases
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// List the commits between two released versions, e.g. for a release announcement
+    Diff {
+        /// Older version or tag, e.g. 1.2.0 or v1.2.0
+        from: String,
+        /// Newer version or tag
+        to: String,
+        /// Also list the changed files with their added and removed lines
+        #[arg(long, default_value_t = false)]
+        stat: bool,
+    },
+    /// Render the release notes of the commits between two refs without a release, e.g. for an announcement
+    Notes {
+        /// Version or tag the notes start after, e.g. 1.2.0 or v1.2.0 (default: the version tag before --to)
+        #[arg(long)]
+        from: Option<String>,
+        /// Version, tag or any git ref the notes end at
+        #[arg(long, default_value = "HEAD")]
+        to: String,
+        /// Write the notes to this file instead of stdout
+        #[arg(long)]
+        output_file: Option<PathBuf>,
+    },
+    /// Watch the repository and automatically cut a release when the conditions are met
+    Watch {
+        /// Release when at least this many commits were made since the last version tag
+        #[arg(long)]
+        min_commits: Option<usize>,
+        /// Version part to increment for releases triggered by min_commits
+        #[arg(long, value_enum, default_value_t = IncrementVersionPart::Patch)]
+        vtype: IncrementVersionPart,
+        /// Release when a merge commit contains a directive like '[release: minor]'
+        #[arg(long, default_value_t = false)]
+        release_label: bool,
+        /// Seconds between two polls
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// Fetch and fast-forward the current branch from the remote before each poll
+        #[arg(long, default_value_t = false)]
+        fetch: bool,
+        /// Only poll once and exit afterward
+        #[arg(long, default_value_t = false)]
+        once: bool,
+    },
+    /// Print the JSON schema of all machine-readable outputs and exit
+    Schema,
+    /// Set all selected cargo.toml files to the same version without incrementing, e.g. to repair
+    /// drifted versions. Only commits, neither tags nor pushes
+    Sync {
+        /// Version to set: the highest one of the cargo.toml files or the one of the latest tag
+        #[arg(long, value_enum, default_value_t = SyncTarget::Highest)]
+        to: SyncTarget,
+    },
+    /// Tag HEAD with the version already in the cargo.toml files and push the tag, without modifying
+    /// them, e.g. after the version bump was merged via a pull request
+    ReleaseCurrent,
+    /// Write a snapshot version like 1.3.0-dev.20240611.abc1234 (next version, date and commit id) to
+    /// the cargo.toml files for nightly builds. Neither commits nor tags
+    Snapshot {
+        /// Version part to increment for the next version
+        #[arg(value_enum, default_value_t = IncrementVersionPart::Minor)]
+        vtype: IncrementVersionPart,
+        /// Pre-release identifier placed before the date
+        #[arg(long, default_value = "dev")]
+        identifier: String,
+    },
+    /// Finalize the release candidate version of the cargo.toml files (X.Y.Z-rc.N -> X.Y.Z) and release
+    /// it. An rc of the version has to be tagged
+    Promote,
+    /// Promote a release candidate: tag the commit of the rc tag with the final version and push it
+    PromoteRc {
+        /// The rc version or tag to promote, e.g. 1.3.0-rc.2
+        rc_tag: String,
+        /// Fast-forward this branch (e.g. main) to the rc commit and push it as well
+        #[arg(long)]
+        fast_forward: Option<String>,
+        /// After the push, yank all rc releases of the version from the registry with 'cargo yank'
+        #[arg(long, default_value_t = false)]
+        yank_rcs: bool,
+    },
 }
 
 
 
+impl VersionChangeType {
+    fn name(&self) -> &'static str {
+        match self {
+            VersionChangeType::Fixed { .. } => "fixed",
+            VersionChangeType::Increment { .. } => "increment",
+            VersionChangeType::Auto => "auto",
+            VersionChangeType::OnlyShow => "only-show",
+            VersionChangeType::PreviewNotes { .. } => "preview-notes",
+            VersionChangeType::Undo { .. } => "undo",
+            VersionChangeType::Check { .. } => "check",
+            VersionChangeType::NextVersion { .. } => "next-version",
+            VersionChangeType::GetLatest { .. } => "get-latest",
+            VersionChangeType::History { .. } => "history",
+            VersionChangeType::Diff { .. } => "diff",
+            VersionChangeType::Notes { .. } => "notes",
+            VersionChangeType::Watch { .. } => "watch",
+            VersionChangeType::Promote => "promote",
+            VersionChangeType::PromoteRc { .. } => "promote-rc",
+            VersionChangeType::Sync { .. } => "sync",
+            VersionChangeType::ReleaseCurrent => "release-current",
+            VersionChangeType::Snapshot { .. } => "snapshot",
+            VersionChangeType::Schema => "schema",
+        }
+    }
+}
+
+
+
+/// The cargo.toml files of `--manifest-path`, or the ones found from the path, and the git base path.
+fn find_cargo_tomls(cli: &Cli, path: PathBuf, work_tree: Option<&Path>) -> Result<(Vec<PathBuf>, Option<PathBuf>)> {
+    if !cli.manifest_path.is_empty() { return manifest_paths_and_git_base(&cli.manifest_path, work_tree, cli.git_dir.as_deref()); }
+    find_cargo_tomls_and_git_base(path, cli.scan_subdirs, work_tree, cli.git_dir.as_deref())
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    // the selected packages are all written, like with the 'all' selector
+    if !cli.package.is_empty() {
+        cli.scan_subdirs = true;
+        cli.cargo_file_selector = Some(CargoFile::All);
+    }
+    if !cli.manifest_path.is_empty() { cli.cargo_file_selector = Some(CargoFile::All); }
+    if matches!(cli.cargo_file_selector, Some(CargoFile::Workspace)) { cli.scan_subdirs = true; }
+    set_output_format(cli.output);
+    set_quiet(cli.quiet);
+    let ssh_key = cli.ssh_key.clone().map(|k| SshKey::new(k, cli.ssh_passphrase_env.as_deref()).unwrap_or_else(|e| print_error(e)));
+    set_push_via_cli(cli.push_via_cli);
+    backend::set_git_backend(cli.git_backend).unwrap_or_else(|e| print_error(e));
+    set_push_retry(PushRetry { retries: cli.push_retries, timeout: Some(Duration::from_secs(cli.push_timeout)) });
+    set_auth_options(AuthOptions { ssh_key, token_host: cli.token_host.clone(), token_user: cli.token_user.clone() });
+    let bot = cli.bot.then(|| (BOT_IDENTITY.0.to_string(), BOT_IDENTITY.1.to_string()));
+    let commit_date = cli.commit_date.or_else(|| std::env::var("SOURCE_DATE_EPOCH").ok()
+        .map(|s| ReleaseTime::from_epoch(&s).unwrap_or_else(|e| print_error(Error::Usage(e)))));
+    set_identity(Identity { author: cli.author.clone().or(bot.clone()), committer: cli.committer.clone().or(bot),
+                            time: commit_date.map(|t| git2::Time::new(t.seconds, t.offset_minutes)) });
+    logging::init(cli.verbose, &cli.trace_subsystems, cli.quiet, cli.log_file.as_deref()).unwrap_or_else(|e| print_error(e));
+    stats::init(cli.stats_file.clone(), cli.change_type.as_ref().map_or("pick", |c| c.name()));
+
+    if let Some(VersionChangeType::Schema) = &cli.change_type {
+        print!("{}", json::SCHEMA);
+        exit_success();
+    }
 
     let path = {
-        let p = cli.path.unwrap_or_else(|| PathBuf::from("./"));
-        if p.is_file() { p.parent().unwrap().to_path_buf() }
+        let p = cli.path.take().unwrap_or_else(|| PathBuf::from("./"));
+        if p.is_file() { p.parent().map(|p| p.to_path_buf()).unwrap_or_default() }
         else { p }
     };
-    if !path.exists() { print_error(format!("Path does not exist ({})", path.display())); }
-    if !path.is_dir() { print_error(format!("Path is not a directory ({})", path.display())); }
+    if !path.exists() { print_error(Error::Discovery(format!("Path does not exist ({})", path.display()))); }
+    if !path.is_dir() { print_error(Error::Discovery(format!("Path is not a directory ({})", path.display()))); }
+
+    tracing::info!(target: logging::DISCOVERY, "Using path: {}", path.display());
+    let work_tree = cli.work_tree.clone().or_else(|| cli.git_dir.as_ref().map(|_| path.clone()));
 
-    if cli.verbose > 0 { println!("Using path: {}", path.display()); }
+    if let Some(VersionChangeType::NextVersion { bump }) = &cli.change_type {
+        // stdout only contains the version, warnings go to the log on stderr
+        set_quiet(true);
+        let (cargo_tomls, git_base_path) = find_cargo_tomls(&cli, path, work_tree.as_deref()).unwrap_or_else(|e| print_error(e));
+        let cargo_tomls = exclude_cargo_tomls(cargo_tomls, git_base_path.as_deref(), &cli.exclude);
+        if cargo_tomls.is_empty() { print_error(Error::Discovery("No cargo.toml found.".to_string())); }
+        let cargo_tomls = if cli.package.is_empty() { filter_cargo_tomls_by_selector(cargo_tomls, &cli.cargo_file_selector) }
+            else { select_cargo_tomls_by_package(cargo_tomls, &cli.package) }.unwrap_or_else(|e| print_error(e));
+        let cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        let tag_base = (cli.increment_source == IncrementSource::Tag).then(|| {
+            let git_base_path = git_base_path.unwrap_or_else(|| print_error(Error::Discovery("Could not find git base path.".to_string())));
+            let repo = open_repository(&git_base_path, cli.git_dir.as_deref()).unwrap_or_else(|e| print_error(e));
+            let tag_format = tag_format(&cli, &cargo_tomls).unwrap_or_else(|e| print_error(e));
+            tag_base_version(&repo, &tag_format, &cargo_content).unwrap_or_else(|e| print_error(e))
+        });
+        let new_version = compute_new_version(&cargo_content, bump, &cli.cargo_file_selector, tag_base.as_ref())
+            .unwrap_or_else(|e| print_error(e));
+        println!("{}", new_version);
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::GetLatest { with_prefix }) = &cli.change_type {
+        set_quiet(true);
+        let (cargo_tomls, git_base_path) = find_cargo_tomls(&cli, path, work_tree.as_deref()).unwrap_or_else(|e| print_error(e));
+        let git_base_path = git_base_path.unwrap_or_else(|| print_error(Error::Discovery("Could not find git base path.".to_string())));
+        // the cargo.tomls are only needed for a tag format with {name}
+        let cargo_tomls = if !cli.tag_format.as_deref().is_some_and(|t| t.contains("{name}")) { Ok(vec![]) }
+            else if cli.package.is_empty() {
+                filter_cargo_tomls_by_selector(exclude_cargo_tomls(cargo_tomls, Some(&git_base_path), &cli.exclude), &cli.cargo_file_selector)
+            } else { select_cargo_tomls_by_package(cargo_tomls, &cli.package) }.unwrap_or_else(|e| print_error(e));
+        let repo = open_repository(&git_base_path, cli.git_dir.as_deref()).unwrap_or_else(|e| print_error(e));
+        let tag_format = tag_format(&cli, &cargo_tomls).unwrap_or_else(|e| print_error(e));
+        let (tag_name, version) = latest_version_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e))
+            .unwrap_or_else(|| print_error(Error::Repository(format!("No version tag matching '{}' found", tag_format))));
+        if *with_prefix { println!("{}", tag_name) } else { println!("{}", version) }
+        exit_success();
+    }
 
     // ***
     let txt = String::from("Analysing cargo project");
-    println!("[1/5] {} {} ...", LOOKING_GLASS, txt);
+    start_step(format!("[1/5] {} {} ...", LOOKING_GLASS, txt));
+    stats::step("analyse");
 
-    let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(path, cli.scan_subdirs);
-    if cargo_tomls.is_empty() { print_error("No cargo.toml found.".to_string()); }
+    let (cargo_tomls, git_base_path) = find_cargo_tomls(&cli, path, work_tree.as_deref()).unwrap_or_else(|e| print_error(e));
+    let cargo_tomls = exclude_cargo_tomls(cargo_tomls, git_base_path.as_deref(), &cli.exclude);
+    if cargo_tomls.is_empty() { print_error(Error::Discovery("No cargo.toml found.".to_string())); }
 
     let git_base_path = match git_base_path {
-        Some(path) => path, None => { print_error("Could not find git base path.".to_string()) }
+        Some(path) => path, None => { print_error(Error::Discovery("Could not find git base path.".to_string())) }
     };
-    println!("{INDENT}Found git base path: {}", git_base_path.display());
-    println!("{INDENT}Found cargo.toml:\n{INDENT} - {}", cargo_tomls.iter().map(|ct| {
+    progress!("{INDENT}Found git base path: {}", git_base_path.display());
+    progress!("{INDENT}Found cargo.toml:\n{INDENT} - {}", cargo_tomls.iter().map(|ct| {
         ct.display().to_string() }).collect::<Vec<String>>().join(format!("\n{INDENT} - ").as_str()));
 
-    let cargo_tomls = filter_cargo_tomls_by_selector(cargo_tomls, &cli.cargo_file_selector);
+    let cargo_tomls = if cli.package.is_empty() { filter_cargo_tomls_by_selector(cargo_tomls, &cli.cargo_file_selector) }
+        else { select_cargo_tomls_by_package(cargo_tomls, &cli.package) }.unwrap_or_else(|e| print_error(e));
+    match &cli.cargo_file_selector {
+        Some(CargoFile::All) if !cli.package.is_empty() => progress!("{INDENT}  -> using packages: {}", cli.package.join(", ")),
+        Some(CargoFile::Leaf) => progress!("{INDENT}  -> using leaf: {}", cargo_tomls[0].display()),
+        Some(CargoFile::Base) => progress!("{INDENT}  -> using base: {}", cargo_tomls[0].display()),
+        Some(CargoFile::All) => progress!("{INDENT}  -> using all."),
+        Some(CargoFile::Workspace) => progress!("{INDENT}  -> using workspace members:\n{INDENT} - {}", cargo_tomls.iter()
+            .map(|ct| ct.display().to_string()).collect::<Vec<String>>().join(format!("\n{INDENT} - ").as_str())),
+        None => (),
+    }
+    let tag_format = tag_format(&cli, &cargo_tomls).unwrap_or_else(|e| print_error(e));
 
     // Init git repo and remote
-    println!("{INDENT}Opening git repo ...");
-    let repo = match Repository::open(git_base_path.clone()) {
-        Ok(repo) => repo,
-        Err(e) => panic!("failed to open git repo: {}", e),
+    progress!("{INDENT}Opening git repo ...");
+    let repo = open_repository(&git_base_path, cli.git_dir.as_deref()).unwrap_or_else(|e| print_error(e));
+    let config = Config::load(&git_base_path.join(&cli.config)).unwrap_or_else(|e| print_error(e));
+    // the cargo.tomls of submodules are released in their own repository
+    let cargo_tomls: Vec<PathBuf> = if !cli.submodules { cargo_tomls } else {
+        let paths = submodules::submodule_paths(&repo).unwrap_or_else(|e| print_error(e));
+        cargo_tomls.into_iter().filter(|f| !paths.iter().any(|p| f.starts_with(git_base_path.join(p)))).collect()
+    };
+    let timezone = cli.timezone.or(config.timezone);
+    let release_time = match commit_date {
+        Some(t) => timezone.map_or(t, |tz| t.with_timezone(tz)),
+        None => ReleaseTime::now(timezone.unwrap_or(date::TimeZone::Local)),
     };
-    if repo.is_bare() {
-        print_error("Cannot use bare repository".to_string());
-    }
 
-    if let VersionChangeType::OnlyShow = &cli.change_type {
-        let cargo_content = read_version_tomls(&cargo_tomls);
-        println!("{INDENT}Cargo.toml file with version:");
+    if let Some(VersionChangeType::OnlyShow) = &cli.change_type {
+        let cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        progress!("{INDENT}Cargo.toml file with version:");
         cargo_content.iter().for_each(|(fname, (version, _))| {
-            println!("{INDENT} - {}: {}", fname.display(), version);
+            progress!("{INDENT} - {}: {}", fname.display(), version);
         });
 
-        let git_tag_prefix = cli.git_prefix_for_tag.unwrap_or("v".to_string());
-        let tns = repo.tag_names(Some(format!("{git_tag_prefix}*").as_str())).unwrap()
-            .into_iter().filter_map(|ct| { match ct {
-            None => None,
-            Some(s) => Some(String::from(s))
-        } }).collect::<Vec<_>>();
+        let tns = list_version_tags(&repo, &tag_format).unwrap_or_else(|e| print_error(e));
         let mut git_tag_strings = "".to_string();
         tns.iter().enumerate().for_each(|(n, tn)| {
             if (n&7) == 0 { git_tag_strings += format!("\n{INDENT}  ").as_str(); }
             git_tag_strings += tn; git_tag_strings += ", ";
         });
-        println!("{INDENT}Git tags wth prefix ('{}'):{}", git_tag_prefix, git_tag_strings);
+        progress!("{INDENT}Git tags matching '{}':{}", tag_format, git_tag_strings);
 
-        println!("\n{INDENT}Show version finished.");
-        exit(0);
+        progress!("\n{INDENT}Show version finished.");
+        if is_json_output() {
+            let mut versions: Vec<_> = cargo_content.iter().map(|(f, (v, _))| JsonValue::object()
+                .with("file", f.display().to_string()).with("version", v.to_string())).collect();
+            versions.sort_by_key(|v| v.to_string());
+            println!("{}", json::document("success").with("command", "only-show")
+                .with("cargo_versions", versions).with("tags", tns));
+        }
+        exit_success();
     }
 
-    let tag_message = match cli.tag_message {
-        Some(s) => s, None => { print_error("No tag message found.".to_string()); }
-    };
+    if let Some(VersionChangeType::History { limit }) = &cli.change_type {
+        let releases = history::release_history(&repo, &tag_format, *limit).unwrap_or_else(|e| print_error(e));
+        end_step();
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "history")
+                .with("releases", releases.iter().map(|r| r.to_json()).collect::<Vec<_>>()));
+        } else {
+            if releases.is_empty() { print_warn(format!("No version tag matching '{}' found", tag_format)); }
+            releases.iter().for_each(|r| println!("{:<16} {:<25} {:.7}  {:<30} {}", r.tag, r.date.datetime(), r.commit.to_string(),
+                r.tagger, r.message.lines().next().unwrap_or("")));
+        }
+        exit_success();
+    }
 
-    let mut git_remote = {
-        let git_remote_name = match cli.remote {
-            None => {
-                if cli.verbose > 0 { println!("Setting git remote to 'origin' as it was not specified"); }
-                "origin".to_string()
+    if let Some(VersionChangeType::Diff { from, to, stat }) = &cli.change_type {
+        let result = (|| -> Result<_> {
+            let (from_tag, from_oid) = diff::resolve_version_tag(&repo, &tag_format, from)?;
+            let (to_tag, to_oid) = diff::resolve_version_tag(&repo, &tag_format, to)?;
+            let commits = notes::commits_between(&repo, Some(from_oid), to_oid)?;
+            let files = if *stat { Some(diff::file_stats(&repo, from_oid, to_oid)?) } else { None };
+            Ok((from_tag, to_tag, commits, files))
+        })();
+        let (from_tag, to_tag, commits, files) = result.unwrap_or_else(|e| print_error(e));
+        end_step();
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "diff").with("from", from_tag).with("to", to_tag)
+                .with("commits", commits.iter().map(|c| JsonValue::object().with("commit", c.id.to_string())
+                    .with("summary", c.message.lines().next().unwrap_or(""))).collect::<Vec<_>>())
+                .with("files", files.map(|f| f.iter().map(|f| f.to_json()).collect::<Vec<_>>())));
+        } else {
+            commits.iter().for_each(|c| println!("{} {}", c.short_id(), c.message.lines().next().unwrap_or("")));
+            if let Some(files) = &files {
+                println!();
+                files.iter().for_each(|f| println!(" {} | +{} -{}", f.path, f.insertions, f.deletions));
             }
-            Some(r) => r
+            println!("{} commit(s) from '{}' to '{}'", commits.len(), from_tag, to_tag);
+        }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Notes { from, to, output_file }) = &cli.change_type {
+        let result = (|| -> Result<_> {
+            // the notes of a version tag are for its version, of any other ref for the version of the cargo.toml
+            let to_tag = diff::resolve_version_tag(&repo, &tag_format, to).ok();
+            let to_oid = match &to_tag {
+                Some((_, oid)) => *oid,
+                None => repo.revparse_single(to).and_then(|o| o.peel_to_commit()).map(|c| c.id())
+                    .map_err(|e| Error::git(format!("Could not resolve '{}'", to), e))?,
+            };
+            let from = match from {
+                Some(from) => Some(diff::resolve_version_tag(&repo, &tag_format, from)?),
+                None => notes::previous_version_tag(&repo, &tag_format, to_oid)?.map(|(tn, _, oid)| (tn, oid)),
+            };
+            let commits = notes::commits_between(&repo, from.as_ref().map(|(_, oid)| *oid), to_oid)?;
+            let (version, tag) = match to_tag {
+                Some((tn, _)) => (Version::try_from(tag_format.version_part(&tn).unwrap_or_default().to_string())
+                    .map_err(|e| Error::Repository(format!("Tag '{}': {}", tn, e)))?, tn),
+                None => {
+                    let cargo_content = read_version_tomls(&cargo_tomls)?;
+                    let version = cargo_content.values().next().map(|(v, _)| v.clone())
+                        .ok_or_else(|| Error::Discovery("No cargo.toml found.".to_string()))?;
+                    (version, to.clone())
+                },
+            };
+            let from_tag = from.map(|(tn, _)| tn);
+            let rendered = render_release_notes(&cli.notes_template, &version, &tag, from_tag.as_deref(), &commits, &release_time)?;
+            if let Some(output_file) = output_file {
+                fs::write(output_file, &rendered).map_err(|e| Error::io(format!("Could not write '{}'", output_file.display()), e))?;
+            }
+            Ok((from_tag, tag, commits.len(), rendered))
+        })();
+        let (from_tag, to_tag, count, rendered) = result.unwrap_or_else(|e| print_error(e));
+        end_step();
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "notes").with("from", from_tag).with("to", to_tag)
+                .with("commits", count as i64).with("notes", rendered)
+                .with("output_file", output_file.as_ref().map(|f| f.display().to_string())));
+        } else if let Some(output_file) = output_file {
+            progress!("{INDENT}Wrote release notes of {} commit(s) since {} to '{}'", count, from_tag.as_deref().unwrap_or("start"),
+                      output_file.display());
+        } else {
+            println!("{}", rendered);
+        }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::PreviewNotes { vtype, only_crate }) = &cli.change_type {
+        let cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        let Some((current_version, _)) = cargo_content.values().next() else {
+            print_error(Error::Discovery("No cargo.toml found.".to_string()));
+        };
+        if cargo_content.values().any(|(v, _)| v != current_version) {
+            print_error(Error::Manifest("Versions differ between the cargo.toml files, cannot determine next version.".to_string()));
+        }
+        let new_version = current_version.increment_clone(vtype).unwrap_or_else(|e| print_error(e));
+
+        let last_tag = latest_version_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e)).map(|(tn, _)| tn);
+        match &last_tag {
+            Some(tn) => progress!("{INDENT}Collecting commits since tag '{}'", tn),
+            None => progress!("{INDENT}No version tag found, collecting all commits"),
+        }
+        let commits = if *only_crate {
+            if cargo_tomls.len() != 1 { print_error(Error::Usage("Option only_crate requires exactly one selected cargo.toml".to_string())); }
+            let crate_dir = cargo_tomls[0].parent().and_then(|d| diff_paths(d, &git_base_path)).unwrap_or_default();
+            progress!("{INDENT}Only commits touching '{}'", crate_dir.display());
+            notes::commits_since_for_path(&repo, last_tag.as_deref(), &crate_dir)
+        } else {
+            notes::commits_since(&repo, last_tag.as_deref())
+        };
+        let commits = commits.unwrap_or_else(|e| print_error(e));
+        let new_tag = tag_format.tag(&new_version);
+        let rendered = render_release_notes(&cli.notes_template, &new_version, &new_tag, last_tag.as_deref(), &commits, &release_time)
+            .unwrap_or_else(|e| print_error(e));
+        end_step();
+        println!("\n{}", rendered);
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Undo { revert, delete_remote }) = &cli.change_type {
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        let txt = String::from("Undo last release");
+        start_step(format!("[2/2] {} {} ...", PEN, txt));
+        stats::step("undo");
+        let latest_tag = undo::last_release_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e));
+        check_writable(&[], &repo).unwrap_or_else(|e| print_error(e));
+        let mut summary = vec![("Undo tag", latest_tag.map(|(tn, _)| tn).unwrap_or_default()),
+                               ("Mode", if *revert || *delete_remote { "revert commit" } else { "drop commit if HEAD" }.to_string())];
+        if *delete_remote { summary.push(("Remote", cli.remote.clone().unwrap_or("origin".to_string()))); }
+        confirm(&summary, cli.yes).unwrap_or_else(|e| print_error(e));
+        let mut transaction = Transaction::new();
+        let r = if *delete_remote {
+            let mut git_remote = find_git_remote(&repo, cli.remote.clone()).unwrap_or_else(|e| print_error(e));
+            undo::undo_last_release(&repo, &tag_format, *revert, Some(&mut git_remote), &mut transaction)
+        } else {
+            undo::undo_last_release(&repo, &tag_format, *revert, None, &mut transaction)
         };
+        finish_transaction(r, transaction, &repo, cli.keep_on_error);
+        finish_step(format!("       {} {} done", CHECK, txt));
+        if is_json_output() { println!("{}", json::document("success").with("command", "undo")); }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Watch { min_commits, vtype, release_label, interval, fetch, once }) = &cli.change_type {
+        if min_commits.is_none() && !release_label {
+            print_error(Error::Usage("Watch needs at least one condition (min_commits or release_label)".to_string()));
+        }
+        finish_step(format!("       {} {} done", CHECK, txt));
+        progress!("[2/2] {} Watching for releases ...", LOOKING_GLASS);
+        stats::step("watch");
+
+        // the global options (everything before the subcommand) are passed on to the release
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let release_args = watch::global_args(&Cli::command(), &args);
+        let conditions = watch::WatchConditions { min_commits: *min_commits, vtype: vtype.clone(), release_label: *release_label };
+        let remote_name = cli.remote.clone().unwrap_or("origin".to_string());
+        let r = watch::watch(&repo, &tag_format, fetch.then_some(remote_name.as_str()), &conditions,
+                             Duration::from_secs(*interval), *once, release_args);
+        if let Err(e) = r { print_error(e); }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Check { strict }) = &cli.change_type {
+        if *strict {
+            match count_uncommitted_changes(&repo) {
+                Ok(0) => {}, Ok(n) => print_error(Error::DirtyTree(n)), Err(e) => print_error(e),
+            }
+            let allowed_branches = if cli.allowed_branches.is_empty() { &config.allowed_branches } else { &cli.allowed_branches };
+            let branch = release_branch(&repo, cli.branch.as_deref()).unwrap_or_else(|e| print_error(e));
+            check_allowed_branch(branch.as_deref(), allowed_branches).unwrap_or_else(|e| print_error(e));
+        }
+        let cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        let mut versions: Vec<_> = cargo_content.iter().map(|(f, (v, _))| (f.clone(), v.clone())).collect();
+        versions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let latest_tag = latest_version_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e));
+        if let Some((tn, _)) = &latest_tag { progress!("{INDENT}Latest version tag: {}", tn); }
+
+        let problems = check::check_consistency(&versions, latest_tag.as_ref());
+        if is_json_output() {
+            println!("{}", json::document(if problems.is_empty() { "success" } else { "error" })
+                .with("command", "check").with("latest_tag", latest_tag.map(|(tn, _)| tn)).with("problems", problems.clone()));
+            if problems.is_empty() { exit_success(); }
+            let e = Error::Check(format!("Check failed with {} problem(s).", problems.len()));
+            stats::finish(Some(&e));
+            exit(e.exit_code());
+        }
+        if !problems.is_empty() {
+            problems.iter().for_each(|p| print_warn(p.clone()));
+            print_error(Error::Check(format!("Check failed with {} problem(s).", problems.len())));
+        }
+        finish_step(format!("       {} Versions are consistent", CHECK));
+        if *strict {
+            // the versions are consistent, so there is at least one and all agree
+            let tag = tag_format.tag(versions[0].1.without_build());
+            check::check_tag_at_head(&repo, &tag).unwrap_or_else(|e| print_error(e));
+            progress!("       {} Tag '{}' points at HEAD", CHECK, tag);
+        }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::PromoteRc { rc_tag, fast_forward, yank_rcs }) = &cli.change_type {
+        let mut git_remote = find_git_remote(&repo, cli.remote.clone()).unwrap_or_else(|e| print_error(e));
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        let txt = String::from("Promote release candidate");
+        start_step(format!("[2/2] {} {} ...", TAG, txt));
+        stats::step("promote");
+        check_writable(&[], &repo).unwrap_or_else(|e| print_error(e));
+        let mut summary = vec![("Promote", rc_tag.clone()), ("Remote", git_remote.name().unwrap_or("").to_string())];
+        if let Some(branch) = fast_forward { summary.push(("Fast-forward", branch.clone())); }
+        // the rcs are determined before the final tag exists, the crates are the selected ones
+        let yank = yank_rcs.then(|| -> Result<_> {
+            let (version, _) = promote::parse_rc_version(rc_tag, &tag_format)
+                .ok_or_else(|| Error::Usage(format!("'{}' is not a release candidate version (expected X.Y.Z-rc.N)", rc_tag)))?;
+            let rcs = promote::superseded_rcs(&repo, &tag_format, &version)?;
+            let crates: Vec<String> = cargo_tomls.iter().map(|ct| package_name(ct)).collect::<Result<Vec<_>>>()?.into_iter().flatten().collect();
+            Ok((crates, rcs))
+        }).transpose().unwrap_or_else(|e| print_error(e));
+        if let Some((crates, rcs)) = &yank { summary.push(("Yank", format!("{} of {}", rcs.join(", "), crates.join(", ")))); }
+        confirm(&summary, cli.yes).unwrap_or_else(|e| print_error(e));
+        let mut transaction = Transaction::new();
+        let result = promote::promote_rc(&repo, &tag_format, rc_tag, cli.tag_message.clone(), fast_forward.as_deref(),
+                                         &mut git_remote, &mut transaction);
+        finish_transaction(result, transaction, &repo, cli.keep_on_error);
+        if let Some((crates, rcs)) = &yank {
+            promote::yank_rcs(crates, rcs).into_iter().for_each(print_warn);
+        }
+        finish_step(format!("       {} {} done", CHECK, txt));
+        if is_json_output() { println!("{}", json::document("success").with("command", "promote-rc")); }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Sync { to }) = &cli.change_type {
+        let mut cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        let txt = String::from("Sync versions of cargo.toml(s)");
+        start_step(format!("[2/2] {} {} ...", PEN, txt));
+        stats::step("sync");
+        let target = match to {
+            SyncTarget::Highest => cargo_content.values().map(|(v, _)| v.clone()).max(),
+            SyncTarget::Tag => latest_version_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e)).map(|(_, v)| v),
+        }.unwrap_or_else(|| print_error(Error::Repository(format!("No version tag matching '{}' found", tag_format))));
+        let mut outdated: Vec<_> = cargo_content.iter().filter(|(_, (v, _))| *v != target).map(|(f, (v, _))| (f.clone(), v.clone())).collect();
+        outdated.sort();
+        if outdated.is_empty() {
+            finish_step(format!("       {} All versions are already {}", CHECK, target));
+        } else {
+            outdated.iter().for_each(|(f, v)| progress!("{INDENT} - {}: {} -> {}", f.display(), v, target));
+            check_uncommitted_changes(&repo, &git_base_path, cli.allow_dirty, &cli.dirty_ignore, &cargo_tomls, &[])
+                .unwrap_or_else(|e| print_error(e));
+            check_writable(&cargo_tomls, &repo).unwrap_or_else(|e| print_error(e));
+            confirm(&[("Sync to", target.to_string()), ("Files", outdated.len().to_string())], cli.yes).unwrap_or_else(|e| print_error(e));
+
+            let mut transaction = Transaction::new();
+            let result = (|| -> Result<Oid> {
+                let written_files = write_versions(&mut cargo_content, &target, &mut transaction)?;
+                let files: Vec<PathBuf> = cargo_content.keys().cloned().chain(written_files).collect();
+                commit_files(&repo, &git_base_path, &files, &format!("{} '{}' by syncing", COMMIT_MESSAGE_PREFIX, target), false, &mut transaction)
+            })();
+            let oid = finish_transaction(result, transaction, &repo, cli.keep_on_error);
+            progress!("{INDENT}Synced versions committed (id: {}), push the branch to publish them", oid);
+            finish_step(format!("       {} {} done", CHECK, txt));
+        }
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "sync").with("new_version", target.to_string()));
+        }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::ReleaseCurrent) = &cli.change_type {
+        let cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        let mut git_remote = find_git_remote(&repo, cli.remote.clone()).unwrap_or_else(|e| print_error(e));
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        let txt = String::from("Tag current version");
+        start_step(format!("[2/2] {} {} ...", TAG, txt));
+        stats::step("tag");
+        let result = (|| -> Result<_> {
+            // build metadata of --with-sha is not part of the tag
+            let mut versions: Vec<Version> = cargo_content.values().map(|(v, _)| v.without_build()).collect();
+            versions.sort();
+            versions.dedup();
+            let version = match versions.as_slice() {
+                [version] => version.clone(),
+                [] => return Err(Error::Discovery("No cargo.toml found.".to_string())),
+                _ => return Err(Error::Manifest(format!("Versions differ between the cargo.toml files: {}",
+                    versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")))),
+            };
+            let tag = tag_format.tag(&version);
+            if list_version_tags(&repo, &tag_format)?.contains(&tag) {
+                return Err(Error::Conflict(format!("Version {} is already tagged as '{}' -> Aborting", version, tag)));
+            }
+            if fetch_remote_tags(&repo, &mut git_remote)?.contains_key(&tag) {
+                return Err(Error::Conflict(format!("Version {} is already tagged as '{}' on remote '{}' -> Aborting",
+                    version, tag, git_remote.name().unwrap_or(""))));
+            }
+            // the tagged commit has to contain the version
+            check_uncommitted_changes(&repo, &git_base_path, cli.allow_dirty, &cli.dirty_ignore, &cargo_tomls, &[])?;
+            let mut ctx = template::TemplateContext::new();
+            ctx.insert("version", &version).insert("tag", &tag);
+            release_time.add_to(&mut ctx);
+            let tag_message = cli.tag_message.as_deref().ok_or_else(|| Error::Usage("No tag message found.".to_string()))?;
+            let tag_message = template::render(tag_message, &ctx).map_err(|e| Error::Usage(format!("Invalid tag message: {}", e)))?;
+            Ok((version, tag, tag_message))
+        })();
+        let (version, tag, tag_message) = result.unwrap_or_else(|e| print_error(e));
+        let head = repo.head().ok().and_then(|h| h.target()).unwrap_or_else(|| print_error(Error::Repository("HEAD does not point to a commit".into())));
+        check_writable(&[], &repo).unwrap_or_else(|e| print_error(e));
+        confirm(&[("Tag", format!("{} at {:.7}", tag, head.to_string())), ("Remote", git_remote.name().unwrap_or("").to_string())], cli.yes)
+            .unwrap_or_else(|e| print_error(e));
 
-        match repo.find_remote(&git_remote_name) {
-            Ok(r) => r,
-            Err(e) => print_error(format!("Failed to find git remote '{}' with error {}", git_remote_name, e)),
+        let mut transaction = Transaction::new();
+        let tag_ref = format!("refs/tags/{}", tag);
+        let result = (|| -> Result<()> {
+            tag_head(&repo, &tag, &tag_message, false, &mut transaction)?;
+            progress!("{INDENT}pushing to remote '{}': {}", git_remote.name().unwrap_or(""), tag_ref);
+            push_refs(&repo, &mut git_remote, std::slice::from_ref(&tag_ref), vec![(tag_ref.clone(), Oid::zero())])
+        })();
+        finish_transaction(result, transaction, &repo, cli.keep_on_error);
+        finish_step(format!("       {} {} done", CHECK, txt));
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "release-current").with("new_version", version.to_string())
+                .with("tag", tag).with("commit", head.to_string()).with("pushed_refs", vec![tag_ref]));
         }
+        exit_success();
+    }
+
+    if let Some(VersionChangeType::Snapshot { vtype, identifier }) = &cli.change_type {
+        let mut cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        let txt = String::from("Write snapshot version");
+        start_step(format!("[2/2] {} {} ...", PEN, txt));
+        stats::step("snapshot");
+        let result = (|| -> Result<_> {
+            let current_version = cargo_content.values().map(|(v, _)| v.clone()).max()
+                .ok_or_else(|| Error::Discovery("No cargo.toml found.".to_string()))?;
+            let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+            let version = snapshot_version(&current_version.increment_clone(vtype)?, identifier,
+                                           &release_time.date().replace('-', ""), &head.id().to_string()[..7])?;
+            check_writable(&cargo_tomls, &repo)?;
+            // the files are not committed, so there is nothing to roll back on success
+            let mut transaction = Transaction::new();
+            let written = write_version_str(&mut cargo_content, &version, &mut transaction);
+            if written.is_err() { transaction.rollback(&repo); }
+            let files: Vec<PathBuf> = cargo_content.keys().cloned().chain(written?).collect();
+            Ok((version, files))
+        })();
+        let (version, files) = result.unwrap_or_else(|e| print_error(e));
+        finish_step(format!("       {} {} done", CHECK, txt));
+        if is_json_output() {
+            println!("{}", json::document("success").with("command", "snapshot").with("new_version", version.as_str())
+                .with("files_changed", files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>()));
+        } else {
+            println!("{}", version);
+        }
+        exit_success();
+    }
+
+    let tag_message = match cli.tag_message {
+        Some(s) => s, None => { print_error(Error::Usage("No tag message found.".to_string())); }
     };
-    println!("{INDENT}Found remote to be used: {}", git_remote.name().unwrap());
 
-    let mut cb = git2::RemoteCallbacks::new();
-    let git_config = repo.config().unwrap();
-    let mut ch = CredentialHandler::new(git_config);
-    cb.credentials(move |url, username, allowed| ch.try_next_credential(url, username, allowed));
-    let mut po = git2::PushOptions::new();
-    po.remote_callbacks(cb);
+    let release_branch_template = cli.release_branch.clone().or_else(|| cli.create_pr.then(|| "release/{{ tag }}".to_string()));
+    let allowed_branches = if cli.allowed_branches.is_empty() { &config.allowed_branches } else { &cli.allowed_branches };
+    let detached = repo.head_detached().unwrap_or(false);
+    let release_branch = release_branch(&repo, cli.branch.as_deref()).unwrap_or_else(|e| print_error(e));
+    // the branch on the remote only differs from the release branch with --push-upstream
+    let (remote_name, remote_branch) = match (&release_branch, cli.push_upstream) {
+        (_, false) => (cli.remote.clone(), release_branch.clone()),
+        (None, true) => print_error(Error::Usage("HEAD is detached, so there is no upstream to push to - use --branch or --remote".to_string())),
+        (Some(branch), true) => {
+            let (remote, merge) = branch_upstream(&repo, branch).unwrap_or_else(|e| print_error(e));
+            progress!("{INDENT}Upstream of branch '{}': {}/{}", branch, remote, merge);
+            (Some(remote), Some(merge))
+        },
+    };
+    let mut git_remote = find_git_remote(&repo, remote_name).unwrap_or_else(|e| print_error(e));
+    progress!("{INDENT}Found remote to be used: {}", git_remote.name().unwrap_or(""));
+    let push = match (&release_branch, if cli.push_tags_only { PushSelection::Tags } else { cli.push }) {
+        _ if release_branch_template.is_some() => PushSelection::Branch,
+        (None, PushSelection::Branch) => print_error(Error::Usage(
+            "HEAD is detached (e.g. a CI checkout), so there is no branch to push - use --branch <name>".to_string())),
+        (None, PushSelection::Both) => {
+            print_warn("HEAD is detached (e.g. a CI checkout), only the tag is pushed - use --branch <name> to push the release commit to a branch".to_string());
+            PushSelection::Tags
+        },
+        (_, push) => push,
+    };
+    check_allowed_branch(release_branch.as_deref(), allowed_branches).unwrap_or_else(|e| print_error(e));
+    let remote_branch_oid = match &remote_branch {
+        Some(branch) => check_up_to_date(&repo, &mut git_remote, branch, cli.allow_behind, cli.force_with_lease)
+            .unwrap_or_else(|e| print_error(e)),
+        None => None,
+    };
+    // a tag already pushed by someone else would only be rejected after the release commit was made
+    let remote_tags = if push == PushSelection::Branch && release_branch_template.is_none() { HashMap::new() } else {
+        fetch_remote_tags(&repo, &mut git_remote).unwrap_or_else(|e| print_error(e))
+    };
 
-    println!("       {} {} done", CHECK, txt);
+    finish_step(format!("       {} {} done", CHECK, txt));
 
     // ***
     let txt = String::from("Writing version to cargo.toml(s)");
-    println!("[2/5] {} {} ...", PEN, txt);
+    start_step(format!("[2/5] {} {} ...", PEN, txt));
+    stats::step("write");
 
-    let mut cargo_content = read_version_tomls(&cargo_tomls);
+    let mut cargo_content = read_version_tomls(&cargo_tomls).unwrap_or_else(|e| print_error(e));
 
     // Check if version
     if cargo_content.len() > 1 && cli.cargo_file_selector.is_none() {
-        print_error("More than one cargo.toml found but option cargo_file_selector not given".to_string());
-    }
-
-    let new_version = match &cli.change_type {
-        VersionChangeType::Increment{ vtype } => {
-            // test if all versions are equal (should work also with one cargo.toml
-            let (version_to_test_against, _) = cargo_content.get(cargo_content.keys().next().unwrap()).unwrap();
-
-            let all_versions_equal = cargo_content.iter().fold(true, |acc, (_, (cv, _))| {
-                let e = version_to_test_against == cv;
-                acc && e });
-            if !all_versions_equal {
-                match cli.cargo_file_selector.unwrap() {
-                    CargoFile::All => { print_error(
-                        "When using increment and updating all cargo-toml files, the versions have to be equal in all files. Use fixed in this case ...".to_string()); },
-                    _ => ()
-                }
-            }
-            version_to_test_against.increment_clone(vtype)
+        print_error(Error::Usage("More than one cargo.toml found but option cargo_file_selector not given".to_string()));
+    }
+    if cli.commit_per_crate && !matches!(cli.cargo_file_selector, Some(CargoFile::All | CargoFile::Workspace)) {
+        print_error(Error::Usage("--commit-per-crate requires --cargo-file-selector all or workspace".to_string()));
+    }
+
+    let tag_base = (cli.increment_source == IncrementSource::Tag).then(|| {
+        let base = tag_base_version(&repo, &tag_format, &cargo_content).unwrap_or_else(|e| print_error(e));
+        progress!("{INDENT}Incrementing from latest version tag: {}", base);
+        base
+    });
+    let bump = match &cli.change_type {
+        None => {
+            let current = tag_base.clone().or_else(|| cargo_content.values().next().map(|(v, _)| v.clone())).unwrap_or_else(|| {
+                print_error(Error::Discovery("No cargo.toml found.".to_string())) });
+            pick_version_bump(&current).unwrap_or_else(|e| print_error(e))
         },
-        VersionChangeType::Fixed { full_version } => {
-            match Version::try_from(full_version.clone()) {
-                Ok(version) => version,
-                Err(_) => { print_error(format!("Wrong format for version specifier '{}'.", full_version)) }
-            }
-        }
-        VersionChangeType::OnlyShow => {
-            print_error("Not yet implemented!!!".to_string());
+        Some(VersionChangeType::Fixed { full_version }) => VersionBump::Fixed { full_version: full_version.clone() },
+        Some(VersionChangeType::Increment { vtype: IncrementSelector::Part(vtype) }) => VersionBump::Increment { vtype: vtype.clone() },
+        Some(VersionChangeType::Increment { vtype: IncrementSelector::FromCommit }) => {
+            let vtype = notes::head_release_directive(&repo).unwrap_or_else(|e| print_error(e)).unwrap_or_else(|| {
+                print_error(Error::Usage("No directive like '[release: minor]' found in the HEAD commit message".to_string())) });
+            progress!("{INDENT}Release directive of HEAD commit: {}", vtype);
+            VersionBump::Increment { vtype }
+        },
+        Some(VersionChangeType::Auto) => {
+            let last_tag = latest_version_tag(&repo, &tag_format).unwrap_or_else(|e| print_error(e)).map(|(tn, _)| tn);
+            let commits = notes::commits_since(&repo, last_tag.as_deref()).unwrap_or_else(|e| print_error(e));
+            let vtype = notes::bump_from_commits(&commits).unwrap_or_else(|| print_error(Error::Repository(format!(
+                "No feature, fix or breaking change commit since {} -> nothing to release", last_tag.as_deref().unwrap_or("start")))));
+            progress!("{INDENT}{} commit(s) since {} require a {} release", commits.len(), last_tag.as_deref().unwrap_or("start"), vtype);
+            VersionBump::Increment { vtype }
+        },
+        Some(VersionChangeType::Promote) => {
+            let version = promote::promoted_version(&repo, &tag_format, &cargo_content).unwrap_or_else(|e| print_error(e));
+            VersionBump::Fixed { full_version: version.to_string() }
+        },
+        _ => {
+            print_error(Error::Usage("Not yet implemented!!!".to_string()));
         }
     };
+    let new_version = compute_new_version(&cargo_content, &bump, &cli.cargo_file_selector, tag_base.as_ref())
+        .unwrap_or_else(|e| print_error(e));
+    // catches copy-paste mistakes like a fixed version of the previous release
+    if matches!(bump, VersionBump::Fixed { .. }) && !cli.allow_downgrade
+        && let Some((f, (v, _))) = cargo_content.iter().find(|(_, (v, _))| v.without_build() >= new_version.without_build()) {
+        print_error(Error::Usage(format!("Fixed version {} is not higher than the current version {} of '{}' (use --allow-downgrade to set it anyway)",
+            new_version, v, f.display())));
+    }
+
+    progress!("{INDENT}New version to be written: {}", new_version);
 
-    println!("{INDENT}New version to be written: {}", new_version.to_string());
+    // the automatic bump and the changelog rely on the commit messages
+    if cli.lint_commits || config.lint.enabled {
+        (|| -> Result<()> {
+            let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+            let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+            let offending = notes::lint_commits(&commits, config.lint.pattern.as_deref())?;
+            if !offending.is_empty() {
+                let format = config.lint.pattern.as_ref().map(|p| format!("the pattern '{}'", p)).unwrap_or("the conventional commit format".to_string());
+                return Err(Error::Check(format!("{} commit(s) since {} do not follow {}:\n{}", offending.len(),
+                    last_tag.as_deref().unwrap_or("start"), format, offending.iter()
+                        .map(|c| format!("{INDENT}- {} {}", c.short_id(), c.message.lines().next().unwrap_or(""))).collect::<Vec<_>>().join("\n"))));
+            }
+            progress!("{INDENT}All {} commit(s) since {} follow {}", commits.len(), last_tag.as_deref().unwrap_or("start"),
+                      if config.lint.pattern.is_some() { "the lint pattern" } else { "the conventional commit format" });
+            Ok(())
+        })().unwrap_or_else(|e| print_error(e));
+    }
 
     // ****************************************
-    let mut opts = StatusOptions::new();
-    opts.include_untracked(false);
-    let mut so = StatusOptions::new();
-    so.include_untracked(false); so.exclude_submodules(true);
-    so.recurse_ignored_dirs(false);
-    // repo.statuses(Some(&mut so)).unwrap().iter().for_each(|x| {
-    //     println!("file: {:?} -> status: {:?}", x.path(), x.status())
-    // });
-    let change_count = repo.statuses(Some(&mut so)).unwrap().iter().count();
-    if change_count > 0 {
-        print_error(format!("There are {} uncommitted changes - please commit before continuing.", change_count));
-    }
-
-    let git_tag_prefix = cli.git_prefix_for_tag.unwrap_or("v".to_string());
-    let git_tag_new_version_str = format!("{git_tag_prefix}{}", new_version.to_string());
-    let tns = repo.tag_names(Some(format!("{git_tag_prefix}*").as_str())).unwrap()
-        .into_iter().filter_map(|ct| { match ct {
-            None => None,
-            Some(s) => Some(String::from(s))
-        } }).collect::<Vec<_>>();
+    let changelog_file = cli.changelog.then(|| git_base_path.join(&cli.changelog_file));
+    let release_info_file = cli.release_info.then(|| git_base_path.join(&cli.release_info_file));
+    let readme_file = cli.update_readme.then(|| git_base_path.join(&cli.readme_file));
+    let version_sinks: Vec<config::Sink> = config.sinks.iter().cloned().chain(cli.write_version_file.then(|| config::Sink {
+        kind: sinks::SinkKind::Plain, file: cli.version_file.to_string_lossy().to_string() })).collect();
+    let mut config_files: Vec<PathBuf> = config.replacements.iter().map(|r| &r.file).chain(version_sinks.iter().map(|s| &s.file))
+        .map(|f| git_base_path.join(f).components().collect()).collect();
+    config_files.sort();
+    config_files.dedup();
+    let written: Vec<PathBuf> = cargo_tomls.iter().flat_map(|f| [f.clone(), f.with_file_name("Cargo.lock")])
+        .chain(changelog_file.iter().cloned()).chain(release_info_file.iter().cloned()).chain(readme_file.iter().cloned())
+        .chain(config_files.iter().cloned()).collect();
+    let extra_files: Vec<PathBuf> = cli.extra_file.iter().map(|f| git_base_path.join(f).components().collect()).collect();
+    if cli.autostash { check_written_files_unchanged(&repo, &git_base_path, &[written, extra_files.clone()].concat(), "--autostash") }
+    else { check_uncommitted_changes(&repo, &git_base_path, cli.allow_dirty, &cli.dirty_ignore, &written, &extra_files) }
+        .unwrap_or_else(|e| print_error(e));
+
+    let git_tag_new_version_str = tag_format.tag(&new_version);
+    let tns = list_version_tags(&repo, &tag_format).unwrap_or_else(|e| print_error(e));
     if tns.contains(&git_tag_new_version_str) {
-        print_error(format!("New version already exists as git tag '{}' -> Aborting", git_tag_new_version_str));
-    }
-
-    //
-    let cargo_locks: Vec<_> = cargo_content.iter_mut().filter_map(|(fname, (_, toml))| {
-        let cargo_prj_name = toml["package"]["name"].to_string();
-        toml["package"]["version"] = value(new_version.to_string());
-        // println!("file: {}\ntoml: {}", fname.display(), toml.to_string());
-        if let Err(e) = write(fname, toml.to_string()) {
-            print_error(format!("Failed to write to '{}': {}", fname.display(), e));
-        }
-        if cli.verbose > 0 { println!("{INDENT}Updated cargo.toml: {}", fname.display()); }
-
-        // adjust version in lock file
-        let lock_file = fname.parent().unwrap();
-        let lock_file = lock_file.join(Path::new("Cargo.lock"));
-        if lock_file.exists() {
-            let cct_content = match fs::read(lock_file.clone()) {
-                Ok(content) => String::from_utf8(content).unwrap(),
-                Err(e) => { print_error(format!("Could not read lock file '{}': {:?}", lock_file.display(), e)); }
-            };
-            let mut toml_lock = match cct_content.parse::<DocumentMut>() {
-                Ok(v) => v, Err(e) => {
-                    print_error(format!("Could not parse toml from lock file '{}': {:?}", lock_file.display(), e)); }
-            };
+        if !cli.force_tag {
+            print_error(Error::Conflict(format!("New version already exists as git tag '{}' -> Aborting (use --force-tag to replace it)",
+                git_tag_new_version_str)));
+        }
+        print_warn(format!("Existing git tag '{}' is replaced (--force-tag)", git_tag_new_version_str));
+    }
+    let newer_tag = tns.iter().chain(remote_tags.keys())
+        .filter_map(|tn| Version::try_from(tag_format.version_part(tn)?.to_string()).ok().map(|v| (tn, v)))
+        .filter(|(_, v)| v.without_build() > new_version.without_build()).max_by(|(_, a), (_, b)| a.cmp(b));
+    if let Some((tn, v)) = newer_tag {
+        let msg = format!("Tag '{}' of the higher version {} exists already, is the branch stale?", tn, v);
+        if cli.require_newest { print_error(Error::Conflict(format!("{} -> Aborting (--require-newest)", msg))); }
+        print_warn(msg);
+    }
+    // the lease only lets the forced push replace the remote tag as it was fetched
+    let remote_tag = remote_tags.get(&git_tag_new_version_str).copied();
+    if remote_tag.is_some() {
+        if !cli.force_tag {
+            print_error(Error::Conflict(format!("New version already exists as git tag '{}' on remote '{}' -> Aborting (use --force-tag to replace it)",
+                git_tag_new_version_str, git_remote.name().unwrap_or(""))));
+        }
+        print_warn(format!("Git tag '{}' on remote '{}' is replaced (--force-tag)", git_tag_new_version_str, git_remote.name().unwrap_or("")));
+    }
+    // the amended commit is replaced, so it must neither be pushed nor released already
+    let amended_head = cli.amend.then(|| {
+        let head = repo.head().ok().and_then(|h| h.target())
+            .unwrap_or_else(|| print_error(Error::Repository("HEAD does not point to a commit".into())));
+        if let Some(remote_oid) = remote_branch_oid && !cli.force_with_lease
+            && (remote_oid == head || repo.graph_descendant_of(remote_oid, head).unwrap_or(false)) {
+            print_error(Error::Repository(format!(
+                "HEAD {} was already pushed, amending it would rewrite published history - use --force-with-lease", head)));
+        }
+        let tag_commit = |tn: &str| repo.revparse_single(&format!("refs/tags/{}", tn)).and_then(|o| o.peel_to_commit()).map(|c| c.id()).ok();
+        if let Some(tn) = tns.iter().find(|tn| tag_commit(tn) == Some(head)) {
+            print_error(Error::Repository(format!("HEAD is tagged as '{}', amending it would leave the tag behind", tn)));
+        }
+        head
+    });
 
-            if let Some(package_sections) = toml_lock["package"].as_array_of_tables_mut() {
-                let mut changed_lock = false;
-                package_sections.iter_mut().for_each(|cps| {
-                    if cps["name"].to_string() == cargo_prj_name {
-                        cps["version"] = value(new_version.to_string());
-                        changed_lock = true;
-                    }
-                });
-                if changed_lock {
-                    if let Err(e) = write(lock_file.clone(), toml_lock.to_string()) {
-                        print_error(format!("Failed to write to lock '{}': {}", lock_file.display(), e));
-                    }
-                    if cli.verbose > 0 { println!("{INDENT}  and respective cargo.lock: {}", lock_file.display()); }
-                    Some(lock_file)
-                }
-                else { None }
+    if cli.build_number == Some(BuildNumberSource::Commits) && cli.commit_per_crate {
+        print_error(Error::Usage("--build-number commits counts a single release commit, it cannot be used with --commit-per-crate".to_string()));
+    }
+    let build = cli.build_number.map(|source| build_number::next_build_number(source, &repo, &cargo_content, cli.amend)
+        .unwrap_or_else(|e| print_error(e)));
+
+    let mut nested = if !cli.submodules { vec![] } else {
+        submodules::plan_nested_releases(&repo, &bump, &tag_format).unwrap_or_else(|e| print_error(e))
+    };
+    // commit and tag message of each submodule
+    let nested_messages: Vec<(String, String)> = nested.iter().map(|n| {
+        let mut ctx = template::TemplateContext::new();
+        ctx.insert("version", &n.new_version).insert("tag", &n.tag);
+        release_time.add_to(&mut ctx);
+        let tag_message = template::render(&tag_message, &ctx).unwrap_or_else(|e| print_error(Error::Usage(format!("Invalid tag message: {}", e))));
+        (commit_message(&bump, &n.new_version), tag_message)
+    }).collect();
+
+    let mut tag_ctx = template::TemplateContext::new();
+    tag_ctx.insert("version", &new_version).insert("tag", &git_tag_new_version_str);
+    if let Some(build) = build { tag_ctx.insert("build", build); }
+    release_time.add_to(&mut tag_ctx);
+    let tag_message = template::render(&tag_message, &tag_ctx)
+        .unwrap_or_else(|e| print_error(Error::Usage(format!("Invalid tag message: {}", e))));
+    // the release commit is merged through the new branch and tagged afterwards
+    let bump_branch = release_branch_template.as_ref().map(|b| template::render(b, &tag_ctx)
+        .unwrap_or_else(|e| print_error(Error::Usage(format!("Invalid release branch: {}", e)))));
+    if let Some(branch) = &bump_branch {
+        if !git2::Branch::name_is_valid(branch).unwrap_or(false) {
+            print_error(Error::Usage(format!("Invalid release branch name '{}'", branch)));
+        }
+        if repo.find_branch(branch, git2::BranchType::Local).is_ok() {
+            print_error(Error::Conflict(format!("Release branch '{}' already exists", branch)));
+        }
+    }
+    let tag_message = if !cli.notes_in_tag || bump_branch.is_some() { tag_message } else {
+        let notes = (|| -> Result<String> {
+            let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+            let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+            progress!("{INDENT}Release notes of {} commit(s) since {} added to the tag message", commits.len(), last_tag.as_deref().unwrap_or("start"));
+            render_release_notes(&cli.notes_template, &new_version, &git_tag_new_version_str, last_tag.as_deref(), &commits, &release_time)
+        })().unwrap_or_else(|e| print_error(e));
+        format!("{}\n\n{}\n", tag_message.trim_end(), notes.trim_end())
+    };
+
+    let commit_message = commit_message(&bump, &new_version);
+    let crate_commit_message = |name: &str| match &bump {
+        VersionBump::Fixed { .. } => format!("{COMMIT_MESSAGE_PREFIX} fixed version '{}' for {}", new_version, name),
+        VersionBump::Increment { vtype } => format!("{COMMIT_MESSAGE_PREFIX} '{}' for {} by incrementing {}", new_version, name, vtype),
+    };
+
+    let old_version = cargo_content.values().next().map(|(v, _)| v.clone()).unwrap_or_else(|| new_version.clone());
+    // All selected cargo.tomls get the new version, the crates are listed in the tag and the release info
+    let mut released_crates: Vec<(String, Version)> = cargo_content.values()
+        .filter_map(|(_, toml)| toml.get("package")?.get("name")?.as_str().map(|n| (n.to_string(), new_version.clone())))
+        .collect();
+    released_crates.sort();
+
+    let gitlab_release = cli.gitlab_release.then(|| -> Result<_> {
+        if push == PushSelection::Branch {
+            return Err(Error::Usage("--gitlab-release requires the tag to be pushed (--push tags or both)".to_string()));
+        }
+        let remote_url = git_remote.url().unwrap_or("");
+        let project = gitlab::Project::from_remote_url(remote_url).ok_or_else(|| Error::Usage(
+            format!("Could not derive the GitLab project from remote url '{}'", remote_url)))?;
+        let token = gitlab::find_token(|k| std::env::var(k).ok()).ok_or_else(|| Error::Usage(
+            "--gitlab-release requires GITLAB_TOKEN or CI_JOB_TOKEN to be set".to_string()))?;
+        let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+        let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+        let notes = render_release_notes(&cli.notes_template, &new_version, &git_tag_new_version_str, last_tag.as_deref(),
+                                          &commits, &release_time)?;
+        Ok((project, token, notes))
+    }).transpose().unwrap_or_else(|e| print_error(e));
+
+    let pull_request = cli.create_pr.then(|| -> Result<_> {
+        let target = release_branch.clone().ok_or_else(|| Error::Usage(
+            "HEAD is detached, so there is no branch to open the pull request into".to_string()))?;
+        let remote_url = git_remote.url().unwrap_or("");
+        let project = gitlab::Project::from_remote_url(remote_url).ok_or_else(|| Error::Usage(
+            format!("Could not derive the project from remote url '{}' for --create-pr", remote_url)))?;
+        let forge = pull_request::Forge::detect(&project, |k| std::env::var(k).ok());
+        let token = forge.find_token(|k| std::env::var(k).ok()).ok_or_else(|| Error::Usage(match forge {
+            pull_request::Forge::GitHub => "--create-pr requires GITHUB_TOKEN or GH_TOKEN to be set",
+            pull_request::Forge::GitLab => "--create-pr requires GITLAB_TOKEN or CI_JOB_TOKEN to be set",
+        }.to_string()))?;
+        let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+        let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+        let notes = render_release_notes(&cli.notes_template, &new_version, &git_tag_new_version_str, last_tag.as_deref(),
+                                          &commits, &release_time)?;
+        Ok((forge, project, token, target, notes))
+    }).transpose().unwrap_or_else(|e| print_error(e));
+
+    // rendered up front, so an invalid template fails before anything is modified. With a release
+    // branch the tag does not exist yet, so there is nothing to announce
+    let notifications = (!config.notify.is_empty() && bump_branch.is_none()).then(|| -> Result<Vec<_>> {
+        let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+        let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+        let mut ctx = notes::release_context(&new_version, &git_tag_new_version_str, last_tag.as_deref(), &commits);
+        release_time.add_to(&mut ctx);
+        ctx.insert("notes", render_release_notes(&cli.notes_template, &new_version, &git_tag_new_version_str, last_tag.as_deref(),
+                                                 &commits, &release_time)?);
+        config.notify.iter().map(|n| Ok((n, notify::render_payload(n, &ctx)?))).collect()
+    }).transpose().unwrap_or_else(|e| print_error(e)).unwrap_or_default();
+
+    // the release commit cannot contain its own id, so the metadata is the id of the commit it is based on
+    let cargo_version = if !cli.with_sha { new_version.to_string() } else {
+        let head = repo.head().and_then(|h| h.peel_to_commit()).unwrap_or_else(|e| print_error(Error::git("Could not resolve HEAD", e)));
+        format!("{}+g{:.7}", new_version.without_build(), head.id().to_string())
+    };
+
+    // Preflight, so a read-only checkout is reported before anything is modified
+    let lock_files: Vec<PathBuf> = cargo_content.keys().map(|f| f.with_file_name("Cargo.lock")).filter(|f| f.exists()).collect();
+    let files_to_write: Vec<PathBuf> = cargo_content.keys().chain(&lock_files).chain(changelog_file.iter())
+        .chain(release_info_file.iter()).chain(readme_file.iter()).chain(&config_files).cloned().collect();
+    check_writable(&files_to_write, &repo).unwrap_or_else(|e| print_error(e));
+
+    let mut files: Vec<_> = cargo_content.keys().chain(changelog_file.iter()).chain(release_info_file.iter()).chain(readme_file.iter())
+        .chain(&config_files).chain(&extra_files).map(|f| f.display().to_string()).collect();
+    files.sort();
+    let mut summary = vec![("Version", format!("{} -> {}", old_version, new_version)), ("Files", files.join(", ")),
+                           ("Tag", git_tag_new_version_str.clone()), ("Remote", git_remote.name().unwrap_or("").to_string())];
+    if cli.with_sha { summary.push(("Cargo.toml", cargo_version.clone())); }
+    if let Some(build) = build { summary.push(("Build", build.to_string())); }
+    if let Some(head) = amended_head { summary.push(("Commit", format!("amend HEAD {}", head))); }
+    if let Some(branch) = &bump_branch { summary.push(("Branch", format!("{} (new, the tag is created after the merge)", branch))); }
+    if cli.commit_per_crate { summary.push(("Commit", format!("one per crate ({})", cargo_content.len()))); }
+    if let Some((project, _, _)) = &gitlab_release {
+        summary.push(("GitLab release", format!("{} on {}", project.path, project.host)));
+    }
+    if let (Some((forge, project, _, target, _)), Some(branch)) = (&pull_request, &bump_branch) {
+        summary.push(("Pull request", format!("{} {} -> {} on {}", forge.request_name(), branch, target, project.host)));
+    }
+    if !notifications.is_empty() {
+        summary.push(("Notify", notifications.iter().map(|(n, _)| n.url.as_str()).collect::<Vec<_>>().join(", ")));
+    }
+    for n in &nested {
+        summary.push(("Submodule", format!("{} {} -> {} (tag {})", n.path.display(), n.old_version, n.new_version, n.tag)));
+    }
+    confirm(&summary, cli.yes).unwrap_or_else(|e| print_error(e));
+
+    // From here on every modification is recorded, so it can be rolled back if a later step fails
+    let mut transaction = Transaction::new();
+    let stash = cli.autostash.then(|| stash_changes(&repo, &format!("cgvs autostash before {}", git_tag_new_version_str))
+        .unwrap_or_else(|e| print_error(e))).flatten();
+    if let Some(stash) = stash {
+        progress!("{INDENT}Stashed uncommitted changes ({})", stash);
+        transaction.record_stash(stash);
+    }
+    let hook_env = hooks::HookEnv { old_version: &old_version, new_version: &new_version, tag: &git_tag_new_version_str };
+    let changed_before = hooks::changed_files(&repo).unwrap_or_else(|e| print_error(e));
+    let result = (|| -> Result<(Vec<PathBuf>, Oid, Vec<String>)> {
+        let mut hook_files = hooks::run_hook_in_transaction(&repo, &git_base_path, "pre_bump", config.hooks.pre_bump.as_deref(),
+                                                            &hook_env, &changed_before, &mut transaction)?;
+        let mut nested_paths = vec![];
+        for (n, (nested_commit_message, nested_tag_message)) in nested.iter_mut().zip(&nested_messages) {
+            let oid = submodules::release_nested(n, nested_commit_message, nested_tag_message)?;
+            progress!("{INDENT}Submodule '{}' released as {} (commit {})", n.path.display(), n.tag, oid);
+            nested_paths.push(git_base_path.join(&n.path));
+        }
+        if let (Some(BuildNumberSource::Metadata), Some(build)) = (cli.build_number, build) {
+            cargo_content.values_mut().for_each(|(_, toml)| build_number::set_metadata_build(toml, build));
+        }
+        let mut written_files = write_version_str(&mut cargo_content, &cargo_version, &mut transaction)?;
+        written_files.extend(nested_paths);
+        for file in sinks::write_sinks(&git_base_path, &version_sinks, &new_version, &mut transaction)? {
+            progress!("{INDENT}Wrote version to '{}'", file.display());
+            written_files.push(file);
+        }
+        for file in replacements::apply_replacements(&git_base_path, &config.replacements, &old_version, &new_version,
+                                                     &git_tag_new_version_str, &mut transaction)? {
+            progress!("{INDENT}Applied replacements to '{}'", file.display());
+            written_files.push(file);
+        }
+        if let Some(readme_file) = &readme_file {
+            let names: Vec<String> = released_crates.iter().map(|(n, _)| n.clone()).collect();
+            if replacements::update_readme(readme_file, &names, &new_version, &mut transaction)? {
+                progress!("{INDENT}Updated crate versions in '{}'", readme_file.display());
+                written_files.push(readme_file.clone());
+            } else {
+                print_warn(format!("No version of {} found in '{}' (--update-readme)", names.join(", "), readme_file.display()));
             }
-            else {
-                print_warn( format!("Unexpected format in lock file content of {}", lock_file.display()));
-                None
+        }
+        if let Some(changelog_file) = &changelog_file {
+            let last_tag = latest_version_tag(&repo, &tag_format)?.map(|(tn, _)| tn);
+            let commits = notes::commits_since(&repo, last_tag.as_deref())?;
+            let section = render_release_notes(&cli.notes_template, &new_version, &git_tag_new_version_str, last_tag.as_deref(),
+                                          &commits, &release_time)?;
+            changelog::update_changelog(changelog_file, &section, &mut transaction)?;
+            progress!("{INDENT}Added {} commit(s) to changelog '{}'", commits.len(), changelog_file.display());
+            written_files.push(changelog_file.clone());
+        }
+        if let Some(release_info_file) = &release_info_file {
+            let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+            release_info::write_release_info(release_info_file, &new_version, &git_tag_new_version_str, head.id(),
+                                             &release_time.datetime(), &released_crates, &mut transaction)?;
+            progress!("{INDENT}Wrote release info '{}'", release_info_file.display());
+            written_files.push(release_info_file.clone());
+        }
+
+        hook_files.extend(hooks::run_hook_in_transaction(&repo, &git_base_path, "pre_commit", config.hooks.pre_commit.as_deref(),
+                                                         &hook_env, &changed_before, &mut transaction)?);
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        // ***
+        let txt = String::from("git commit for cargo.toml(s)");
+        start_step(format!("[3/5] {} {} ...", CLIP, txt));
+        stats::step("commit");
+
+        if let Some(branch) = &bump_branch {
+            checkout_new_branch(&repo, branch, &mut transaction)?;
+            progress!("{INDENT}Created and checked out branch '{}'", branch);
+        }
+
+        let mut files: Vec<PathBuf> = cargo_content.keys().cloned().chain(written_files).collect();
+        // extra files are committed even if the [commit] config does not cover them
+        for extra_file in &extra_files {
+            if !extra_file.exists() {
+                return Err(Error::Usage(format!("Extra file '{}' does not exist", extra_file.display())));
             }
+            if !files.contains(extra_file) { files.push(extra_file.clone()); }
+        }
+        hook_files.retain(|f| !files.contains(f));
+        let (hook_files, skipped): (Vec<_>, Vec<_>) = hook_files.into_iter().partition(|f| {
+            f.strip_prefix(&git_base_path).is_ok_and(|p| config.commit.is_eligible(&p.to_string_lossy()))
+        });
+        skipped.iter().for_each(|f| print_warn(format!("'{}' changed by a hook is not committed (see [commit] in the config)", f.display())));
+        files.extend(hook_files);
+        // with one commit per crate the release spans all commits since the current HEAD
+        let release_base = if cli.commit_per_crate { repo.head().ok().and_then(|h| h.target()) } else { amended_head };
+        let oid = if cli.commit_per_crate {
+            let mut remaining = files.clone();
+            let mut crates: Vec<_> = cargo_content.iter().collect();
+            crates.sort_by(|a, b| a.0.cmp(b.0));
+            for (cargo_toml, (_, toml)) in crates {
+                let name = toml.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()).map(str::to_string)
+                    .unwrap_or_else(|| diff_paths(cargo_toml, &git_base_path).unwrap_or_default().display().to_string());
+                let oid = commit_files(&repo, &git_base_path, std::slice::from_ref(cargo_toml), &crate_commit_message(&name), false, &mut transaction)?;
+                progress!("{INDENT}'{}' comitted (id: {})", name, oid);
+                remaining.retain(|f| f != cargo_toml);
+            }
+            if remaining.is_empty() { repo.head().ok().and_then(|h| h.target()).ok_or(Error::Repository("HEAD does not point to a commit".into()))? }
+            else { commit_files(&repo, &git_base_path, &remaining, &commit_message, false, &mut transaction)? }
+        }
+        else { commit_files(&repo, &git_base_path, &files, &commit_message, cli.amend, &mut transaction)? };
+        if cli.commit_per_crate { progress!("{INDENT}Cargo.tomls with updated version comitted per crate (HEAD: {})", oid); }
+        else if cli.amend { progress!("{INDENT}Cargo.tomls with updated version amended to HEAD (id: {})", oid); }
+        else { progress!("{INDENT}Cargo.tomls with updated version comitted (id: {})", oid); }
+        if let Some(branch) = release_branch.as_ref().filter(|_| detached) {
+            let ref_name = format!("refs/heads/{}", branch);
+            let previous = repo.refname_to_id(&ref_name).ok();
+            repo.reference(&ref_name, oid, true, "cgvs: release commit")
+                .map_err(|e| Error::git(format!("Could not update branch '{}'", branch), e))?;
+            transaction.record_reference(&ref_name, previous);
+            progress!("{INDENT}Branch '{}' set to the release commit", branch);
         }
-        else { None }
-    }).collect();
 
-    println!("       {} {} done", CHECK, txt);
+        finish_step(format!("       {} {} done", CHECK, txt));
 
-    // ***
-    let txt = String::from("git commit for cargo.toml(s)");
-    println!("[3/5] {} {} ...", CLIP, txt);
-
-    // https://users.rust-lang.org/t/how-can-i-do-git-add-some-file-rs-git-commit-m-message-git-push-with-git2-crate-on-a-bare-repo/94109/3
-    // open the index database of the given repository
-    // the repo can't be bare, must have a worktree
-    let mut index = repo.index().unwrap();
-    // suppose you made some change to "hello.txt", add it to the index
-    cargo_content.keys().into_iter().for_each(|fname| {
-        let fname_repo_rel = diff_paths(fname.as_path(), git_base_path.as_path()).unwrap();
-        // println!("rel file to commit: {}", fname_repo_rel.display());
-        index.add_path(fname_repo_rel.as_path()).unwrap();
+        // ***
+        let txt = String::from("Add git tag for version");
+        start_step(format!("[4/5] {} {} ...", TAG, txt));
+        stats::step("tag");
+
+        if let Some(branch) = &bump_branch {
+            progress!("{INDENT}Tag '{}' is left for after the merge of branch '{}'", git_tag_new_version_str, branch);
+        } else {
+            let tag_message = release_info::annotate_release_set(&tag_message, &released_crates);
+            tag_head(&repo, &git_tag_new_version_str, &tag_message, cli.force_tag, &mut transaction)?;
+        }
+        finish_step(format!("       {} {} done", CHECK, txt));
+
+        // ***
+        let txt = String::from("git push for cargo.toml(s) and tag");
+        start_step(format!("[5/5] {} {} ...", TRUCK, txt));
+        stats::step("push");
+
+        let expected: Vec<PathBuf> = files.iter().filter_map(|f| diff_paths(f, &git_base_path)).collect();
+        check::verify_release_commit(&repo, oid, release_base, &expected, cli.max_blob_size)?;
+        progress!("{INDENT}Release commit only contains the expected files");
+
+        if let Some(command) = &config.hooks.pre_push { hooks::run_hook("pre_push", command, &git_base_path, &hook_env)?; }
+
+        let branch_ref_name = match (&release_branch, &remote_branch) {
+            (Some(local), Some(remote)) if local != remote => format!("refs/heads/{}:refs/heads/{}", local, remote),
+            _ => format!("refs/heads/{}", release_branch.as_deref().unwrap_or_default()),
+        };
+        let mut leases = vec![];
+        if cli.force_with_lease && push != PushSelection::Tags {
+            // a zero oid lets the push fail if the branch was created on the remote meanwhile
+            leases.push((format!("refs/heads/{}", remote_branch.as_deref().unwrap_or_default()), remote_branch_oid.unwrap_or(Oid::zero())));
+        }
+        let branch_ref_name = if leases.is_empty() { branch_ref_name } else { format!("+{}", branch_ref_name) };
+        let tag_ref = format!("refs/tags/{}", git_tag_new_version_str);
+        let tag_refspec = match remote_tag {
+            Some(oid) if push != PushSelection::Branch => { leases.push((tag_ref.clone(), oid)); format!("+{}", tag_ref) },
+            _ => tag_ref,
+        };
+        let refs_to_push = match push {
+            _ if let Some(branch) = &bump_branch => vec![format!("refs/heads/{}", branch)],
+            PushSelection::Branch => vec![branch_ref_name],
+            PushSelection::Tags => vec![tag_refspec],
+            PushSelection::Both => vec![branch_ref_name, tag_refspec],
+        };
+        // the submodule commits have to be on their remotes before the superproject refers to them
+        for n in &nested {
+            let refs = submodules::push_nested(n)?;
+            progress!("{INDENT}pushed submodule '{}': {}", n.path.display(), refs.join(", "));
+        }
+        progress!("{INDENT}pushing to remote '{}': {}", git_remote.name().unwrap_or(""), refs_to_push.join(", "));
+        push_refs(&repo, &mut git_remote, &refs_to_push, leases)?;
+
+        finish_step(format!("       {} {} done", CHECK, txt));
+        Ok((files, oid, refs_to_push))
+    })();
+
+    if result.is_err() && !cli.keep_on_error {
+        for submodules::NestedRelease { path, repo, transaction, .. } in nested.into_iter().rev() {
+            let errors = transaction.rollback(&repo);
+            errors.iter().for_each(|e| print_warn(format!("Rollback of submodule '{}' failed: {}", path.display(), e)));
+        }
+    }
+    let (files, oid, pushed_refs) = finish_transaction(result, transaction, &repo, cli.keep_on_error);
+    if let Some(stash) = stash {
+        pop_stash(&repo, stash).unwrap_or_else(|e| print_error(e));
+        progress!("{INDENT}Restored stashed changes");
+    }
+
+    // The release refers to the pushed tag, so it is created after the transaction is completed
+    let git_config = repo.config().unwrap_or_else(|e| print_error(Error::git("Could not read git config", e)));
+    let release_url = gitlab_release.and_then(|(project, token, notes)| {
+        stats::step("release");
+        let url = gitlab::create_release(&project, &token, &git_tag_new_version_str, &notes, &git_config).unwrap_or_else(|e| print_error(Error::Api(
+            format!("{} (commit and tag were pushed already)", e))));
+        progress!("{INDENT}Created GitLab release for {}{}", git_tag_new_version_str, url.as_ref().map(|u| format!(": {u}")).unwrap_or_default());
+        url
     });
-    cargo_locks.iter().for_each(|fname| {
-        let fname_repo_rel = diff_paths(fname.as_path(), git_base_path.as_path()).unwrap();
-        // println!("rel file to commit: {}", fname_repo_rel.display());
-        index.add_path(fname_repo_rel.as_path()).unwrap();
+    let pull_request_url = pull_request.zip(bump_branch.as_ref()).and_then(|((forge, project, token, target, notes), branch)| {
+        stats::step("pull_request");
+        let url = pull_request::create_pull_request(forge, &project, &token, (branch, &target), &commit_message, &notes, &git_config)
+            .unwrap_or_else(|e| print_error(Error::Api(format!("{} (the branch '{}' was pushed already)", e, branch))));
+        progress!("{INDENT}Opened {} {} -> {}{}", forge.request_name(), branch, target, url.as_ref().map(|u| format!(": {u}")).unwrap_or_default());
+        url
     });
-    // the modified in-memory index need to flush back to disk
-    index.write().unwrap();
-
-    // write the whole tree from the index to the repo object store
-    // returns the object id you can use to lookup the actual tree object
-    let new_tree_oid = index.write_tree().unwrap();
-    // this is our new tree, i.e. the root directory of the new commit
-    let new_tree = repo.find_tree(new_tree_oid).unwrap();
-
-    // either use the configured author signature
-    let author = repo.signature().unwrap();
-    // or use an alternative signature. commiter and author need not be the same
-    /* let author = Signature::now("nick", "nick@example.com"); */
-
-    // for simple commit, use current head as parent
-    // you need more than one parent if the commit is a merge
-    let head = repo.head().unwrap();
-    let parent = repo.find_commit(head.target().unwrap()).unwrap();
-    let message = match &cli.change_type {
-        VersionChangeType::Fixed { .. } => format!("Changed version in tomls to fixed version '{}'", new_version.to_string()),
-        VersionChangeType::Increment { vtype } => format!("Changed version in tomls to '{}' by incrementing {}", new_version.to_string(), vtype),
-        VersionChangeType::OnlyShow => { print_error("Commit called for 'OnlyShow' -> aborting".into()) }
-    };
-    let oid = repo.commit(Some("HEAD"), &author, &author, message.as_str(),  &new_tree, &[&parent], )
-        .unwrap();
-    println!("{INDENT}Cargo.tomls with updated version comitted (id: {})", oid);
-
-    println!("       {} {} done", CHECK, txt);
+    for (notification, payload) in &notifications {
+        stats::step("notify");
+        notify::send(notification, payload, &git_config).unwrap_or_else(|e| print_error(Error::Api(
+            format!("{} (the release was pushed already)", e))));
+        progress!("{INDENT}Notified {}", notification.url);
+    }
+    if let Some(command) = &config.hooks.post_release {
+        stats::step("post_release");
+        hooks::run_hook("post_release", command, &git_base_path, &hook_env).unwrap_or_else(|e| print_error(Error::Hook(
+            format!("{} (the release was pushed already)", e))));
+    }
+    // the facts of the release in one place, as the step output is scattered
+    let mut manifests: Vec<(String, String)> = cargo_content.iter().map(|(f, (v, _))| (f.display().to_string(), v.to_string())).collect();
+    manifests.sort();
+    let mut report: Vec<(&str, String)> = manifests.iter().map(|(f, v)| ("Version", format!("{}: {} -> {}", f, v, cargo_version))).collect();
+    report.push(("Commit", oid.to_string()));
+    match &bump_branch {
+        Some(branch) => report.push(("Branch", branch.clone())),
+        None => report.push(("Tag", git_tag_new_version_str.clone())),
+    }
+    report.push(("Remote", git_remote.name().unwrap_or("").to_string()));
+    report.push(("Pushed", if pushed_refs.is_empty() { "nothing".to_string() } else { pushed_refs.join(", ") }));
+    progress!("");
+    print_summary("Release summary", &report);
 
-    // ***
-    let txt = String::from("Add git tag for version");
-    println!("[4/5] {} {} ...", TAG, txt);
+    if is_json_output() {
+        let command = match (&cli.change_type, &bump) {
+            (Some(VersionChangeType::Auto), _) => "auto",
+            (Some(VersionChangeType::Promote), _) => "promote",
+            (_, VersionBump::Fixed { .. }) => "fixed",
+            _ => "increment",
+        };
+        println!("{}", json::document("success").with("command", command)
+            .with("old_version", old_version.to_string()).with("new_version", new_version.to_string())
+            .with("manifests", manifests.iter().map(|(f, v)| JsonValue::object().with("file", f.as_str())
+                .with("old_version", v.as_str()).with("new_version", cargo_version.as_str())).collect::<Vec<_>>())
+            .with("files_changed", files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>())
+            .with("commit", oid.to_string()).with("tag", bump_branch.is_none().then_some(git_tag_new_version_str))
+            .with("release_branch", bump_branch).with("build", build.map(|b| b as i64))
+            .with("remote", git_remote.name().map(String::from)).with("pushed_refs", pushed_refs)
+            .with("release_url", release_url).with("pull_request_url", pull_request_url));
+    }
+    stats::finish(None);
+}
 
-    let obj = repo.revparse_single("HEAD").unwrap();
-    let r = repo.tag(git_tag_new_version_str.as_str(), &obj, &author, tag_message.as_str(), false);
-    if let Err(e) = r {
-        print_error(format!("Error adding git tag {}: {}", git_tag_new_version_str, e));
+/// Renders the release notes of a version with the template file or in the default format.
+fn render_release_notes(template_file: &Option<PathBuf>, version: &Version, tag: &str, previous_tag: Option<&str>,
+                        commits: &[notes::CommitInfo], release_time: &ReleaseTime) -> Result<String> {
+    match template_file {
+        None => Ok(notes::render_notes(version, commits)),
+        Some(template_file) => {
+            let template = fs::read_to_string(template_file)
+                .map_err(|e| Error::io(format!("Could not read template '{}'", template_file.display()), e))?;
+            let mut ctx = notes::release_context(version, tag, previous_tag, commits);
+            release_time.add_to(&mut ctx);
+            template::render(&template, &ctx).map_err(Error::Usage)
+        }
     }
-    println!("       {} {} done", CHECK, txt);
+}
 
-    // ***
-    let txt = String::from("git push for cargo.toml(s) and tag");
-    println!("[5/5] {} {} ...", TRUCK, txt);
+/// Lets the user pick the next version from the candidate bumps of the current version or enter it.
+fn pick_version_bump(current: &Version) -> Result<VersionBump> {
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::Usage("No command given and no terminal attached for the interactive version picker".to_string()));
+    }
+    let parts = [IncrementVersionPart::Patch, IncrementVersionPart::Minor, IncrementVersionPart::Major];
+    let mut items: Vec<String> = parts.iter().map(|p| Ok(format!("{:<5} -> {}", p, current.increment_clone(p)?))).collect::<Result<_>>()?;
+    items.push("Enter version ...".to_string());
+    let selection = suspend_step(|| Select::new().with_prompt(format!("{INDENT}Current version is {}, select the next version", current))
+        .items(&items).default(0).interact())
+        .map_err(|e| Error::Usage(format!("Could not read selection: {}", e)))?;
+    if let Some(vtype) = parts.get(selection) {
+        return Ok(VersionBump::Increment { vtype: vtype.clone() });
+    }
+    let full_version: String = suspend_step(|| Input::new().with_prompt(format!("{INDENT}Version"))
+        .validate_with(|v: &String| Version::try_from(v.clone()).map(|_| ())).interact_text())
+        .map_err(|e| Error::Usage(format!("Could not read version: {}", e)))?;
+    Ok(VersionBump::Fixed { full_version })
+}
 
-    let branch_ref = repo.head().unwrap();
-    let branch_ref_name = branch_ref.name().unwrap();
-    //base_repo.set_head(branch_ref_name).unwrap();
-    let tag_ref = format!("refs/tags/{}", git_tag_new_version_str);
-    println!("{INDENT}pushing to remote '{}' with branch_ref_name '{}' and '{}'", git_remote.name().unwrap(), branch_ref_name, tag_ref);
-    if let Err(e) = git_remote.push(&[branch_ref_name, tag_ref.as_str()], Some(&mut po)) {
-        print_error(format!("Error pushing to git remote: {}", e));
+/// Tag format from --tag-format or --git-prefix-for-tag, `{name}` needs a single selected package.
+fn tag_format(cli: &Cli, cargo_tomls: &[PathBuf]) -> Result<TagFormat> {
+    let Some(template) = &cli.tag_format else {
+        return Ok(TagFormat::from_prefix(cli.git_prefix_for_tag.as_deref().unwrap_or("v")));
+    };
+    let mut names = vec![];
+    if template.contains("{name}") {
+        for ct in cargo_tomls {
+            if let Some(name) = package_name(ct)? && !names.contains(&name) { names.push(name); }
+        }
     }
+    TagFormat::parse(template, if names.len() == 1 { names.first().map(String::as_str) } else { None })
+}
 
-    println!("       {} {} done", CHECK, txt);
+/// Returns the version of the highest version tag as base for increments. Warns if the cargo.toml
+/// files have a different version.
+fn tag_base_version(repo: &Repository, tag_format: &TagFormat, cargo_content: &HashMap<PathBuf, (Version, DocumentMut)>) -> Result<Version> {
+    let (tag_name, base) = latest_version_tag(repo, tag_format)?.ok_or_else(|| Error::Repository(
+        format!("No version tag matching '{}' found to increment from", tag_format)))?;
+    cargo_content.iter().filter(|(_, (v, _))| v.without_build() != base.without_build()).for_each(|(f, (v, _))| {
+        print_warn(format!("Version {} of '{}' differs from latest tag '{}'", v, f.display(), tag_name));
+    });
+    Ok(base)
+}
 
+/// Rolls back the transaction if the result is an error (unless keep_on_error is set) and exits.
+fn finish_transaction<T>(result: Result<T>, transaction: Transaction, repo: &Repository, keep_on_error: bool) -> T {
+    match result {
+        Ok(v) => v,
+        Err(e) => {
+            if keep_on_error {
+                print_warn("Keeping modifications as option keep_on_error is set.".to_string());
+                match transaction.keep(repo) {
+                    Ok(true) => progress!("{INDENT}Restored stashed changes"),
+                    Ok(false) => {},
+                    Err(e) => print_warn(format!("{} - restore them with 'git stash pop' after cleaning up the release", e)),
+                }
+            }
+            else if !transaction.is_empty() {
+                progress!("\n{INDENT}Rolling back modifications ...");
+                let errors = transaction.rollback(repo);
+                errors.iter().for_each(|re| print_warn(format!("Rollback failed: {}", re)));
+                if errors.is_empty() { progress!("{INDENT}Rollback finished."); }
+            }
+            print_error(e);
+        }
+    }
 }
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use git2::{Delta, DiffFindOptions, Oid, Repository};
+use regex::Regex;
+use crate::backend::open_backend;
+use crate::error::{Error, Result};
+use crate::template::TemplateContext;
+use crate::utils::{list_version_tags, IncrementVersionPart, TagFormat, Version, COMMIT_MESSAGE_PREFIX};
+
+// ********************************************************
+// ********************************************************
+/// Classification of a commit following the conventional commit specification.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CommitKind {
+    Breaking,
+    Feature,
+    Fix,
+    Other,
+}
+impl CommitKind {
+    pub const ALL: [CommitKind; 4] = [CommitKind::Breaking, CommitKind::Feature, CommitKind::Fix, CommitKind::Other];
+}
+impl Display for CommitKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            CommitKind::Breaking => "Breaking Changes",
+            CommitKind::Feature => "Features",
+            CommitKind::Fix => "Bug Fixes",
+            CommitKind::Other => "Other",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CommitInfo {
+    pub id: Oid,
+    pub kind: CommitKind,
+    pub scope: Option<String>,
+    pub description: String,
+    pub message: String,
+    pub is_merge: bool,
+}
+impl CommitInfo {
+    pub fn short_id(&self) -> String { self.id.to_string()[..7].to_string() }
+}
+
+/// Splits a commit message into kind, scope and description. Messages not following the
+/// conventional commit format are classified as `Other` with the summary as description.
+pub fn classify_commit(message: &str) -> (CommitKind, Option<String>, String) {
+    let re = Regex::new(r"^([a-zA-Z]+)(?:\(([^)]*)\))?(!)?: (.+)$").unwrap();
+    let summary = message.lines().next().unwrap_or("").trim();
+    let breaking_footer = message.lines().any(|l| l.starts_with("BREAKING CHANGE:") || l.starts_with("BREAKING-CHANGE:"));
+
+    match re.captures(summary) {
+        None => (if breaking_footer { CommitKind::Breaking } else { CommitKind::Other }, None, summary.to_string()),
+        Some(c) => {
+            let ctype = c.get(1).unwrap().as_str().to_lowercase();
+            let scope = c.get(2).map(|s| s.as_str().to_string());
+            let description = c.get(4).unwrap().as_str().to_string();
+            let kind = if c.get(3).is_some() || breaking_footer { CommitKind::Breaking }
+                else if ctype == "feat" { CommitKind::Feature }
+                else if ctype == "fix" { CommitKind::Fix }
+                else { CommitKind::Other };
+            (kind, scope, description)
+        }
+    }
+}
+
+/// Returns the commits whose summary line neither follows the conventional commit format nor matches
+/// `pattern` if given. Merge commits and the version commits of this tool are not checked.
+pub fn lint_commits<'a>(commits: &'a [CommitInfo], pattern: Option<&str>) -> Result<Vec<&'a CommitInfo>> {
+    let re = Regex::new(pattern.unwrap_or(r"^[a-zA-Z]+(\([^)]*\))?!?: \S"))
+        .map_err(|e| Error::Usage(format!("Invalid commit lint pattern: {}", e)))?;
+    Ok(commits.iter().filter(|c| !c.is_merge && !c.message.starts_with(COMMIT_MESSAGE_PREFIX))
+        .filter(|c| !re.is_match(c.message.lines().next().unwrap_or("").trim())).collect())
+}
+
+/// Finds a release directive like `[release: minor]` in a commit message.
+pub fn find_release_directive(message: &str) -> Option<IncrementVersionPart> {
+    let re = Regex::new(r"(?i)\[release:\s*(patch|minor|major)\s*\]").unwrap();
+    re.captures(message).map(|c| match c[1].to_lowercase().as_str() {
+        "major" => IncrementVersionPart::Major,
+        "minor" => IncrementVersionPart::Minor,
+        _ => IncrementVersionPart::Patch,
+    })
+}
+
+/// Derives the version part to increment from the conventional commit types: major for breaking
+/// changes, minor for features and patch for fixes. Returns None if there is nothing to release.
+pub fn bump_from_commits(commits: &[CommitInfo]) -> Option<IncrementVersionPart> {
+    commits.iter().filter(|c| !c.message.starts_with(COMMIT_MESSAGE_PREFIX)).filter_map(|c| match c.kind {
+        CommitKind::Breaking => Some(IncrementVersionPart::Major),
+        CommitKind::Feature => Some(IncrementVersionPart::Minor),
+        CommitKind::Fix => Some(IncrementVersionPart::Patch),
+        CommitKind::Other => None,
+    }).max()
+}
+
+/// Returns the release directive of the HEAD commit message, e.g. of the merge commit of a PR.
+pub fn head_release_directive(repo: &Repository) -> Result<Option<IncrementVersionPart>> {
+    let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    Ok(find_release_directive(head.message().unwrap_or("")))
+}
+
+/// Collects all commits reachable from HEAD but not from `since_tag` (all commits if None),
+/// newest first.
+pub fn commits_since(repo: &Repository, since_tag: Option<&str>) -> Result<Vec<CommitInfo>> {
+    let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let since = since_tag.map(|tag| repo.revparse_single(&format!("refs/tags/{}", tag)).and_then(|o| o.peel_to_commit())
+        .map(|c| c.id()).map_err(|e| Error::git(format!("Could not resolve tag '{}'", tag), e))).transpose()?;
+    commits_between(repo, since, head.id())
+}
+
+/// Collects all commits reachable from `to` but not from `from` (all commits if None), newest first.
+pub fn commits_between(repo: &Repository, from: Option<Oid>, to: Oid) -> Result<Vec<CommitInfo>> {
+    Ok(open_backend(repo)?.commits_between(from, to)?.into_iter().map(|c| {
+        let (kind, scope, description) = classify_commit(&c.message);
+        CommitInfo { id: c.id, kind, scope, description, message: c.message, is_merge: c.parent_count > 1 }
+    }).collect())
+}
+
+/// Highest version tag whose commit is an ancestor of `to` but not `to` itself, i.e. the release the
+/// commits up to `to` are based on. Returns the tag, its version and commit.
+pub fn previous_version_tag(repo: &Repository, tag_format: &TagFormat, to: Oid) -> Result<Option<(String, Version, Oid)>> {
+    let mut previous: Option<(String, Version, Oid)> = None;
+    for tn in list_version_tags(repo, tag_format)? {
+        let Some(version) = tag_format.version_part(&tn).and_then(|v| Version::try_from(v.to_string()).ok()) else { continue; };
+        let Ok(commit) = repo.revparse_single(&format!("refs/tags/{}", tn)).and_then(|o| o.peel_to_commit()) else { continue; };
+        if commit.id() == to || !repo.graph_descendant_of(to, commit.id()).unwrap_or(false) { continue; }
+        if previous.as_ref().is_none_or(|(_, v, _)| version > *v) { previous = Some((tn, version, commit.id())); }
+    }
+    Ok(previous)
+}
+
+/// Like `commits_since`, but only commits touching files below `dir` (relative to the repository
+/// root) are returned. Renames are followed (like `git log --follow`), so commits done before the
+/// crate directory was moved are reported as well.
+pub fn commits_since_for_path(repo: &Repository, since_tag: Option<&str>, dir: &Path) -> Result<Vec<CommitInfo>> {
+    let mut prefix = dir.to_path_buf();
+    let mut commits = vec![];
+    for c in commits_since(repo, since_tag)? {
+        let commit = repo.find_commit(c.id).map_err(|e| Error::git(format!("Could not find commit {}", c.id), e))?;
+        let tree = commit.tree().map_err(|e| Error::git(format!("Could not read tree of {}", c.id), e))?;
+        let parent_tree = match commit.parent(0) {
+            Ok(p) => Some(p.tree().map_err(|e| Error::git(format!("Could not read tree of {}", p.id()), e))?),
+            Err(_) => None,
+        };
+        let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+            .map_err(|e| Error::git(format!("Could not diff commit {}", c.id), e))?;
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+            .map_err(|e| Error::git(format!("Could not detect renames in {}", c.id), e))?;
+
+        let mut touched = false;
+        let mut renamed_from = None;
+        for delta in diff.deltas() {
+            let new_path = delta.new_file().path().unwrap_or(Path::new(""));
+            let old_path = delta.old_file().path().unwrap_or(Path::new(""));
+            if !new_path.starts_with(&prefix) && !old_path.starts_with(&prefix) { continue; }
+            touched = true;
+            if delta.status() == Delta::Renamed && new_path.starts_with(&prefix) && !old_path.starts_with(&prefix) {
+                renamed_from = renamed_prefix(&prefix, new_path, old_path);
+            }
+        }
+        if touched { commits.push(c); }
+        // older commits know the crate under its previous path
+        if let Some(p) = renamed_from { prefix = p; }
+    }
+    Ok(commits)
+}
+
+/// Determines the previous location of `prefix` from a file renamed from `old_path` to `new_path`.
+fn renamed_prefix(prefix: &Path, new_path: &Path, old_path: &Path) -> Option<PathBuf> {
+    let rest = new_path.strip_prefix(prefix).ok()?;
+    let old = old_path.to_str()?.strip_suffix(rest.to_str()?)?;
+    Some(PathBuf::from(old.trim_end_matches('/')))
+}
+
+/// Renders markdown release notes for the version grouped by commit kind.
+pub fn render_notes(version: &Version, commits: &[CommitInfo]) -> String {
+    let mut notes = format!("## {}\n", version);
+    if commits.is_empty() {
+        notes += "\nNo changes.\n";
+    }
+    for kind in CommitKind::ALL {
+        let entries: Vec<_> = commits.iter().filter(|c| c.kind == kind).collect();
+        if entries.is_empty() { continue; }
+        notes += &format!("\n### {}\n", kind);
+        entries.iter().for_each(|c| {
+            let scope = c.scope.as_ref().map(|s| format!("**{}:** ", s)).unwrap_or_default();
+            notes += &format!("- {}{} ({})\n", scope, c.description, c.short_id());
+        });
+    }
+    notes
+}
+
+/// Builds the release context available to notification and release notes templates.
+pub fn release_context(version: &Version, tag: &str, previous_tag: Option<&str>, commits: &[CommitInfo]) -> TemplateContext {
+    let mut ctx = TemplateContext::new();
+    ctx.insert("version", version).insert("tag", tag).insert("previous_tag", previous_tag.unwrap_or(""))
+        .insert("notes", render_notes(version, commits));
+    ctx.insert_list("commits", commits.iter().map(|c| BTreeMap::from([
+        ("id".to_string(), c.id.to_string()),
+        ("short_id".to_string(), c.short_id()),
+        ("kind".to_string(), c.kind.to_string()),
+        ("scope".to_string(), c.scope.clone().unwrap_or_default()),
+        ("description".to_string(), c.description.clone()),
+    ])).collect());
+    for kind in CommitKind::ALL {
+        let name = match kind {
+            CommitKind::Breaking => "breaking", CommitKind::Feature => "features",
+            CommitKind::Fix => "fixes", CommitKind::Other => "others",
+        };
+        ctx.insert_list(name, commits.iter().filter(|c| c.kind == kind).map(|c| BTreeMap::from([
+            ("short_id".to_string(), c.short_id()),
+            ("scope".to_string(), c.scope.clone().unwrap_or_default()),
+            ("description".to_string(), c.description.clone()),
+        ])).collect());
+    }
+    ctx
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_notes {
+    use super::*;
+
+    #[test]
+    fn test_classify_commit() {
+        assert_eq!(classify_commit("feat(cli): add notes"), (CommitKind::Feature, Some("cli".to_string()), "add notes".to_string()));
+        assert_eq!(classify_commit("fix: typo\n\nbody"), (CommitKind::Fix, None, "typo".to_string()));
+        assert_eq!(classify_commit("refactor!: drop api"), (CommitKind::Breaking, None, "drop api".to_string()));
+        assert_eq!(classify_commit("chore: x\n\nBREAKING CHANGE: y"), (CommitKind::Breaking, None, "x".to_string()));
+        assert_eq!(classify_commit("Update readme"), (CommitKind::Other, None, "Update readme".to_string()));
+    }
+    #[test]
+    fn test_lint_commits() {
+        let commit = |message: &str, is_merge: bool| {
+            let (kind, scope, description) = classify_commit(message);
+            CommitInfo { id: Oid::zero(), kind, scope, description, message: message.to_string(), is_merge }
+        };
+        let commits = [commit("feat(cli): a", false), commit("Update readme", false), commit("Merge branch 'x'", true),
+                       commit(&format!("{COMMIT_MESSAGE_PREFIX} '1.0.0' by incrementing minor"), false), commit("fix:missing space", false)];
+        let offending: Vec<_> = lint_commits(&commits, None).unwrap().iter().map(|c| c.message.clone()).collect();
+        assert_eq!(offending, vec!["Update readme", "fix:missing space"]);
+        assert_eq!(lint_commits(&commits, Some(r"^(feat|Update)")).unwrap().len(), 1);
+    }
+    #[test]
+    fn test_find_release_directive() {
+        assert_eq!(find_release_directive("Merge pull request #1\n\n[release: minor]"), Some(IncrementVersionPart::Minor));
+        assert_eq!(find_release_directive("feat: x [Release:MAJOR]"), Some(IncrementVersionPart::Major));
+        assert_eq!(find_release_directive("fix: release notes"), None);
+    }
+    #[test]
+    fn test_bump_from_commits() {
+        let commit = |message: &str| {
+            let (kind, scope, description) = classify_commit(message);
+            CommitInfo { id: Oid::zero(), kind, scope, description, message: message.to_string(), is_merge: false }
+        };
+        assert_eq!(bump_from_commits(&[commit("fix: a"), commit("docs: b")]), Some(IncrementVersionPart::Patch));
+        assert_eq!(bump_from_commits(&[commit("fix: a"), commit("feat(cli): b")]), Some(IncrementVersionPart::Minor));
+        assert_eq!(bump_from_commits(&[commit("feat!: a"), commit("fix: b")]), Some(IncrementVersionPart::Major));
+        assert_eq!(bump_from_commits(&[commit("chore: a"), commit("Update readme")]), None);
+    }
+    #[test]
+    fn test_previous_version_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let mut parents = vec![];
+        let mut oids = vec![];
+        for message in ["feat: a", "fix: b", "fix: c"] {
+            let parent_commits: Vec<_> = parents.iter().map(|p| repo.find_commit(*p).unwrap()).collect();
+            let oid = repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parent_commits.iter().collect::<Vec<_>>()).unwrap();
+            parents = vec![oid];
+            oids.push(oid);
+        }
+        repo.tag_lightweight("v1.0.0", &repo.find_object(oids[0], None).unwrap(), false).unwrap();
+        repo.tag_lightweight("v1.1.0", &repo.find_object(oids[1], None).unwrap(), false).unwrap();
+        let tag_format = TagFormat::from_prefix("v");
+        let previous = |to| previous_version_tag(&repo, &tag_format, to).unwrap().map(|(tn, _, _)| tn);
+        assert_eq!(previous(oids[2]).as_deref(), Some("v1.1.0"));
+        assert_eq!(previous(oids[1]).as_deref(), Some("v1.0.0"));
+        assert_eq!(previous(oids[0]), None);
+    }
+    #[test]
+    fn test_renamed_prefix() {
+        assert_eq!(renamed_prefix(Path::new("crates/new"), Path::new("crates/new/src/lib.rs"), Path::new("old/src/lib.rs")),
+                   Some(PathBuf::from("old")));
+        assert_eq!(renamed_prefix(Path::new("crates/new"), Path::new("crates/new/src/lib.rs"), Path::new("old/lib.rs")), None);
+    }
+    #[test]
+    fn test_render_notes() {
+        let version = Version::try_from("1.2.0".to_string()).unwrap();
+        let commits = vec![
+            CommitInfo { id: Oid::from_str("1234567890abcdef1234567890abcdef12345678").unwrap(), kind: CommitKind::Fix,
+                scope: Some("git".to_string()), description: "handle tags".to_string(),
+                message: "fix(git): handle tags".to_string(), is_merge: false },
+        ];
+        assert_eq!(render_notes(&version, &commits), "## 1.2.0\n\n### Bug Fixes\n- **git:** handle tags (1234567)\n");
+        assert_eq!(render_notes(&version, &[]), "## 1.2.0\n\nNo changes.\n");
+    }
+}
@@ -0,0 +1,79 @@
+use git2::Config;
+use crate::config::Notification;
+use crate::error::{Error, Result};
+use crate::gitlab;
+use crate::json::{self, JsonValue};
+use crate::template::{self, TemplateContext};
+
+// ********************************************************
+// ********************************************************
+/// Format of the payload posted to the url of a notification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotifyKind {
+    /// The rendered template is the json body. Variables are json-escaped, so they can be put
+    /// into string literals of the template
+    Webhook,
+    /// The rendered template is the text of a Slack (or Mattermost) incoming webhook message
+    Slack,
+}
+impl NotifyKind {
+    /// Parses the `kind` of a notification in the config.
+    pub fn parse(kind: &str) -> Option<NotifyKind> {
+        match kind {
+            "webhook" => Some(NotifyKind::Webhook),
+            "slack" => Some(NotifyKind::Slack),
+            _ => None,
+        }
+    }
+
+    /// Template used if the config does not give one.
+    pub fn default_template(&self) -> &'static str {
+        match self {
+            NotifyKind::Webhook => concat!(r#"{"version": "{{ version }}", "tag": "{{ tag }}", "previous_tag": "{{ previous_tag }}", "#,
+                                           r#""date": "{{ date }}", "notes": "{{ notes }}"}"#),
+            NotifyKind::Slack => "Released *{{ tag }}*\n{{ notes }}",
+        }
+    }
+}
+
+/// Renders the payload of the notification with the release context, see `notes::release_context`.
+pub fn render_payload(notification: &Notification, ctx: &TemplateContext) -> Result<String> {
+    let template = notification.template.as_deref().unwrap_or(notification.kind.default_template());
+    let error = |e: String| Error::Usage(format!("Invalid template of the notification to {}: {}", notification.url, e));
+    match notification.kind {
+        NotifyKind::Webhook => template::render_escaped(template, ctx, json::escape).map_err(error),
+        NotifyKind::Slack => Ok(JsonValue::object().with("text", template::render(template, ctx).map_err(error)?).to_string()),
+    }
+}
+
+/// Posts the rendered payload to the url of the notification, with the proxy of the git `config`.
+pub fn send(notification: &Notification, payload: &str, config: &Config) -> Result<()> {
+    gitlab::post(&notification.url, None, payload, config, &format!("notify {}", notification.url))?;
+    Ok(())
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_notify {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        let mut ctx = TemplateContext::new();
+        ctx.insert("version", "1.2.0").insert("tag", "v1.2.0").insert("previous_tag", "v1.1.0").insert("date", "2024-03-01")
+            .insert("notes", "Features:\n- say \"hi\"\n");
+        ctx
+    }
+
+    #[test]
+    fn test_render_payload() {
+        let webhook = Notification { kind: NotifyKind::Webhook, url: "https://hooks/x".to_string(), template: None };
+        assert_eq!(render_payload(&webhook, &context()).unwrap(), concat!(r#"{"version": "1.2.0", "tag": "v1.2.0", "previous_tag": "v1.1.0", "#,
+                   r#""date": "2024-03-01", "notes": "Features:\n- say \"hi\"\n"}"#));
+        let slack = Notification { kind: NotifyKind::Slack, url: "https://hooks/y".to_string(),
+                                   template: Some("{{ tag }} is out: {{ notes }}".to_string()) };
+        assert_eq!(render_payload(&slack, &context()).unwrap(), r#"{"text":"v1.2.0 is out: Features:\n- say \"hi\"\n"}"#);
+        let invalid = Notification { template: Some("{{ unknown }}".to_string()), ..slack };
+        assert!(render_payload(&invalid, &context()).unwrap_err().to_string().contains("notification to https://hooks/y"));
+    }
+}
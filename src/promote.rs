@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use git2::{Oid, Remote, Repository, ResetType};
+use regex::Regex;
+use toml_edit::DocumentMut;
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// Splits a release candidate version like `1.3.0-rc.2` (optionally as tag name) into the final
+/// version and the rc number.
+pub fn parse_rc_version(rc: &str, tag_format: &TagFormat) -> Option<(Version, u32)> {
+    let re = Regex::new(r"^([0-9]+\.[0-9]+\.[0-9]+)-rc\.?([0-9]+)$").unwrap();
+    let c = re.captures(tag_format.version_part(rc).unwrap_or(rc))?;
+    let version = Version::try_from(c[1].to_string()).ok()?;
+    Some((version, c[2].parse().ok()?))
+}
+
+/// Tags the commit of an rc tag with the final version, optionally fast-forwards a branch to
+/// it and pushes tag (and branch). All modifications are recorded in the transaction. The remote
+/// is only updated if the branch is still at its previous commit and the tag does not exist there.
+pub fn promote_rc(repo: &Repository, tag_format: &TagFormat, rc: &str, tag_message: Option<String>,
+                  fast_forward: Option<&str>, git_remote: &mut Remote, transaction: &mut Transaction) -> Result<()> {
+    let (version, rc_number) = parse_rc_version(rc, tag_format)
+        .ok_or_else(|| Error::Usage(format!("'{}' is not a release candidate version (expected X.Y.Z-rc.N)", rc)))?;
+    let rc_tag = if tag_format.version_part(rc).is_some() { rc.to_string() } else { tag_format.tag(rc) };
+    let rc_commit = repo.revparse_single(&format!("refs/tags/{}", rc_tag)).and_then(|o| o.peel_to_commit())
+        .map_err(|e| Error::git(format!("Could not resolve rc tag '{}'", rc_tag), e))?;
+    progress!("{INDENT}Promoting rc {} of {} (commit {})", rc_number, version, rc_commit.id());
+
+    let final_tag = tag_format.tag(&version);
+    if list_version_tags(repo, tag_format)?.contains(&final_tag) {
+        return Err(Error::Conflict(format!("Final version already exists as git tag '{}' -> Aborting", final_tag)));
+    }
+
+    let mut refs_to_push = vec![];
+    let mut leases = vec![];
+    if let Some(branch) = fast_forward {
+        let branch_ref_name = format!("refs/heads/{}", branch);
+        let branch_ref = repo.find_reference(&branch_ref_name)
+            .map_err(|e| Error::git(format!("Could not find branch '{}'", branch), e))?;
+        let branch_oid = branch_ref.target().ok_or_else(|| Error::Repository(format!("Branch '{}' is not a direct reference", branch)))?;
+        if branch_oid != rc_commit.id() && !repo.graph_descendant_of(rc_commit.id(), branch_oid).unwrap_or(false) {
+            return Err(Error::Repository(format!("Branch '{}' cannot be fast-forwarded to {}", branch, rc_commit.id())));
+        }
+
+        let is_head = repo.head().ok().and_then(|h| h.name().map(|n| n == branch_ref_name)).unwrap_or(false);
+        if is_head {
+            let change_count = count_uncommitted_changes(repo)?;
+            if change_count > 0 { return Err(Error::DirtyTree(change_count)); }
+            repo.reset(rc_commit.as_object(), ResetType::Hard, None)
+                .map_err(|e| Error::git(format!("Could not fast-forward '{}'", branch), e))?;
+            transaction.record_checkout(&branch_ref_name, branch_oid);
+        } else {
+            repo.reference(&branch_ref_name, rc_commit.id(), true, &format!("cgvs: fast-forward to {}", final_tag))
+                .map_err(|e| Error::git(format!("Could not fast-forward '{}'", branch), e))?;
+            transaction.record_commit(&branch_ref_name, branch_oid);
+        }
+        progress!("{INDENT}Fast-forwarded branch '{}' to {}", branch, rc_commit.id());
+        leases.push((branch_ref_name.clone(), branch_oid));
+        refs_to_push.push(branch_ref_name);
+    }
+
+    let tagger = committer_signature(repo)?;
+    let message = tag_message.unwrap_or(format!("Promoted {} to {}", rc_tag, final_tag));
+    repo.tag(&final_tag, rc_commit.as_object(), &tagger, &message, false)
+        .map_err(|e| Error::git(format!("Error adding git tag {}", final_tag), e))?;
+    transaction.record_tag(&final_tag);
+    progress!("{INDENT}Added tag '{}'", final_tag);
+    refs_to_push.push(format!("refs/tags/{}", final_tag));
+    leases.push((format!("refs/tags/{}", final_tag), Oid::zero()));
+
+    progress!("{INDENT}pushing to remote '{}': {}", git_remote.name().unwrap_or(""), refs_to_push.join(", "));
+    push_refs(repo, git_remote, &refs_to_push, leases)
+}
+
+/// Final version of the release candidate version in the cargo.toml files, e.g. `1.3.0` for
+/// `1.3.0-rc.2` (`promote`). An rc of the final version has to be tagged, so only a tested
+/// candidate is promoted.
+pub fn promoted_version(repo: &Repository, tag_format: &TagFormat, cargo_content: &HashMap<PathBuf, (Version, DocumentMut)>) -> Result<Version> {
+    let mut versions: Vec<String> = cargo_content.values().filter_map(|(_, toml)| match manifest_version(toml) {
+        ManifestVersion::Package(v) | ManifestVersion::Workspace(v) => Some(v),
+        _ => None,
+    }).collect();
+    versions.sort();
+    versions.dedup();
+    let current = match versions.as_slice() {
+        [] => return Err(Error::Discovery("No cargo.toml with a version found.".to_string())),
+        [current] => current,
+        _ => return Err(Error::Manifest(format!("Versions differ between the cargo.toml files: {}", versions.join(", ")))),
+    };
+    let (version, _) = parse_rc_version(current, tag_format).ok_or_else(|| Error::Usage(
+        format!("Version '{}' is not a release candidate (expected X.Y.Z-rc.N)", current)))?;
+    let rcs = superseded_rcs(repo, tag_format, &version)?;
+    if rcs.is_empty() {
+        return Err(Error::Repository(format!("No release candidate of {} is tagged (e.g. '{}') -> Aborting", version,
+                                             tag_format.tag(format!("{}-rc.1", version)))));
+    }
+    progress!("{INDENT}Promoting {} to {} (tagged candidates: {})", current, version, rcs.join(", "));
+    Ok(version)
+}
+
+/// Returns the rc versions of `version` which have a tag (e.g. `1.3.0-rc.1`), lowest rc first. They
+/// are superseded once the final version is released.
+pub fn superseded_rcs(repo: &Repository, tag_format: &TagFormat, version: &Version) -> Result<Vec<String>> {
+    let mut rcs: Vec<(u32, String)> = list_version_tags(repo, tag_format)?.iter().filter_map(|tn| {
+        let (v, n) = parse_rc_version(tn, tag_format)?;
+        (&v == version).then(|| (n, tag_format.version_part(tn).unwrap_or(tn).to_string()))
+    }).collect();
+    rcs.sort();
+    Ok(rcs.into_iter().map(|(_, rc)| rc).collect())
+}
+
+/// Yanks the rc releases of the crates from the registry with `cargo yank` (`--yank-rcs`). The final
+/// version is already pushed at this point, so failures (e.g. an rc which was never published) are
+/// only returned to be reported.
+pub fn yank_rcs(crates: &[String], rcs: &[String]) -> Vec<String> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut failures = vec![];
+    for name in crates {
+        for rc in rcs {
+            tracing::debug!("{} yank --version {} {}", cargo, rc, name);
+            match suspend_step(|| Command::new(&cargo).args(["yank", "--version", rc, name]).status()) {
+                Ok(status) if status.success() => progress!("{INDENT}Yanked {} {}", name, rc),
+                Ok(status) => failures.push(format!("Could not yank {} {} ({})", name, rc, status)),
+                Err(e) => failures.push(format!("Could not run '{} yank' for {} {}: {}", cargo, name, rc, e)),
+            }
+        }
+    }
+    failures
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_promote {
+    use super::*;
+
+    #[test]
+    fn test_parse_rc_version() {
+        let v = Version::try_from("1.3.0".to_string()).unwrap();
+        assert_eq!(parse_rc_version("v1.3.0-rc.2", &TagFormat::from_prefix("v")), Some((v.clone(), 2)));
+        assert_eq!(parse_rc_version("1.3.0-rc1", &TagFormat::from_prefix("v")), Some((v, 1)));
+        assert_eq!(parse_rc_version("v1.3.0", &TagFormat::from_prefix("v")), None);
+        assert_eq!(parse_rc_version("v1.3.0-beta.1", &TagFormat::from_prefix("v")), None);
+    }
+
+    #[test]
+    fn test_superseded_rcs() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let obj = repo.find_object(oid, None).unwrap();
+        for tn in ["v1.3.0-rc.10", "v1.3.0-rc.2", "v1.2.0-rc.1", "v1.3.0"] { repo.tag_lightweight(tn, &obj, false).unwrap(); }
+        let version = Version::try_from("1.3.0".to_string()).unwrap();
+        assert_eq!(superseded_rcs(&repo, &TagFormat::from_prefix("v"), &version).unwrap(), vec!["1.3.0-rc.2", "1.3.0-rc.10"]);
+    }
+
+    #[test]
+    fn test_promoted_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let content = |version: &str| {
+            let toml: DocumentMut = format!("[package]\nname = \"a\"\nversion = \"{}\"\n", version).parse().unwrap();
+            HashMap::from([(PathBuf::from("Cargo.toml"), (Version::try_from(version.to_string()).unwrap(), toml))])
+        };
+        let tag_format = TagFormat::from_prefix("v");
+        assert_eq!(promoted_version(&repo, &tag_format, &content("1.3.0-rc.2")).unwrap_err().exit_code(), 6);
+        repo.tag_lightweight("v1.3.0-rc.1", &repo.find_object(oid, None).unwrap(), false).unwrap();
+        assert_eq!(promoted_version(&repo, &tag_format, &content("1.3.0-rc.2")).unwrap().to_string(), "1.3.0");
+        assert_eq!(promoted_version(&repo, &tag_format, &content("1.3.0")).unwrap_err().exit_code(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+use git2::Config;
+use crate::error::Result;
+use crate::gitlab::{self, Project};
+use crate::json::JsonValue;
+
+// ********************************************************
+// ********************************************************
+/// Hosting service of the remote, which the pull request is opened on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+}
+impl Forge {
+    /// GitHub for github.com and in GitHub Actions (`GITHUB_API_URL`), otherwise GitLab.
+    pub fn detect(project: &Project, env: impl Fn(&str) -> Option<String>) -> Forge {
+        let github_server = env("GITHUB_SERVER_URL").and_then(|u| u.split_once("://").map(|(_, h)| h.trim_end_matches('/').to_string()));
+        if project.host == "github.com" || github_server.as_deref() == Some(project.host.as_str()) { Forge::GitHub } else { Forge::GitLab }
+    }
+
+    /// Name of a pull request on the forge, for messages.
+    pub fn request_name(&self) -> &'static str {
+        match self { Forge::GitHub => "pull request", Forge::GitLab => "merge request" }
+    }
+
+    /// Returns the header and value used for authentication: `GITHUB_TOKEN` or `GH_TOKEN` for
+    /// GitHub, `GITLAB_TOKEN` or `CI_JOB_TOKEN` for GitLab.
+    pub fn find_token(&self, env: impl Fn(&str) -> Option<String>) -> Option<(&'static str, String)> {
+        match self {
+            Forge::GitHub => env("GITHUB_TOKEN").filter(|t| !t.is_empty()).or_else(|| env("GH_TOKEN").filter(|t| !t.is_empty()))
+                .map(|t| ("Authorization", format!("Bearer {}", t))),
+            Forge::GitLab => gitlab::find_token(env),
+        }
+    }
+
+    /// Base url of the API. For GitHub `GITHUB_API_URL`, api.github.com or `/api/v3` of a GitHub
+    /// Enterprise host.
+    pub fn api_url(&self, project: &Project, env: impl Fn(&str) -> Option<String>) -> String {
+        match self {
+            Forge::GitHub => env("GITHUB_API_URL").filter(|u| !u.is_empty()).map(|u| u.trim_end_matches('/').to_string())
+                .unwrap_or_else(|| match project.host.as_str() {
+                    "github.com" => "https://api.github.com".to_string(),
+                    host => format!("https://{}/api/v3", host),
+                }),
+            Forge::GitLab => gitlab::api_url(project, env),
+        }
+    }
+}
+
+/// Opens a pull request (merge request on GitLab) from the pushed source branch into the target
+/// branch (`branches`) and returns its url if the response contains it. Uses the `curl` executable with the proxy
+/// of the git `config`.
+pub fn create_pull_request(forge: Forge, project: &Project, token: &(&str, String), (source, target): (&str, &str),
+                           title: &str, description: &str, config: &Config) -> Result<Option<String>> {
+    let api_url = forge.api_url(project, |k| std::env::var(k).ok());
+    let (url, body, url_field) = match forge {
+        Forge::GitHub => (format!("{}/repos/{}/pulls", api_url, project.path),
+                          JsonValue::object().with("title", title).with("head", source).with("base", target).with("body", description),
+                          "html_url"),
+        Forge::GitLab => (format!("{}/projects/{}/merge_requests", api_url, project.encoded_path()),
+                          JsonValue::object().with("title", title).with("source_branch", source).with("target_branch", target)
+                              .with("description", description).with("remove_source_branch", true),
+                          "web_url"),
+    };
+    let response = gitlab::post_json(&url, token, &body, config, &format!("open the {} for '{}'", forge.request_name(), source))?;
+    Ok(gitlab::response_field(&response, url_field))
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_pull_request {
+    use super::*;
+
+    #[test]
+    fn test_forge() {
+        let github = Project::from_remote_url("git@github.com:a/b.git").unwrap();
+        let gitlab = Project::from_remote_url("https://git.example.com/a/b.git").unwrap();
+        assert_eq!(Forge::detect(&github, |_| None), Forge::GitHub);
+        assert_eq!(Forge::detect(&gitlab, |_| None), Forge::GitLab);
+        let ghes = |k: &str| (k == "GITHUB_SERVER_URL").then(|| "https://git.example.com".to_string());
+        assert_eq!(Forge::detect(&gitlab, ghes), Forge::GitHub);
+        assert_eq!(Forge::GitHub.api_url(&gitlab, |_| None), "https://git.example.com/api/v3");
+        assert_eq!(Forge::GitHub.api_url(&github, |_| None), "https://api.github.com");
+        let env = |k: &str| (k == "GH_TOKEN").then(|| "t".to_string());
+        assert_eq!(Forge::GitHub.find_token(env), Some(("Authorization", "Bearer t".to_string())));
+        assert_eq!(Forge::GitLab.find_token(env), None);
+    }
+}
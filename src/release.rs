@@ -0,0 +1,560 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::Subcommand;
+use git2::{Oid, Repository};
+use pathdiff::diff_paths;
+use toml_edit::DocumentMut;
+use crate::error::{Error, Result};
+use crate::logging;
+use crate::sinks::{read_source, write_sink, VersionSink, VersionSource};
+use crate::template;
+use crate::transaction::Transaction;
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// How the new version is derived from the current one
+#[derive(Subcommand, Clone, PartialEq, Debug)]
+pub enum VersionBump {
+    /// Set fixed version
+    Fixed {
+        full_version: String
+    },
+    /// Increment part of the version
+    Increment {
+        vtype: IncrementVersionPart,
+    },
+}
+
+
+/// Computes the new version from the versions of the cargo.toml files, or from `base` (the version
+/// tag or a version source) if given.
+pub fn compute_new_version(cargo_content: &HashMap<PathBuf, (Version, DocumentMut)>, bump: &VersionBump,
+                           cargo_file_selector: &Option<CargoFile>, base: Option<&Version>) -> Result<Version> {
+    match bump {
+        VersionBump::Increment{ vtype } => {
+            if let Some(base) = base { return base.increment_clone(vtype); }
+
+            // test if all versions are equal (should work also with one cargo.toml
+            let (version_to_test_against, _) = cargo_content.values().next()
+                .ok_or_else(|| Error::Discovery("No cargo.toml found.".to_string()))?;
+
+            let all_versions_equal = cargo_content.iter().fold(true, |acc, (_, (cv, _))| {
+                let e = version_to_test_against == cv;
+                acc && e });
+            if !all_versions_equal && matches!(cargo_file_selector, Some(CargoFile::All | CargoFile::Workspace)) { return Err(Error::Manifest(
+                "When using increment and updating all cargo-toml files, the versions have to be equal in all files. Use fixed in this case ...".to_string()));
+            }
+            version_to_test_against.increment_clone(vtype)
+        },
+        VersionBump::Fixed { full_version } => {
+            Version::try_from(full_version.clone())
+                .map_err(|_| Error::Usage(format!("Wrong format for version specifier '{}'.", full_version)))
+        }
+    }
+}
+
+/// Writes the new version to all cargo.toml files and to the respective Cargo.lock files if they
+/// contain the package, as well as to the `[workspace.dependencies]` of their workspace roots.
+/// Returns the lock files and workspace roots that were changed in addition.
+pub fn write_versions(cargo_content: &mut HashMap<PathBuf, (Version, DocumentMut)>, new_version: &Version,
+                      transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    write_version_str(cargo_content, &new_version.to_string(), transaction)
+}
+
+/// Like [`write_versions`], but for versions which are no plain `X.Y.Z`, e.g. snapshot versions.
+pub fn write_version_str(cargo_content: &mut HashMap<PathBuf, (Version, DocumentMut)>, new_version: &str,
+                         transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    let mut cargo_locks = vec![];
+    for (fname, (_, toml)) in cargo_content.iter_mut() {
+        let mut lock_file = fname.with_file_name("Cargo.lock");
+        match manifest_version(toml) {
+            ManifestVersion::Workspace(_) => {
+                set_string_item(&mut toml["workspace"]["package"]["version"], new_version);
+            },
+            ManifestVersion::Inherited => {
+                // the version is written to the workspace root, only its lock file is updated
+                tracing::info!("{} inherits the version of the workspace", fname.display());
+                lock_file = find_workspace_root(fname).map(|r| r.with_file_name("Cargo.lock")).unwrap_or(lock_file);
+            },
+            _ => { set_string_item(&mut toml["package"]["version"], new_version); },
+        }
+        if !matches!(manifest_version(toml), ManifestVersion::Inherited) {
+            transaction.backup_file(fname)?;
+            write_toml(fname, toml).map_err(|e| Error::io(format!("Failed to write to '{}'", fname.display()), e))?;
+            tracing::info!("Updated cargo.toml: {}", fname.display());
+        }
+
+        // adjust version in lock file
+        let Some(cargo_prj_name) = toml.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()).map(String::from) else { continue; };
+        if !lock_file.exists() { continue; }
+
+        let cct_content = fs::read_to_string(&lock_file)
+            .map_err(|e| Error::io(format!("Could not read lock file '{}'", lock_file.display()), e))?;
+        let mut toml_lock = cct_content.parse::<DocumentMut>()
+            .map_err(|e| Error::Manifest(format!("Could not parse toml from lock file '{}': {}", lock_file.display(), e)))?;
+
+        if let Some(package_sections) = toml_lock["package"].as_array_of_tables_mut() {
+            let mut changed_lock = false;
+            package_sections.iter_mut().for_each(|cps| {
+                if cps.get("name").and_then(|n| n.as_str()) == Some(cargo_prj_name.as_str()) {
+                    set_string_item(&mut cps["version"], new_version);
+                    changed_lock = true;
+                }
+            });
+            if changed_lock {
+                transaction.backup_file(&lock_file)?;
+                write_toml(&lock_file, &toml_lock)
+                    .map_err(|e| Error::io(format!("Failed to write to lock '{}'", lock_file.display()), e))?;
+                tracing::info!("  and respective cargo.lock: {}", lock_file.display());
+                if !cargo_locks.contains(&lock_file) { cargo_locks.push(lock_file); }
+            }
+        }
+        else {
+            print_warn( format!("Unexpected format in lock file content of {}", lock_file.display()));
+        }
+    }
+
+    // members depending on each other via the workspace dependencies get the new version as well
+    let packages: Vec<String> = cargo_content.values()
+        .filter_map(|(_, toml)| toml.get("package")?.get("name")?.as_str().map(String::from)).collect();
+    let mut roots: Vec<PathBuf> = cargo_content.keys().filter_map(|f| find_workspace_root(f)).collect();
+    roots.extend(cargo_content.iter().filter(|(_, (_, t))| t.contains_key("workspace")).map(|(f, _)| f.clone()));
+    roots.sort();
+    roots.dedup();
+    for root in roots {
+        let mut toml = fs::read_to_string(&root).map_err(|e| Error::io(format!("Could not read file '{}'", root.display()), e))?
+            .parse::<DocumentMut>().map_err(|e| Error::Manifest(format!("Could not parse toml form file '{}': {}", root.display(), e)))?;
+        // build metadata is ignored in version requirements, cargo warns about it
+        let requirement = new_version.split('+').next().unwrap_or(new_version);
+        if !update_workspace_dependencies(&mut toml, &packages, requirement) { continue; }
+        transaction.backup_file(&root)?;
+        write_toml(&root, &toml).map_err(|e| Error::io(format!("Failed to write to '{}'", root.display()), e))?;
+        tracing::info!("Updated workspace dependencies in {}", root.display());
+        if !cargo_content.contains_key(&root) { cargo_locks.push(root); }
+    }
+    Ok(cargo_locks)
+}
+
+/// Adds the files to the index and commits them on top of HEAD. With `amend`, HEAD is replaced by a
+/// commit with its parents, message and author instead, like `git commit --amend --no-edit`.
+pub fn commit_files(repo: &Repository, git_base_path: &Path, files: &[PathBuf], message: &str, amend: bool,
+                    transaction: &mut Transaction) -> Result<Oid> {
+    // https://users.rust-lang.org/t/how-can-i-do-git-add-some-file-rs-git-commit-m-message-git-push-with-git2-crate-on-a-bare-repo/94109/3
+    // open the index database of the given repository
+    // the repo can't be bare, must have a worktree
+    // resolve the signature first, so a missing user.name does not leave staged files behind
+    let _span = tracing::debug_span!(target: logging::GIT, "commit", amend).entered();
+    let (author, committer) = (author_signature(repo)?, committer_signature(repo)?);
+    let mut index = repo.index().map_err(|e| Error::git("Could not open git index", e))?;
+    for fname in files {
+        let fname_repo_rel = diff_paths(fname.as_path(), git_base_path).ok_or_else(|| Error::Discovery(
+            format!("'{}' is not inside the git repository", fname.display())))?;
+        tracing::debug!(target: logging::GIT, "git add {}", fname_repo_rel.display());
+        index.add_path(fname_repo_rel.as_path())
+            .map_err(|e| Error::git(format!("Could not add '{}' to git index", fname_repo_rel.display()), e))?;
+    }
+    // the modified in-memory index need to flush back to disk
+    index.write().map_err(|e| Error::git("Could not write git index", e))?;
+
+    // write the whole tree from the index to the repo object store
+    let new_tree_oid = index.write_tree().map_err(|e| Error::git("Could not write git tree", e))?;
+    let new_tree = repo.find_tree(new_tree_oid).map_err(|e| Error::git("Could not find git tree", e))?;
+
+    // for simple commit, use current head as parent
+    let head = repo.head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let head_ref_name = head.name().ok_or(Error::Repository("HEAD is not a valid utf-8 reference".into()))?.to_string();
+    let parent = head.peel_to_commit().map_err(|e| Error::git("Could not find HEAD commit", e))?;
+    let oid = if amend {
+        let oid = parent.amend(Some("HEAD"), None, Some(&committer), None, None, Some(&new_tree))
+            .map_err(|e| Error::git("Could not amend HEAD", e))?;
+        tracing::debug!(target: logging::GIT, "git commit --amend {} on {} (replaces {})", oid, head_ref_name, parent.id());
+        oid
+    } else {
+        let oid = repo.commit(Some("HEAD"), &author, &committer, message, &new_tree, &[&parent])
+            .map_err(|e| Error::git("Could not commit", e))?;
+        tracing::debug!(target: logging::GIT, "git commit {} on {} (parent {})", oid, head_ref_name, parent.id());
+        oid
+    };
+    transaction.record_commit(&head_ref_name, parent.id());
+    Ok(oid)
+}
+
+/// Adds an annotated tag to the HEAD commit. With `force` an existing tag is replaced and restored
+/// on rollback.
+pub fn tag_head(repo: &Repository, tag_name: &str, tag_message: &str, force: bool, transaction: &mut Transaction) -> Result<()> {
+    let _span = tracing::debug_span!(target: logging::GIT, "tag", tag = %tag_name, force).entered();
+    let tagger = committer_signature(repo)?;
+    let obj = repo.revparse_single("HEAD").map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let tag_ref = format!("refs/tags/{}", tag_name);
+    let previous = if force { repo.refname_to_id(&tag_ref).ok() } else { None };
+    tracing::debug!(target: logging::GIT, "git tag{} {} {}", if force { " -f" } else { "" }, tag_name, obj.id());
+    repo.tag(tag_name, &obj, &tagger, tag_message, force)
+        .map_err(|e| Error::git(format!("Error adding git tag {}", tag_name), e))?;
+    match previous {
+        Some(previous) => transaction.record_reference(&tag_ref, Some(previous)),
+        None => transaction.record_tag(tag_name),
+    }
+    Ok(())
+}
+
+/// Creates the branch at HEAD and checks it out, so the release commit is made on it instead of the
+/// branch of HEAD. The working tree is not touched, the branch points to the HEAD commit.
+pub fn checkout_new_branch(repo: &Repository, branch: &str, transaction: &mut Transaction) -> Result<()> {
+    let head = repo.head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let previous = if repo.head_detached().unwrap_or(false) { head.target().map(|o| o.to_string()) } else { head.name().map(str::to_string) }
+        .ok_or(Error::Repository("HEAD does not point to a commit".into()))?;
+    let commit = head.peel_to_commit().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    tracing::debug!(target: logging::GIT, "git switch -c {} {}", branch, commit.id());
+    repo.branch(branch, &commit, false).map_err(|e| match e.code() {
+        git2::ErrorCode::Exists => Error::Conflict(format!("Branch '{}' already exists", branch)),
+        _ => Error::git(format!("Could not create branch '{}'", branch), e),
+    })?;
+    let ref_name = format!("refs/heads/{}", branch);
+    transaction.record_reference(&ref_name, None);
+    repo.set_head(&ref_name).map_err(|e| Error::git(format!("Could not check out branch '{}'", branch), e))?;
+    transaction.record_head(&previous);
+    Ok(())
+}
+
+/// Message of the release commit.
+pub fn commit_message(bump: &VersionBump, new_version: &Version) -> String {
+    match bump {
+        VersionBump::Fixed { .. } => format!("{COMMIT_MESSAGE_PREFIX} fixed version '{}'", new_version),
+        VersionBump::Increment { vtype } => format!("{COMMIT_MESSAGE_PREFIX} '{}' by incrementing {}", new_version, vtype),
+    }
+}
+
+/// Snapshot version like `1.3.0-dev.20240611.abc1234` from the version, the pre-release identifier,
+/// the date as `YYYYMMDD` and the short commit id. A commit id of digits only gets a `g` prefix, as
+/// numeric pre-release identifiers must not have leading zeros.
+pub fn snapshot_version(version: &Version, identifier: &str, date: &str, short_id: &str) -> Result<String> {
+    if identifier.split('.').any(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')) {
+        return Err(Error::Usage(format!("Invalid pre-release identifier '{}', only [0-9A-Za-z-] separated by dots are allowed", identifier)));
+    }
+    let short_id = if short_id.chars().all(|c| c.is_ascii_digit()) { format!("g{}", short_id) } else { short_id.to_string() };
+    Ok(format!("{}-{}.{}.{}", version, identifier, date, short_id))
+}
+
+// ********************************************************
+// ********************************************************
+/// Release of a cargo project for embedding the tool, e.g. in an xtask or a release bot. The steps
+/// are the ones of the binary: [discover](Release::discover) the cargo.toml files and the repository,
+/// [plan](Release::plan) the new version, [apply](Release::apply) it (write, commit, tag) and
+/// [push](Release::push) it. Files, commit and tag are rolled back if apply or push fail.
+///
+/// ```no_run
+/// use cargo_git_version_setter::release::{Release, VersionBump};
+/// use cargo_git_version_setter::utils::IncrementVersionPart;
+///
+/// let release = Release::new("./").tag_message("Release {{ version }}");
+/// let mut project = release.discover()?;
+/// let plan = release.plan(&project, &VersionBump::Increment { vtype: IncrementVersionPart::Minor })?;
+/// let applied = release.apply(&mut project, &plan)?;
+/// release.push(&project, applied)?;
+/// # Ok::<(), cargo_git_version_setter::error::Error>(())
+/// ```
+pub struct Release {
+    path: PathBuf,
+    cargo_file_selector: Option<CargoFile>,
+    scan_subdirs: bool,
+    tag_format: TagFormat,
+    tag_message: String,
+    remote: Option<String>,
+    push: PushSelection,
+    source: Option<(PathBuf, Box<dyn VersionSource>)>,
+    sinks: Vec<(PathBuf, Box<dyn VersionSink>)>,
+}
+
+/// The project found by [Release::discover].
+pub struct Project {
+    pub git_base_path: PathBuf,
+    pub repo: Repository,
+    /// Selected cargo.toml files with their version and content
+    pub cargo_content: HashMap<PathBuf, (Version, DocumentMut)>,
+}
+
+/// The release computed by [Release::plan], nothing is modified yet.
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    pub old_version: Version,
+    pub new_version: Version,
+    pub tag: String,
+    pub commit_message: String,
+}
+
+/// The release commit and tag created by [Release::apply]. They are rolled back if the push fails.
+pub struct Applied {
+    pub commit: Oid,
+    pub tag: String,
+    /// Files changed by the release commit
+    pub files: Vec<PathBuf>,
+    transaction: Transaction,
+}
+impl Applied {
+    /// Reverts the files, the commit and the tag, e.g. if the release is not to be pushed after all.
+    /// Returns the errors of the steps that could not be rolled back.
+    pub fn rollback(self, project: &Project) -> Vec<String> {
+        self.transaction.rollback(&project.repo)
+    }
+}
+
+/// The refs pushed by [Release::push].
+#[derive(Debug, PartialEq)]
+pub struct Pushed {
+    pub remote: String,
+    pub refs: Vec<String>,
+}
+
+impl Release {
+    /// Release of the project at `path` with the defaults of the binary: tags like `v1.2.3`,
+    /// pushing branch and tag to `origin`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Release { path: path.into(), cargo_file_selector: None, scan_subdirs: false, tag_format: TagFormat::from_prefix("v"),
+                  tag_message: "Version {{ version }}".to_string(), remote: None, push: PushSelection::Both, source: None, sinks: vec![] }
+    }
+    /// Selects the cargo.toml files if there are several, see `--cargo-file-selector`.
+    pub fn cargo_file_selector(mut self, selector: CargoFile) -> Self {
+        if matches!(selector, CargoFile::Workspace) { self.scan_subdirs = true; }
+        self.cargo_file_selector = Some(selector); self
+    }
+    pub fn scan_subdirs(mut self, scan_subdirs: bool) -> Self { self.scan_subdirs = scan_subdirs; self }
+    pub fn tag_format(mut self, tag_format: TagFormat) -> Self { self.tag_format = tag_format; self }
+    /// Template of the message of the annotated tag, e.g. with `{{ version }}` and `{{ tag }}`.
+    pub fn tag_message(mut self, tag_message: impl Into<String>) -> Self { self.tag_message = tag_message.into(); self }
+    pub fn remote(mut self, remote: impl Into<String>) -> Self { self.remote = Some(remote.into()); self }
+    pub fn push_selection(mut self, push: PushSelection) -> Self { self.push = push; self }
+    /// Takes the current version from the file (relative to the git base path) instead of the
+    /// cargo.toml files, which still get the new version.
+    pub fn version_source(mut self, file: impl Into<PathBuf>, source: impl VersionSource + 'static) -> Self {
+        self.source = Some((file.into(), Box::new(source))); self
+    }
+    /// Writes the new version to the file (relative to the git base path) as well and commits it
+    /// with the cargo.toml files. Can be given multiple times.
+    pub fn sink(mut self, file: impl Into<PathBuf>, sink: impl VersionSink + 'static) -> Self {
+        self.sinks.push((file.into(), Box::new(sink))); self
+    }
+
+    /// Finds the cargo.toml files and the git repository and reads the versions.
+    pub fn discover(&self) -> Result<Project> {
+        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(self.path.clone(), self.scan_subdirs, None, None)?;
+        if cargo_tomls.is_empty() { return Err(Error::Discovery("No cargo.toml found.".to_string())); }
+        let git_base_path = git_base_path.ok_or_else(|| Error::Discovery("Could not find git base path.".to_string()))?;
+        let cargo_tomls = filter_cargo_tomls_by_selector(cargo_tomls, &self.cargo_file_selector)?;
+        if cargo_tomls.len() > 1 && self.cargo_file_selector.is_none() {
+            return Err(Error::Usage("More than one cargo.toml found but no cargo file selector given".to_string()));
+        }
+        let repo = open_repository(&git_base_path, None)?;
+        let cargo_content = read_version_tomls(&cargo_tomls)?;
+        Ok(Project { git_base_path, repo, cargo_content })
+    }
+
+    /// Computes the new version and tag. Fails if the tree is dirty or the tag exists already.
+    pub fn plan(&self, project: &Project, bump: &VersionBump) -> Result<Plan> {
+        let base = match &self.source {
+            Some((file, source)) => Some(read_source(&project.git_base_path.join(file), source.as_ref())?),
+            None => None,
+        };
+        let new_version = compute_new_version(&project.cargo_content, bump, &self.cargo_file_selector, base.as_ref())?;
+        let old_version = base.or_else(|| project.cargo_content.values().next().map(|(v, _)| v.clone()))
+            .unwrap_or_else(|| new_version.clone());
+        match count_uncommitted_changes(&project.repo)? { 0 => {}, n => return Err(Error::DirtyTree(n)) }
+        let tag = self.tag_format.tag(&new_version);
+        if list_version_tags(&project.repo, &self.tag_format)?.contains(&tag) {
+            return Err(Error::Conflict(format!("New version already exists as git tag '{}'", tag)));
+        }
+        Ok(Plan { commit_message: commit_message(bump, &new_version), old_version, new_version, tag })
+    }
+
+    /// Writes the new version to the cargo.toml and Cargo.lock files, commits and tags them.
+    pub fn apply(&self, project: &mut Project, plan: &Plan) -> Result<Applied> {
+        let mut transaction = Transaction::new();
+        let result = (|| -> Result<(Oid, Vec<PathBuf>)> {
+            let written = write_versions(&mut project.cargo_content, &plan.new_version, &mut transaction)?;
+            let mut files: Vec<PathBuf> = project.cargo_content.keys().cloned().chain(written).collect();
+            for (file, sink) in &self.sinks {
+                let path = project.git_base_path.join(file);
+                if write_sink(&path, sink.as_ref(), &plan.new_version, &mut transaction)? { files.push(path); }
+            }
+            let oid = commit_files(&project.repo, &project.git_base_path, &files, &plan.commit_message, false, &mut transaction)?;
+            let mut ctx = template::TemplateContext::new();
+            ctx.insert("version", &plan.new_version).insert("tag", &plan.tag);
+            let tag_message = template::render(&self.tag_message, &ctx).map_err(|e| Error::Usage(format!("Invalid tag message: {}", e)))?;
+            tag_head(&project.repo, &plan.tag, &tag_message, false, &mut transaction)?;
+            Ok((oid, files))
+        })();
+        match result {
+            Ok((commit, files)) => Ok(Applied { commit, tag: plan.tag.clone(), files, transaction }),
+            Err(e) => { transaction.rollback(&project.repo); Err(e) },
+        }
+    }
+
+    /// Pushes the release branch and/or the tag. The release is rolled back if the push fails.
+    pub fn push(&self, project: &Project, applied: Applied) -> Result<Pushed> {
+        let result = (|| -> Result<Pushed> {
+            let mut remote = find_git_remote(&project.repo, self.remote.clone())?;
+            let branch = release_branch(&project.repo, None)?;
+            let tag_ref = format!("refs/tags/{}", applied.tag);
+            let refs = match (self.push, branch) {
+                (PushSelection::Tags, _) | (PushSelection::Both, None) => vec![tag_ref],
+                (PushSelection::Branch, Some(b)) => vec![format!("refs/heads/{}", b)],
+                (PushSelection::Both, Some(b)) => vec![format!("refs/heads/{}", b), tag_ref],
+                (PushSelection::Branch, None) => return Err(Error::Usage("HEAD is detached, so there is no branch to push".to_string())),
+            };
+            push_refs(&project.repo, &mut remote, &refs, vec![])?;
+            Ok(Pushed { remote: remote.name().unwrap_or("").to_string(), refs })
+        })();
+        if result.is_err() { applied.rollback(project); }
+        result
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_release {
+    use super::*;
+
+    fn init_project(dir: &Path) -> Repository {
+        let repo = Repository::init(dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Cargo.toml")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap();
+        drop(tree);
+        repo
+    }
+
+    #[test]
+    fn test_release_apply_and_push() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = Repository::init_bare(remote_dir.path()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_project(dir.path());
+        repo.remote("origin", remote_dir.path().to_str().unwrap()).unwrap();
+
+        let release = Release::new(dir.path()).tag_message("Release {{ version }}");
+        let mut project = release.discover().unwrap();
+        let plan = release.plan(&project, &VersionBump::Increment { vtype: IncrementVersionPart::Minor }).unwrap();
+        assert_eq!((plan.new_version.to_string(), plan.tag.as_str()), ("0.2.0".to_string(), "v0.2.0"));
+
+        let applied = release.apply(&mut project, &plan).unwrap();
+        assert!(fs::read_to_string(dir.path().join("Cargo.toml")).unwrap().contains("version = \"0.2.0\""));
+        let pushed = release.push(&project, applied).unwrap();
+        assert_eq!(pushed.remote, "origin");
+        let tag = remote.find_reference("refs/tags/v0.2.0").unwrap().peel_to_tag().unwrap();
+        assert_eq!(tag.message(), Some("Release 0.2.0"));
+        assert_eq!(release.plan(&project, &VersionBump::Fixed { full_version: "0.2.0".to_string() }).unwrap_err().exit_code(), 8);
+    }
+
+    #[test]
+    fn test_checkout_new_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_project(dir.path());
+        let main = repo.head().unwrap().name().unwrap().to_string();
+        let initial = repo.head().unwrap().target().unwrap();
+
+        let mut transaction = Transaction::new();
+        checkout_new_branch(&repo, "release/v0.2.0", &mut transaction).unwrap();
+        assert_eq!(repo.head().unwrap().name(), Some("refs/heads/release/v0.2.0"));
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"\nversion = \"0.2.0\"\n").unwrap();
+        commit_files(&repo, dir.path(), &[dir.path().join("Cargo.toml")], "bump", false, &mut transaction).unwrap();
+        assert_eq!(repo.refname_to_id(&main).unwrap(), initial);
+        assert_eq!(checkout_new_branch(&repo, "release/v0.2.0", &mut Transaction::new()).unwrap_err().exit_code(), 8);
+
+        assert!(transaction.rollback(&repo).is_empty());
+        assert_eq!(repo.head().unwrap().name(), Some(main.as_str()));
+        assert!(repo.find_reference("refs/heads/release/v0.2.0").is_err());
+    }
+
+    #[test]
+    fn test_write_versions_keeps_formatting() {
+        // (manifest, expected after writing 0.2.0): only the version scalar may change
+        let manifests = [
+            ("[package]\nname = \"a\"\nversion = \"0.1.0\" # bumped by cgvs\n", "version = \"0.2.0\" # bumped by cgvs\n"),
+            ("[package]\nname = \"a\"\nversion='0.1.0'\n", "version='0.2.0'\n"),
+            ("[package]\nname    = \"a\"\nversion   =   \"0.1.0\"\t# x\nedition = \"2021\"\n", "version   =   \"0.2.0\"\t# x\n"),
+            ("# header\n\n[package] # pkg\n  name = \"a\"\n  version = \"\"\"0.1.0\"\"\"\n\n[dependencies]\nserde = '1' # ser\n",
+             "  version = \"\"\"0.2.0\"\"\"\n"),
+            ("[workspace]\nmembers = []\n\n[workspace.package]\nversion = '0.1.0'  # shared\n", "version = '0.2.0'  # shared\n"),
+            ("[package]\r\nname = \"a\"\r\nversion = \"0.1.0\"\r\n\r\n[features]\r\ndefault = []\r\n", "version = \"0.2.0\"\r\n"),
+            ("[package]\nname = \"a\"\nversion = \"0.1.0\"", "version = \"0.2.0\""),
+        ];
+        for (manifest, expected_line) in manifests {
+            let dir = tempfile::tempdir().unwrap();
+            let file = dir.path().join("Cargo.toml");
+            fs::write(&file, manifest).unwrap();
+            let mut cargo_content = read_version_tomls(&vec![file.clone()]).unwrap();
+            write_versions(&mut cargo_content, &Version::try_from("0.2.0".to_string()).unwrap(), &mut Transaction::new()).unwrap();
+            let written = fs::read_to_string(&file).unwrap();
+            let old_line = manifest.split_inclusive('\n').find(|l| l.contains("0.1.0")).unwrap();
+            assert_eq!(written, manifest.replacen(old_line, expected_line, 1), "{:?}", manifest);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_version() {
+        let version = Version::try_from("1.3.0".to_string()).unwrap();
+        assert_eq!(snapshot_version(&version, "dev", "20240611", "abc1234").unwrap(), "1.3.0-dev.20240611.abc1234");
+        assert_eq!(snapshot_version(&version, "nightly", "20240611", "0123456").unwrap(), "1.3.0-nightly.20240611.g0123456");
+        assert_eq!(snapshot_version(&version, "dev_build", "20240611", "abc1234").unwrap_err().exit_code(), 2);
+    }
+
+    /// Sink of a custom format, like a third party would add one
+    struct KeyValue;
+    impl VersionSink for KeyValue {
+        fn set_version(&self, content: &str, version: &Version) -> std::result::Result<String, String> {
+            if !content.contains("version=") { return Err("no version= line".to_string()); }
+            Ok(content.lines().map(|l| if l.starts_with("version=") { format!("version={}\n", version) } else { format!("{}\n", l) }).collect())
+        }
+    }
+
+    #[test]
+    fn test_release_with_source_and_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("VERSION"), "0.5.0\n").unwrap();
+        fs::write(dir.path().join("app.properties"), "name=demo\nversion=0.5.0\n").unwrap();
+        let repo = init_project(dir.path());
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "files", &tree, &[&repo.head().unwrap().peel_to_commit().unwrap()]).unwrap();
+
+        let release = Release::new(dir.path()).version_source("VERSION", crate::sinks::SinkKind::Plain)
+            .sink("VERSION", crate::sinks::SinkKind::Plain).sink("app.properties", KeyValue);
+        let mut project = release.discover().unwrap();
+        let plan = release.plan(&project, &VersionBump::Increment { vtype: IncrementVersionPart::Patch }).unwrap();
+        assert_eq!((plan.old_version.to_string(), plan.new_version.to_string()), ("0.5.0".to_string(), "0.5.1".to_string()));
+        let applied = release.apply(&mut project, &plan).unwrap();
+        assert_eq!(applied.files.len(), 3);
+        assert_eq!(fs::read_to_string(dir.path().join("app.properties")).unwrap(), "name=demo\nversion=0.5.1\n");
+        assert!(fs::read_to_string(dir.path().join("Cargo.toml")).unwrap().contains("version = \"0.5.1\""));
+        assert_eq!(count_uncommitted_changes(&repo).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_release_rolled_back_if_push_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_project(dir.path());
+        let head = repo.head().unwrap().target().unwrap();
+
+        // no remote configured
+        let release = Release::new(dir.path());
+        let mut project = release.discover().unwrap();
+        let plan = release.plan(&project, &VersionBump::Fixed { full_version: "1.0.0".to_string() }).unwrap();
+        let applied = release.apply(&mut project, &plan).unwrap();
+        assert!(release.push(&project, applied).is_err());
+        assert_eq!(repo.head().unwrap().target().unwrap(), head);
+        assert!(repo.find_reference("refs/tags/v1.0.0").is_err());
+        assert!(fs::read_to_string(dir.path().join("Cargo.toml")).unwrap().contains("version = \"0.1.0\""));
+    }
+}
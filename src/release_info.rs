@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use git2::Oid;
+use toml_edit::{table, value, DocumentMut};
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::{write_file_atomic, Version};
+
+// ********************************************************
+// ********************************************************
+/// Format of the release info file, derived from the file name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InfoFormat {
+    Toml,
+    /// Rust module with constants, for `include!` (`.rs`)
+    Rust,
+    /// `KEY=value` lines, e.g. for docker `--env-file` or a shell `source` (`.env`)
+    Env,
+}
+impl InfoFormat {
+    pub fn from_path(path: &Path) -> InfoFormat {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if name.ends_with(".rs") { InfoFormat::Rust }
+        else if name.ends_with(".env") { InfoFormat::Env }
+        else { InfoFormat::Toml }
+    }
+}
+
+/// Renders the release info file which a build.rs or a docker build can read to embed the release
+/// identity. `commit` is the released source commit, i.e. the parent of the release commit, `date`
+/// the release time in RFC 3339 format. If several crates are released together, they are listed
+/// as well.
+///
+/// A build.rs of the project can pass the values of the TOML file on to the compiler, so they are
+/// available with `env!("RELEASE_TAG")` and friends:
+///
+/// ```
+/// # use cargo_git_version_setter::release_info::{render_release_info, InfoFormat};
+/// # use cargo_git_version_setter::utils::Version;
+/// // build.rs
+/// fn release_env(info: &str) -> Vec<(String, String)> {
+///     info.lines().filter(|l| !l.starts_with('#')).filter_map(|l| l.split_once(" = "))
+///         .map(|(key, value)| (format!("RELEASE_{}", key.to_uppercase()), value.trim_matches('"').to_string()))
+///         .collect()
+/// }
+///
+/// fn main() {
+///     println!("cargo:rerun-if-changed=.release-info.toml");
+///     let info = std::fs::read_to_string(".release-info.toml").unwrap_or_default();
+///     for (key, value) in release_env(&info) {
+///         println!("cargo:rustc-env={}={}", key, value);
+///     }
+/// #   let version = Version::try_from("1.2.0".to_string()).unwrap();
+/// #   let info = render_release_info(InfoFormat::Toml, &version, "v1.2.0", git2::Oid::zero(), "2024-03-01T00:15:00+01:00", &[]);
+/// #   let expected = [("VERSION", "1.2.0"), ("TAG", "v1.2.0"), ("COMMIT", "0000000000000000000000000000000000000000"),
+/// #                   ("DATE", "2024-03-01T00:15:00+01:00")].map(|(k, v)| (format!("RELEASE_{}", k), v.to_string()));
+/// #   assert_eq!(release_env(&info), expected);
+/// }
+/// ```
+pub fn render_release_info(format: InfoFormat, version: &Version, tag: &str, commit: Oid, date: &str,
+                           crates: &[(String, Version)]) -> String {
+    let crates = if crates.len() > 1 { crates } else { &[] };
+    match format {
+        InfoFormat::Toml => {
+            let mut doc = DocumentMut::new();
+            doc["version"] = value(version.to_string());
+            doc["tag"] = value(tag);
+            doc["commit"] = value(commit.to_string());
+            doc["date"] = value(date);
+            if !crates.is_empty() {
+                doc["crates"] = table();
+                crates.iter().for_each(|(name, v)| doc["crates"][name] = value(v.to_string()));
+            }
+            format!("# Generated by cgvs during the release, do not edit\n{}", doc)
+        }
+        InfoFormat::Rust => {
+            let mut out = format!("// Generated by cgvs during the release, do not edit\n\
+                pub const VERSION: &str = {:?};\npub const TAG: &str = {:?};\npub const COMMIT: &str = \"{}\";\npub const DATE: &str = {:?};\n",
+                version.to_string(), tag, commit, date);
+            if !crates.is_empty() {
+                let list: Vec<String> = crates.iter().map(|(name, v)| format!("({:?}, \"{}\")", name, v)).collect();
+                out.push_str(&format!("pub const CRATES: &[(&str, &str)] = &[{}];\n", list.join(", ")));
+            }
+            out
+        }
+        InfoFormat::Env => {
+            let mut out = format!("# Generated by cgvs during the release, do not edit\n\
+                RELEASE_VERSION={}\nRELEASE_TAG={}\nRELEASE_COMMIT={}\nRELEASE_DATE={}\n", version, tag, commit, date);
+            if !crates.is_empty() {
+                let list: Vec<String> = crates.iter().map(|(name, v)| format!("{}@{}", name, v)).collect();
+                out.push_str(&format!("RELEASE_CRATES={}\n", list.join(",")));
+            }
+            out
+        }
+    }
+}
+
+/// Writes the release info file atomically in the format given by its name. The file is backed up
+/// in the transaction before.
+pub fn write_release_info(path: &PathBuf, version: &Version, tag: &str, commit: Oid, date: &str, crates: &[(String, Version)],
+                          transaction: &mut Transaction) -> Result<()> {
+    transaction.backup_file(path)?;
+    write_file_atomic(path, render_release_info(InfoFormat::from_path(path), version, tag, commit, date, crates).as_bytes())
+        .map_err(|e| Error::io(format!("Failed to write to '{}'", path.display()), e))?;
+    tracing::info!("Updated release info: {}", path.display());
+    Ok(())
+}
+
+/// Appends the crates released together to the tag message, so a tag viewed in isolation shows the
+/// full release set. The message is unchanged for a single crate.
+pub fn annotate_release_set(tag_message: &str, crates: &[(String, Version)]) -> String {
+    if crates.len() < 2 { return tag_message.to_string(); }
+    let list: Vec<String> = crates.iter().map(|(name, v)| format!("- {} {}", name, v)).collect();
+    format!("{}\n\nReleased together:\n{}\n", tag_message.trim_end(), list.join("\n"))
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_release_info {
+    use super::*;
+
+    #[test]
+    fn test_render_release_info() {
+        let version = Version::try_from("1.2.0".to_string()).unwrap();
+        let commit = Oid::from_str("1234567890abcdef1234567890abcdef12345678").unwrap();
+        let date = "2024-03-01T00:15:00Z";
+        assert_eq!(render_release_info(InfoFormat::Toml, &version, "v1.2.0", commit, date, &[]), "# Generated by cgvs during the release, do not edit\n\
+            version = \"1.2.0\"\ntag = \"v1.2.0\"\ncommit = \"1234567890abcdef1234567890abcdef12345678\"\ndate = \"2024-03-01T00:15:00Z\"\n");
+
+        let crates = vec![("core".to_string(), version.clone()), ("cli".to_string(), version.clone())];
+        assert!(render_release_info(InfoFormat::Toml, &version, "v1.2.0", commit, date, &crates)
+            .ends_with("\n[crates]\ncore = \"1.2.0\"\ncli = \"1.2.0\"\n"));
+        assert_eq!(annotate_release_set("Release", &crates), "Release\n\nReleased together:\n- core 1.2.0\n- cli 1.2.0\n");
+        assert_eq!(annotate_release_set("Release", &crates[..1]), "Release");
+    }
+
+    #[test]
+    fn test_render_release_info_formats() {
+        let version = Version::try_from("1.2.0".to_string()).unwrap();
+        let commit = Oid::from_str("1234567890abcdef1234567890abcdef12345678").unwrap();
+        let crates = vec![("core".to_string(), version.clone()), ("cli".to_string(), version.clone())];
+        assert_eq!(InfoFormat::from_path(Path::new("src/version_info.rs")), InfoFormat::Rust);
+        assert_eq!(InfoFormat::from_path(Path::new("release.env")), InfoFormat::Env);
+        assert_eq!(InfoFormat::from_path(Path::new(".env")), InfoFormat::Env);
+        assert_eq!(render_release_info(InfoFormat::Rust, &version, "v1.2.0", commit, "2024-03-01", &crates),
+            "// Generated by cgvs during the release, do not edit\npub const VERSION: &str = \"1.2.0\";\npub const TAG: &str = \"v1.2.0\";\n\
+            pub const COMMIT: &str = \"1234567890abcdef1234567890abcdef12345678\";\npub const DATE: &str = \"2024-03-01\";\n\
+            pub const CRATES: &[(&str, &str)] = &[(\"core\", \"1.2.0\"), (\"cli\", \"1.2.0\")];\n");
+        assert_eq!(render_release_info(InfoFormat::Env, &version, "v1.2.0", commit, "2024-03-01", &crates[..1]),
+            "# Generated by cgvs during the release, do not edit\nRELEASE_VERSION=1.2.0\nRELEASE_TAG=v1.2.0\n\
+            RELEASE_COMMIT=1234567890abcdef1234567890abcdef12345678\nRELEASE_DATE=2024-03-01\n");
+    }
+}
@@ -0,0 +1,121 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use regex::{Captures, Regex};
+use crate::config::Replacement;
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::{write_file_atomic, Version};
+
+// ********************************************************
+// ********************************************************
+/// Fills `{version}`, `{prev_version}` and `{tag}` into the replacement template.
+pub fn render_replacement(template: &str, prev_version: &Version, version: &Version, tag: &str) -> String {
+    template.replace("{version}", &version.to_string()).replace("{prev_version}", &prev_version.to_string())
+        .replace("{tag}", tag)
+}
+
+/// Replaces all matches of the regex in the content, `$1` or `${name}` in the replacement refer to
+/// the groups of the match. Returns None if the regex does not match.
+pub fn replace_all(content: &str, search: &str, replacement: &str) -> Result<Option<String>> {
+    let re = Regex::new(search).map_err(|e| Error::Usage(format!("Invalid replacement regex '{}': {}", search, e)))?;
+    if !re.is_match(content) { return Ok(None); }
+    Ok(Some(re.replace_all(content, replacement).into_owned()))
+}
+
+/// Applies the replacements of the config to their files (relative to the git base path) and
+/// returns the changed files. A regex without match is an error, so an outdated pattern is noticed
+/// before the release. Each file is backed up in the transaction and written once.
+pub fn apply_replacements(git_base_path: &Path, replacements: &[Replacement], prev_version: &Version, version: &Version,
+                          tag: &str, transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<&str> = vec![];
+    for r in replacements {
+        if !files.contains(&r.file.as_str()) { files.push(&r.file); }
+    }
+
+    let mut changed = vec![];
+    for file in files {
+        let path = git_base_path.join(file);
+        let original = fs::read_to_string(&path).map_err(|e| Error::io(format!("Could not read '{}'", path.display()), e))?;
+        let mut content = original.clone();
+        for r in replacements.iter().filter(|r| r.file == file) {
+            let replacement = render_replacement(&r.replace, prev_version, version, tag);
+            content = replace_all(&content, &r.search, &replacement)?.ok_or_else(|| Error::Usage(
+                format!("Replacement '{}' does not match in '{}'", r.search, path.display())))?;
+        }
+        if content == original { continue; }
+        transaction.backup_file(&path)?;
+        write_file_atomic(&path, content.as_bytes()).map_err(|e| Error::io(format!("Failed to write to '{}'", path.display()), e))?;
+        tracing::info!("Applied replacements to {}", path.display());
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+/// Updates the version of the named crates in dependency snippets (`name = "1.2"`, also with an
+/// inline table and a `^`/`~`/`=` requirement) and in docs.rs and crates.io URLs. Other crates are
+/// left alone and a snippet keeps its number of version components.
+pub fn update_readme_versions(content: &str, names: &[String], version: &Version) -> String {
+    let version = version.to_string();
+    let mut content = content.to_string();
+    for name in names {
+        let name = regex::escape(name);
+        let snippet = Regex::new(&format!(r#"(?m)^(\s*{name}\s*=\s*(?:\{{[^}}\n]*?\bversion\s*=\s*)?"[=^~]?)(\d+(?:\.\d+){{0,2}})""#)).unwrap();
+        content = snippet.replace_all(&content, |c: &Captures| {
+            let parts = c[2].split('.').count();
+            format!("{}{}\"", &c[1], version.split('.').take(parts).collect::<Vec<_>>().join("."))
+        }).into_owned();
+        let url = Regex::new(&format!(r"((?:docs\.rs/(?:crate/)?|crates\.io/crates/){name}/|docs\.rs/{name}/badge\.svg\?version=)\d+\.\d+\.\d+\b")).unwrap();
+        content = url.replace_all(&content, format!("${{1}}{}", version)).into_owned();
+    }
+    content
+}
+
+/// Updates the crate versions in the README (`--update-readme`), see `update_readme_versions`.
+/// Returns whether the file changed, it is backed up in the transaction before it is written.
+pub fn update_readme(path: &Path, names: &[String], version: &Version, transaction: &mut Transaction) -> Result<bool> {
+    let original = fs::read_to_string(path).map_err(|e| Error::io(format!("Could not read '{}'", path.display()), e))?;
+    let content = update_readme_versions(&original, names, version);
+    if content == original { return Ok(false); }
+    transaction.backup_file(&path.to_path_buf())?;
+    write_file_atomic(path, content.as_bytes()).map_err(|e| Error::io(format!("Failed to write to '{}'", path.display()), e))?;
+    tracing::info!("Updated crate versions in {}", path.display());
+    Ok(true)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_replacements {
+    use super::*;
+
+    fn v(s: &str) -> Version { Version::try_from(s.to_string()).unwrap() }
+
+    #[test]
+    fn test_apply_replacements() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("README.md"), "cgvs = \"0.1.0\"\nsee v0.1.0\n").unwrap();
+        let replacements = vec![
+            Replacement { file: "README.md".to_string(), search: r#"cgvs = "[^"]+""#.to_string(), replace: r#"cgvs = "{version}""#.to_string() },
+            Replacement { file: "README.md".to_string(), search: r"see (v)\S+".to_string(), replace: "see ${1}{version} (was {prev_version})".to_string() },
+        ];
+        let mut transaction = Transaction::new();
+        let changed = apply_replacements(dir.path(), &replacements, &v("0.1.0"), &v("0.2.0"), "v0.2.0", &mut transaction).unwrap();
+        assert_eq!(changed, vec![dir.path().join("README.md")]);
+        assert_eq!(fs::read_to_string(dir.path().join("README.md")).unwrap(), "cgvs = \"0.2.0\"\nsee v0.2.0 (was 0.1.0)\n");
+
+        let stale = vec![Replacement { file: "README.md".to_string(), search: "nothing".to_string(), replace: "{tag}".to_string() }];
+        assert!(apply_replacements(dir.path(), &stale, &v("0.1.0"), &v("0.2.0"), "v0.2.0", &mut transaction).unwrap_err()
+            .to_string().starts_with("Replacement 'nothing' does not match"));
+    }
+
+    #[test]
+    fn test_update_readme_versions() {
+        let readme = "[![docs](https://docs.rs/my-crate/badge.svg?version=0.1.0)](https://docs.rs/my-crate/0.1.0/my_crate/)\n\
+            ```toml\nmy-crate = \"0.1\"\nmy-crate = { version = \"^0.1.0\", features = [\"x\"] }\n\
+            my-crate-macros = \"0.1\"\nserde = \"1.0\"\n```\nhttps://crates.io/crates/my-crate/0.1.0\n";
+        assert_eq!(update_readme_versions(readme, &["my-crate".to_string()], &v("0.2.3")),
+            "[![docs](https://docs.rs/my-crate/badge.svg?version=0.2.3)](https://docs.rs/my-crate/0.2.3/my_crate/)\n\
+            ```toml\nmy-crate = \"0.2\"\nmy-crate = { version = \"^0.2.3\", features = [\"x\"] }\n\
+            my-crate-macros = \"0.1\"\nserde = \"1.0\"\n```\nhttps://crates.io/crates/my-crate/0.2.3\n");
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+use toml_edit::DocumentMut;
+use crate::config::Sink;
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::{keep_line_endings, manifest_version, set_string_item, write_file_atomic, ManifestVersion, Version};
+
+// ********************************************************
+// ********************************************************
+/// Reads the version from the content of a file. Implemented by [CargoToml] and the [SinkKind]s,
+/// other file formats can be added by implementing it, see
+/// [Release::version_source](crate::release::Release::version_source).
+pub trait VersionSource {
+    /// Returns the version, or a description of why it was not found.
+    fn read_version(&self, content: &str) -> std::result::Result<Version, String>;
+}
+
+/// Writes the version to the content of a file. Implemented by [CargoToml] and the [SinkKind]s,
+/// other file formats can be added by implementing it, see [Release::sink](crate::release::Release::sink).
+pub trait VersionSink {
+    /// Returns the content with the version set, or a description of why the version was not found.
+    fn set_version(&self, content: &str, version: &Version) -> std::result::Result<String, String>;
+    /// Whether a missing file is created from empty content instead of failing.
+    fn creates_missing_file(&self) -> bool { false }
+}
+
+/// `package.version` of a cargo.toml or `workspace.package.version` of a workspace root, the default
+/// source and sink of the version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CargoToml;
+impl VersionSource for CargoToml {
+    fn read_version(&self, content: &str) -> std::result::Result<Version, String> {
+        let doc = content.parse::<DocumentMut>().map_err(|e| format!("could not parse toml: {}", e))?;
+        match manifest_version(&doc) {
+            ManifestVersion::Package(v) | ManifestVersion::Workspace(v) => Ok(Version::try_from(v)?),
+            ManifestVersion::Inherited => Err("the version is inherited from the workspace".to_string()),
+            ManifestVersion::NotAString(found) => Err(format!("the version has to be a string, found {}", found)),
+            _ => Err("no package.version or workspace.package.version found".to_string()),
+        }
+    }
+}
+impl VersionSink for CargoToml {
+    fn set_version(&self, content: &str, version: &Version) -> std::result::Result<String, String> {
+        let mut doc = content.parse::<DocumentMut>().map_err(|e| format!("could not parse toml: {}", e))?;
+        match manifest_version(&doc) {
+            ManifestVersion::Package(_) => set_string_item(&mut doc["package"]["version"], &version.to_string()),
+            ManifestVersion::Workspace(_) => set_string_item(&mut doc["workspace"]["package"]["version"], &version.to_string()),
+            ManifestVersion::Inherited => return Err("the version is inherited from the workspace".to_string()),
+            _ => return Err("no package.version or workspace.package.version found".to_string()),
+        }
+        Ok(doc.to_string())
+    }
+}
+
+/// Manifest of another ecosystem which gets the version of the crates, e.g. a JS binding or a
+/// Helm chart released together with the crate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SinkKind {
+    /// `version` of a npm package.json
+    PackageJson,
+    /// `[project].version` (PEP 621) or `[tool.poetry].version` of a pyproject.toml
+    Pyproject,
+    /// `version` and, if present, `appVersion` of a Helm Chart.yaml
+    HelmChart,
+    /// File only containing the version, e.g. VERSION. It is created if it does not exist
+    Plain,
+}
+impl SinkKind {
+    /// Parses the `kind` of a sink in the config, the file names are accepted as well.
+    pub fn parse(kind: &str) -> Option<SinkKind> {
+        match kind {
+            "npm" | "package.json" => Some(SinkKind::PackageJson),
+            "python" | "pyproject.toml" => Some(SinkKind::Pyproject),
+            "helm" | "Chart.yaml" => Some(SinkKind::HelmChart),
+            "plain" | "VERSION" => Some(SinkKind::Plain),
+            _ => None,
+        }
+    }
+    /// Derives the kind from the file name, if the config does not give it.
+    pub fn from_file_name(file: &str) -> Option<SinkKind> {
+        SinkKind::parse(Path::new(file).file_name()?.to_str()?)
+    }
+}
+impl VersionSink for SinkKind {
+    fn set_version(&self, content: &str, version: &Version) -> std::result::Result<String, String> {
+        match self {
+            SinkKind::PackageJson => set_package_json_version(content, &version.to_string()),
+            SinkKind::Pyproject => set_pyproject_version(content, &version.to_string()),
+            SinkKind::HelmChart => set_chart_version(content, &version.to_string()),
+            SinkKind::Plain => Ok(format!("{}\n", version)),
+        }
+    }
+    fn creates_missing_file(&self) -> bool { *self == SinkKind::Plain }
+}
+impl VersionSource for SinkKind {
+    fn read_version(&self, content: &str) -> std::result::Result<Version, String> {
+        let version = match self {
+            SinkKind::PackageJson => {
+                let (start, end) = package_json_version_span(content)?;
+                content[start + 1..end].to_string()
+            },
+            SinkKind::Pyproject => {
+                let doc = content.parse::<DocumentMut>().map_err(|e| format!("could not parse toml: {}", e))?;
+                doc.get("project").and_then(|p| p.get("version")).or(doc.get("tool").and_then(|t| t.get("poetry")).and_then(|p| p.get("version")))
+                    .and_then(|v| v.as_str()).ok_or("no [project] or [tool.poetry] version found")?.to_string()
+            },
+            SinkKind::HelmChart => Regex::new(r#"(?m)^version:[ \t]*["']?([^"'\s#]+)"#).unwrap().captures(content)
+                .map(|c| c[1].to_string()).ok_or("no top-level version found")?,
+            SinkKind::Plain => content.trim().to_string(),
+        };
+        Ok(Version::try_from(version)?)
+    }
+}
+
+/// Returns the index of the quote closing the JSON string starting at `start`.
+fn json_string_end(bytes: &[u8], start: usize) -> std::result::Result<usize, String> {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Ok(i),
+            _ => i += 1,
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Returns the indices of the quotes of the string value of the top-level `version` key.
+fn package_json_version_span(content: &str) -> std::result::Result<(usize, usize), String> {
+    let bytes = content.as_bytes();
+    let (mut depth, mut i) = (0, 0);
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            b'"' => {
+                let end = json_string_end(bytes, i)?;
+                let mut next = end + 1;
+                while next < bytes.len() && bytes[next].is_ascii_whitespace() { next += 1; }
+                // a key is followed by a colon, a value of "version" is not
+                if depth == 1 && &content[i + 1..end] == "version" && bytes.get(next) == Some(&b':') {
+                    let mut start = next + 1;
+                    while start < bytes.len() && bytes[start].is_ascii_whitespace() { start += 1; }
+                    if bytes.get(start) != Some(&b'"') { return Err("\"version\" is not a string".to_string()); }
+                    return Ok((start, json_string_end(bytes, start)?));
+                }
+                i = end;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Err("no top-level \"version\" found".to_string())
+}
+
+/// Replaces the string value of the top-level `version` key, the rest of the file is kept as is.
+pub fn set_package_json_version(content: &str, version: &str) -> std::result::Result<String, String> {
+    let (start, end) = package_json_version_span(content)?;
+    Ok(format!("{}\"{}\"{}", &content[..start], version, &content[end + 1..]))
+}
+
+/// Sets `[project].version` or, for poetry, `[tool.poetry].version`. A dynamic version is not supported.
+pub fn set_pyproject_version(content: &str, version: &str) -> std::result::Result<String, String> {
+    let mut doc = content.parse::<DocumentMut>().map_err(|e| format!("could not parse toml: {}", e))?;
+    if doc.get("project").and_then(|p| p.get("version")).is_some_and(|v| v.is_str()) {
+        set_string_item(&mut doc["project"]["version"], version);
+    } else if doc.get("tool").and_then(|t| t.get("poetry")).and_then(|p| p.get("version")).is_some_and(|v| v.is_str()) {
+        set_string_item(&mut doc["tool"]["poetry"]["version"], version);
+    } else {
+        return Err("no [project] or [tool.poetry] version found (a dynamic version is not supported)".to_string());
+    }
+    Ok(doc.to_string())
+}
+
+/// Sets the top-level `version` and, if present, `appVersion`, keeping quotes and comments.
+pub fn set_chart_version(content: &str, version: &str) -> std::result::Result<String, String> {
+    let re = Regex::new(r#"(?m)^((?:version|appVersion):[ \t]*)(["']?)[^"'\s#]+(["']?)"#).unwrap();
+    if !re.captures_iter(content).any(|c| c[1].starts_with("version")) {
+        return Err("no top-level version found".to_string());
+    }
+    Ok(re.replace_all(content, format!("${{1}}${{2}}{}${{3}}", version)).into_owned())
+}
+
+/// Reads the version of the file with the source.
+pub fn read_source(path: &Path, source: &dyn VersionSource) -> Result<Version> {
+    let content = fs::read_to_string(path).map_err(|e| Error::io(format!("Could not read '{}'", path.display()), e))?;
+    source.read_version(&content).map_err(|e| Error::Manifest(format!("Could not read version of '{}': {}", path.display(), e)))
+}
+
+/// Writes the version to the file with the sink and returns whether it changed. The file is backed
+/// up in the transaction before it is written.
+pub fn write_sink(path: &Path, sink: &dyn VersionSink, version: &Version, transaction: &mut Transaction) -> Result<bool> {
+    let original = if sink.creates_missing_file() && !path.exists() { String::new() }
+        else { fs::read_to_string(path).map_err(|e| Error::io(format!("Could not read '{}'", path.display()), e))? };
+    let content = sink.set_version(&original, version)
+        .map(|c| keep_line_endings(&original, &c))
+        .map_err(|e| Error::Manifest(format!("Could not set version in '{}': {}", path.display(), e)))?;
+    if content == original { return Ok(false); }
+    transaction.backup_file(&path.to_path_buf())?;
+    write_file_atomic(path, content.as_bytes()).map_err(|e| Error::io(format!("Failed to write to '{}'", path.display()), e))?;
+    tracing::info!("Updated version in {}", path.display());
+    Ok(true)
+}
+
+/// Writes the version to the sink files of the config (relative to the git base path) and returns
+/// the changed files.
+pub fn write_sinks(git_base_path: &Path, sinks: &[Sink], version: &Version, transaction: &mut Transaction) -> Result<Vec<PathBuf>> {
+    let mut changed = vec![];
+    for Sink { kind, file } in sinks {
+        let path = git_base_path.join(file);
+        if write_sink(&path, kind, version, transaction)? { changed.push(path); }
+    }
+    Ok(changed)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_sinks {
+    use super::*;
+
+    #[test]
+    fn test_set_package_json_version() {
+        let json = "{\n  \"name\": \"x\",\n  \"dependencies\": { \"version\": \"9.9.9\" },\n  \"keywords\": [\"version\"],\n  \"version\" : \"0.1.0\"\n}\n";
+        assert_eq!(set_package_json_version(json, "0.2.0").unwrap(),
+                   "{\n  \"name\": \"x\",\n  \"dependencies\": { \"version\": \"9.9.9\" },\n  \"keywords\": [\"version\"],\n  \"version\" : \"0.2.0\"\n}\n");
+        assert!(set_package_json_version("{\"name\": \"a\\\"version\"}", "0.2.0").is_err());
+        assert!(set_package_json_version("{\"version\": 1}", "0.2.0").is_err());
+    }
+
+    #[test]
+    fn test_set_pyproject_and_chart_version() {
+        assert_eq!(set_pyproject_version("[project]\nname = \"x\"\nversion = \"0.1.0\"\n", "0.2.0").unwrap(),
+                   "[project]\nname = \"x\"\nversion = \"0.2.0\"\n");
+        assert_eq!(set_pyproject_version("[tool.poetry]\nversion = \"0.1.0\"\n", "0.2.0").unwrap(), "[tool.poetry]\nversion = \"0.2.0\"\n");
+        assert!(set_pyproject_version("[project]\ndynamic = [\"version\"]\n", "0.2.0").is_err());
+
+        let chart = "apiVersion: v2\nname: x\nversion: 0.1.0 # chart\nappVersion: \"0.1.0\"\ndependencies:\n  - version: 1.0.0\n";
+        assert_eq!(set_chart_version(chart, "0.2.0").unwrap(),
+                   "apiVersion: v2\nname: x\nversion: 0.2.0 # chart\nappVersion: \"0.2.0\"\ndependencies:\n  - version: 1.0.0\n");
+        assert!(set_chart_version("name: x\n", "0.2.0").is_err());
+        assert_eq!(SinkKind::from_file_name("charts/x/Chart.yaml"), Some(SinkKind::HelmChart));
+    }
+
+    #[test]
+    fn test_read_version() {
+        let v = |s: &str| Version::try_from(s.to_string()).unwrap();
+        assert_eq!(SinkKind::PackageJson.read_version("{\"deps\": {\"version\": \"9.9.9\"}, \"version\": \"0.3.1\"}").unwrap(), v("0.3.1"));
+        assert_eq!(SinkKind::Pyproject.read_version("[tool.poetry]\nversion = \"0.3.1\"\n").unwrap(), v("0.3.1"));
+        assert_eq!(SinkKind::HelmChart.read_version("name: x\nversion: '0.3.1'\n").unwrap(), v("0.3.1"));
+        assert_eq!(SinkKind::Plain.read_version("0.3.1\n").unwrap(), v("0.3.1"));
+        assert!(SinkKind::Plain.read_version("").is_err());
+
+        let toml = "[package]\nname = \"x\"\nversion = \"0.3.1\"\n";
+        assert_eq!(CargoToml.read_version(toml).unwrap(), v("0.3.1"));
+        assert_eq!(CargoToml.set_version(toml, &v("0.4.0")).unwrap(), "[package]\nname = \"x\"\nversion = \"0.4.0\"\n");
+        assert!(CargoToml.set_version("[package]\nversion.workspace = true\n", &v("0.4.0")).is_err());
+    }
+
+    #[test]
+    fn test_write_plain_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let sinks = [Sink { kind: SinkKind::Plain, file: "VERSION".to_string() }];
+        let mut transaction = Transaction::new();
+        let version = Version::try_from("1.2.3".to_string()).unwrap();
+        assert_eq!(write_sinks(dir.path(), &sinks, &version, &mut transaction).unwrap(), vec![dir.path().join("VERSION")]);
+        assert_eq!(fs::read_to_string(dir.path().join("VERSION")).unwrap(), "1.2.3\n");
+        assert!(write_sinks(dir.path(), &sinks, &version, &mut transaction).unwrap().is_empty());
+    }
+}
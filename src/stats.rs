@@ -0,0 +1,96 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use crate::error::Error;
+use crate::json::{JsonValue, SCHEMA_VERSION};
+use crate::utils::print_warn;
+
+// ********************************************************
+// ********************************************************
+/// Collects the outcome and duration of each step of a run. Only anonymized data is recorded
+/// (command, step names, outcome, error kind and durations), no paths, versions or remote urls.
+struct Recorder {
+    file: PathBuf,
+    command: String,
+    started: Instant,
+    steps: Vec<(String, Instant, Option<u128>)>,
+}
+impl Recorder {
+    fn close_running_step(&mut self) {
+        if let Some((_, started, duration)) = self.steps.last_mut()
+            && duration.is_none() {
+            *duration = Some(started.elapsed().as_millis());
+        }
+    }
+
+    fn record(mut self, error: Option<&Error>) -> JsonValue {
+        let failed_step = error.and_then(|_| self.steps.last().map(|(name, _, _)| name.clone()));
+        self.close_running_step();
+        let steps: Vec<_> = self.steps.iter().map(|(name, _, duration)| JsonValue::object()
+            .with("name", name.as_str())
+            .with("outcome", if failed_step.as_ref() == Some(name) { "failure" } else { "success" })
+            .with("duration_ms", duration.unwrap_or(0) as i64)).collect();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        JsonValue::object()
+            .with("schema_version", SCHEMA_VERSION)
+            .with("timestamp", timestamp)
+            .with("tool_version", env!("CARGO_PKG_VERSION"))
+            .with("command", self.command.as_str())
+            .with("outcome", if error.is_some() { "failure" } else { "success" })
+            .with("error_kind", error.map(|e| e.kind()))
+            .with("exit_code", error.map(|e| e.exit_code() as i64).unwrap_or(0))
+            .with("duration_ms", self.started.elapsed().as_millis() as i64)
+            .with("steps", steps)
+    }
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+/// Starts recording usage statistics which are appended to the file at the end of the run.
+pub fn init(file: Option<PathBuf>, command: &str) {
+    let Some(file) = file else { return; };
+    if let Ok(mut r) = RECORDER.lock() {
+        *r = Some(Recorder { file, command: command.to_string(), started: Instant::now(), steps: vec![] });
+    }
+}
+
+/// Finishes the running step successfully and starts the next one.
+pub fn step(name: &str) {
+    if let Ok(mut r) = RECORDER.lock() && let Some(recorder) = r.as_mut() {
+        recorder.close_running_step();
+        recorder.steps.push((name.to_string(), Instant::now(), None));
+    }
+}
+
+/// Appends the record of the run as a single JSON line to the statistics file. The running step is
+/// marked as failed if an error is given. Does nothing if recording was not started.
+pub fn finish(error: Option<&Error>) {
+    let Some(recorder) = RECORDER.lock().ok().and_then(|mut r| r.take()) else { return; };
+    let file = recorder.file.clone();
+    let line = recorder.record(error);
+    let r = OpenOptions::new().create(true).append(true).open(&file)
+        .and_then(|mut f| writeln!(f, "{}", line));
+    if let Err(e) = r {
+        print_warn(format!("Could not write usage statistics to '{}': {}", file.display(), e));
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_stats {
+    use super::*;
+
+    #[test]
+    fn test_record_marks_failed_step() {
+        let mut recorder = Recorder { file: PathBuf::new(), command: "increment".to_string(), started: Instant::now(), steps: vec![] };
+        recorder.steps.push(("analyse".to_string(), Instant::now(), Some(5)));
+        recorder.steps.push(("push".to_string(), Instant::now(), None));
+        let record = recorder.record(Some(&Error::Conflict("exists".to_string()))).to_string();
+        assert!(record.contains(r#""command":"increment","outcome":"failure","error_kind":"conflict","exit_code":8"#));
+        assert!(record.contains(r#"{"name":"analyse","outcome":"success","duration_ms":5}"#));
+        assert!(record.contains(r#"{"name":"push","outcome":"failure""#));
+    }
+}
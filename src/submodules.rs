@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use git2::{Oid, Repository};
+use toml_edit::DocumentMut;
+use crate::error::{Error, Result};
+use crate::release::{commit_files, compute_new_version, tag_head, write_versions, VersionBump};
+use crate::transaction::Transaction;
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// Release of a submodule with its own cargo.toml, done before the release of the superproject.
+/// The modifications of the submodule are recorded in its own transaction.
+pub struct NestedRelease {
+    /// Path of the submodule relative to the git base path of the superproject
+    pub path: PathBuf,
+    pub repo: Repository,
+    pub cargo_content: HashMap<PathBuf, (Version, DocumentMut)>,
+    pub old_version: Version,
+    pub new_version: Version,
+    pub tag: String,
+    /// Branch of the submodule pushed with the tag, none if its HEAD is detached
+    pub branch: Option<String>,
+    pub transaction: Transaction,
+}
+impl NestedRelease {
+    pub fn workdir(&self) -> PathBuf {
+        self.repo.workdir().map(Path::to_path_buf).unwrap_or_default()
+    }
+}
+
+/// Paths of all submodules, relative to the git base path.
+pub fn submodule_paths(repo: &Repository) -> Result<Vec<PathBuf>> {
+    Ok(repo.submodules().map_err(|e| Error::git("Could not list submodules", e))?.iter().map(|sm| sm.path().to_path_buf()).collect())
+}
+
+/// Finds the submodules with a cargo.toml in their root and plans their release with the bump of
+/// the superproject. Fails if a submodule is not initialized, has uncommitted changes or already
+/// has the new version tag.
+pub fn plan_nested_releases(repo: &Repository, bump: &VersionBump, tag_format: &TagFormat) -> Result<Vec<NestedRelease>> {
+    let mut nested = vec![];
+    for sm in repo.submodules().map_err(|e| Error::git("Could not list submodules", e))? {
+        let path = sm.path().to_path_buf();
+        let sub_repo = sm.open().map_err(|e| Error::git(format!("Submodule '{}' is not initialized", path.display()), e))?;
+        let cargo_toml = sub_repo.workdir().map(|w| w.join("Cargo.toml")).filter(|f| f.is_file());
+        let Some(cargo_toml) = cargo_toml else {
+            tracing::debug!(target: crate::logging::DISCOVERY, "Submodule {} has no Cargo.toml", path.display());
+            continue;
+        };
+        match count_uncommitted_changes(&sub_repo)? {
+            0 => {},
+            n => return Err(Error::Repository(format!("Submodule '{}' has {} uncommitted change(s)", path.display(), n))),
+        }
+        let cargo_content = read_version_tomls(&vec![cargo_toml])?;
+        let old_version = cargo_content.values().next().map(|(v, _)| v.clone())
+            .ok_or_else(|| Error::Manifest(format!("No version in the Cargo.toml of submodule '{}'", path.display())))?;
+        let new_version = compute_new_version(&cargo_content, bump, &None, None)?;
+        let tag = tag_format.tag(&new_version);
+        if list_version_tags(&sub_repo, tag_format)?.contains(&tag) {
+            return Err(Error::Conflict(format!("Version tag '{}' already exists in submodule '{}'", tag, path.display())));
+        }
+        let branch = release_branch(&sub_repo, sm.branch())?.filter(|_| !sub_repo.head_detached().unwrap_or(true));
+        nested.push(NestedRelease { path, repo: sub_repo, cargo_content, old_version, new_version, tag, branch,
+                                    transaction: Transaction::new() });
+    }
+    Ok(nested)
+}
+
+/// Writes the new version to the cargo.toml (and Cargo.lock) of the submodule, commits and tags it.
+pub fn release_nested(nested: &mut NestedRelease, commit_message: &str, tag_message: &str) -> Result<Oid> {
+    let workdir = nested.workdir();
+    let written = write_versions(&mut nested.cargo_content, &nested.new_version, &mut nested.transaction)?;
+    let files: Vec<PathBuf> = nested.cargo_content.keys().cloned().chain(written).collect();
+    let oid = commit_files(&nested.repo, &workdir, &files, commit_message, false, &mut nested.transaction)?;
+    tag_head(&nested.repo, &nested.tag, tag_message, false, &mut nested.transaction)?;
+    Ok(oid)
+}
+
+/// Pushes the tag and, if the submodule is on a branch, the branch of the submodule to its remote.
+/// Returns the pushed refs.
+pub fn push_nested(nested: &NestedRelease) -> Result<Vec<String>> {
+    let mut remote = find_git_remote(&nested.repo, None)?;
+    let mut refs: Vec<String> = nested.branch.iter().map(|b| format!("refs/heads/{}", b)).collect();
+    refs.push(format!("refs/tags/{}", nested.tag));
+    push_refs(&nested.repo, &mut remote, &refs, vec![])?;
+    Ok(refs)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_submodules {
+    use super::*;
+    use std::fs;
+    use crate::utils::IncrementVersionPart;
+
+    fn commit_all(repo: &Repository, message: &str) -> Oid {
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let parents: Vec<_> = repo.head().ok().and_then(|h| h.peel_to_commit().ok()).into_iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_release_nested() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = Repository::init(dir.path().join("lib")).unwrap();
+        fs::write(dir.path().join("lib/Cargo.toml"), "[package]\nname = \"lib\"\nversion = \"1.4.0\"\n").unwrap();
+        commit_all(&lib, "lib");
+
+        let repo = Repository::init(dir.path().join("app")).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let url = format!("file://{}", dir.path().join("lib").display());
+        let mut sm = repo.submodule(&url, Path::new("lib"), true).unwrap();
+        sm.clone(None).unwrap();
+        sm.add_finalize().unwrap();
+        commit_all(&repo, "app");
+
+        let bump = VersionBump::Increment { vtype: IncrementVersionPart::Minor };
+        let mut nested = plan_nested_releases(&repo, &bump, &TagFormat::from_prefix("v")).unwrap();
+        assert_eq!(nested.len(), 1);
+        assert_eq!((nested[0].path.as_path(), nested[0].new_version.to_string()), (Path::new("lib"), "1.5.0".to_string()));
+
+        let mut config = nested[0].repo.config().unwrap();
+        config.set_str("user.name", "test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let oid = release_nested(&mut nested[0], "bump", "Release 1.5.0").unwrap();
+        assert_eq!(nested[0].repo.revparse_single("refs/tags/v1.5.0").unwrap().peel_to_commit().unwrap().id(), oid);
+        assert!(fs::read_to_string(dir.path().join("app/lib/Cargo.toml")).unwrap().contains("1.5.0"));
+
+        // a tag of the new version in the submodule conflicts
+        assert_eq!(plan_nested_releases(&repo, &VersionBump::Fixed { full_version: "1.5.0".to_string() },
+                                        &TagFormat::from_prefix("v")).err().unwrap().exit_code(), 8);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use regex::Regex;
+
+// ********************************************************
+// ********************************************************
+/// Value which can be referenced from a template.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Str(String),
+    List(Vec<BTreeMap<String, String>>),
+}
+
+/// Variables available to a template, e.g. the release context.
+#[derive(Clone, Default, Debug)]
+pub struct TemplateContext {
+    values: BTreeMap<String, Value>,
+}
+impl TemplateContext {
+    pub fn new() -> Self { Self::default() }
+    pub fn insert(&mut self, name: &str, value: impl ToString) -> &mut Self {
+        self.values.insert(name.to_string(), Value::Str(value.to_string()));
+        self
+    }
+    pub fn insert_list(&mut self, name: &str, list: Vec<BTreeMap<String, String>>) -> &mut Self {
+        self.values.insert(name.to_string(), Value::List(list));
+        self
+    }
+    pub fn get(&self, name: &str) -> Option<&Value> { self.values.get(name) }
+}
+
+enum Node {
+    Text(String),
+    Var(String),
+    For { item: String, list: String, body: Vec<Node> },
+    If { cond: String, body: Vec<Node> },
+}
+
+/// Renders a jinja-style template. Supported are `{{ name }}`, `{% if name %}...{% endif %}` and
+/// `{% for item in list %}...{{ item.field }}...{% endfor %}`. Referencing an unknown variable is an error.
+pub fn render(template: &str, ctx: &TemplateContext) -> Result<String, String> {
+    render_escaped(template, ctx, |v| v.to_string())
+}
+
+/// Like [render], but every printed variable goes through `escape`, e.g. to keep a json payload
+/// valid if the release notes contain quotes or line breaks. The template text itself is kept.
+pub fn render_escaped(template: &str, ctx: &TemplateContext, escape: fn(&str) -> String) -> Result<String, String> {
+    let nodes = parse(template)?;
+    let mut out = String::new();
+    render_nodes(&nodes, ctx, None, escape, &mut out)?;
+    Ok(out)
+}
+
+fn parse(template: &str) -> Result<Vec<Node>, String> {
+    let re = Regex::new(r"(?s)\{\{\s*(.*?)\s*\}\}|\{%\s*(.*?)\s*%\}").unwrap();
+    let re_for = Regex::new(r"^for\s+(\w+)\s+in\s+(\w+)$").unwrap();
+    let re_if = Regex::new(r"^if\s+([\w.]+)$").unwrap();
+
+    // stack of open blocks: (block header, nodes collected so far)
+    let mut stack: Vec<(Option<Node>, Vec<Node>)> = vec![(None, vec![])];
+    let mut pos = 0;
+    for c in re.captures_iter(template) {
+        let m = c.get(0).unwrap();
+        let nodes = &mut stack.last_mut().unwrap().1;
+        if m.start() > pos { nodes.push(Node::Text(template[pos..m.start()].to_string())); }
+        pos = m.end();
+
+        if let Some(var) = c.get(1) {
+            nodes.push(Node::Var(var.as_str().to_string()));
+            continue;
+        }
+        let tag = c.get(2).unwrap().as_str();
+        if let Some(fc) = re_for.captures(tag) {
+            stack.push((Some(Node::For { item: fc[1].to_string(), list: fc[2].to_string(), body: vec![] }), vec![]));
+        } else if let Some(ic) = re_if.captures(tag) {
+            stack.push((Some(Node::If { cond: ic[1].to_string(), body: vec![] }), vec![]));
+        } else if tag == "endfor" || tag == "endif" {
+            let (block, body) = stack.pop().unwrap();
+            let node = match (block, tag) {
+                (Some(Node::For { item, list, .. }), "endfor") => Node::For { item, list, body },
+                (Some(Node::If { cond, .. }), "endif") => Node::If { cond, body },
+                _ => return Err(format!("Unexpected '{{% {} %}}' in template", tag)),
+            };
+            stack.last_mut().unwrap().1.push(node);
+        } else {
+            return Err(format!("Unknown template tag '{{% {} %}}'", tag));
+        }
+    }
+    if stack.len() > 1 { return Err("Unclosed block in template".to_string()); }
+    let mut nodes = stack.pop().unwrap().1;
+    if pos < template.len() { nodes.push(Node::Text(template[pos..].to_string())); }
+    Ok(nodes)
+}
+
+fn lookup<'a>(name: &str, ctx: &'a TemplateContext, item: Option<(&str, &'a BTreeMap<String, String>)>) -> Result<Option<&'a str>, String> {
+    if let Some((item_name, record)) = item
+        && let Some(field) = name.strip_prefix(item_name).and_then(|n| n.strip_prefix('.')) {
+        return match record.get(field) {
+            Some(v) => Ok(Some(v.as_str())),
+            None => Err(format!("Unknown field '{}' in template", name)),
+        };
+    }
+    match ctx.get(name) {
+        Some(Value::Str(s)) => Ok(Some(s.as_str())),
+        Some(Value::List(_)) => Ok(None),
+        None => Err(format!("Unknown variable '{}' in template", name)),
+    }
+}
+
+fn render_nodes(nodes: &[Node], ctx: &TemplateContext, item: Option<(&str, &BTreeMap<String, String>)>, escape: fn(&str) -> String,
+                out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(t) => out.push_str(t),
+            Node::Var(name) => match lookup(name, ctx, item)? {
+                Some(v) => out.push_str(&escape(v)),
+                None => return Err(format!("Variable '{}' is a list and cannot be printed", name)),
+            },
+            Node::If { cond, body } => {
+                let is_true = match ctx.get(cond) {
+                    Some(Value::List(l)) => !l.is_empty(),
+                    _ => lookup(cond, ctx, item)?.is_some_and(|v| !v.is_empty()),
+                };
+                if is_true { render_nodes(body, ctx, item, escape, out)?; }
+            }
+            Node::For { item: item_name, list, body } => match ctx.get(list) {
+                Some(Value::List(records)) => for record in records {
+                    render_nodes(body, ctx, Some((item_name.as_str(), record)), escape, out)?;
+                },
+                _ => return Err(format!("Variable '{}' is not a list", list)),
+            },
+        }
+    }
+    Ok(())
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_template {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        let mut ctx = TemplateContext::new();
+        ctx.insert("version", "1.2.0").insert("empty", "");
+        ctx.insert_list("commits", vec![
+            BTreeMap::from([("short_id".to_string(), "abc1234".to_string()), ("description".to_string(), "one".to_string())]),
+            BTreeMap::from([("short_id".to_string(), "def5678".to_string()), ("description".to_string(), "two".to_string())]),
+        ]);
+        ctx
+    }
+
+    #[test]
+    fn test_render_variables_and_blocks() {
+        let t = "v{{ version }}:{% for c in commits %} {{ c.description }} ({{c.short_id}}){% endfor %}{% if empty %}x{% endif %}";
+        assert_eq!(render(t, &context()).unwrap(), "v1.2.0: one (abc1234) two (def5678)");
+    }
+    #[test]
+    fn test_render_escaped() {
+        let t = "{% for c in commits %}<{{ c.description }}>{% endfor %}";
+        assert_eq!(render_escaped(t, &context(), |v| v.to_uppercase()).unwrap(), "<ONE><TWO>");
+    }
+    #[test]
+    fn test_render_errors() {
+        assert!(render("{{ unknown }}", &context()).is_err());
+        assert!(render("{% for c in commits %}{{ c.unknown }}{% endfor %}", &context()).is_err());
+        assert!(render("{% if version %}", &context()).is_err());
+        assert!(render("{% endfor %}", &context()).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use git2::{Oid, Repository, ResetType};
+use crate::error::{Error, Result};
+use crate::utils::write_file_atomic;
+
+// ********************************************************
+// ********************************************************
+/// A single modification done during a release which can be reverted.
+pub enum Action {
+    /// File was (over)written; holds the original content or None if the file did not exist
+    FileWritten { path: PathBuf, original: Option<Vec<u8>> },
+    /// Reference was moved to a new commit; holds the commit it pointed to before and whether
+    /// the working tree was updated as well
+    Commit { ref_name: String, previous: Oid, worktree: bool },
+    /// Tag was created
+    Tag { name: String },
+    /// Reference other than HEAD was created, moved or deleted; holds its previous target or None if
+    /// it did not exist
+    Reference { name: String, previous: Option<Oid> },
+    /// Uncommitted changes were stashed (`--autostash`), they are popped again
+    Stash { oid: Oid },
+    /// HEAD was switched to another branch; holds the reference name or, if it was detached, the
+    /// commit HEAD pointed to before
+    Head { previous: String },
+}
+
+/// Records all actions of a release so they can be rolled back if a later step fails.
+#[derive(Default)]
+pub struct Transaction {
+    actions: Vec<Action>,
+}
+impl Transaction {
+    pub fn new() -> Self { Self::default() }
+
+    /// Stores the current content of the file. Has to be called before the file is written.
+    pub fn backup_file(&mut self, path: &PathBuf) -> Result<()> {
+        let original = if path.exists() {
+            Some(fs::read(path).map_err(|e| Error::io(format!("Could not backup file '{}'", path.display()), e))?)
+        } else { None };
+        self.actions.push(Action::FileWritten { path: path.clone(), original });
+        Ok(())
+    }
+    /// Records a file modified by someone else together with its original content (None if the
+    /// file did not exist).
+    pub fn record_file(&mut self, path: &Path, original: Option<Vec<u8>>) {
+        self.actions.push(Action::FileWritten { path: path.to_path_buf(), original });
+    }
+    pub fn record_commit(&mut self, ref_name: &str, previous: Oid) {
+        self.actions.push(Action::Commit { ref_name: ref_name.to_string(), previous, worktree: false });
+    }
+    /// Records a reference move which also updated the working tree (e.g. a fast-forward of the
+    /// checked out branch).
+    pub fn record_checkout(&mut self, ref_name: &str, previous: Oid) {
+        self.actions.push(Action::Commit { ref_name: ref_name.to_string(), previous, worktree: true });
+    }
+    pub fn record_reference(&mut self, name: &str, previous: Option<Oid>) {
+        self.actions.push(Action::Reference { name: name.to_string(), previous });
+    }
+    pub fn record_tag(&mut self, name: &str) {
+        self.actions.push(Action::Tag { name: name.to_string() });
+    }
+    /// Records the stash of the uncommitted changes. As it is the first action, it is popped after
+    /// everything else was rolled back.
+    pub fn record_stash(&mut self, oid: Oid) {
+        self.actions.push(Action::Stash { oid });
+    }
+    pub fn record_head(&mut self, previous: &str) {
+        self.actions.push(Action::Head { previous: previous.to_string() });
+    }
+    pub fn is_empty(&self) -> bool { self.actions.is_empty() }
+
+    /// Keeps all modifications of a failed release (`--keep-on-error`) except for the stash, which is
+    /// popped so the uncommitted changes are not left behind. Returns whether a stash was restored;
+    /// if it cannot be applied, e.g. because of conflicts with the kept files, it stays in the stash.
+    pub fn keep(self, repo: &Repository) -> Result<bool> {
+        match self.actions.iter().find_map(|a| match a { Action::Stash { oid } => Some(*oid), _ => None }) {
+            Some(oid) => crate::utils::pop_stash(repo, oid).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Reverts all recorded actions in reverse order. Returns a description of each
+    /// action which could not be reverted.
+    pub fn rollback(self, repo: &Repository) -> Vec<String> {
+        let mut errors = vec![];
+        for action in self.actions.into_iter().rev() {
+            if let Err(e) = Self::revert(repo, &action) { errors.push(e.to_string()); }
+        }
+        errors
+    }
+
+    fn revert(repo: &Repository, action: &Action) -> Result<()> {
+        tracing::debug!("Rolling back {}", match action {
+            Action::FileWritten { path, .. } => format!("file {}", path.display()),
+            Action::Commit { ref_name, previous, .. } => format!("{} to {}", ref_name, previous),
+            Action::Tag { name } => format!("tag {}", name),
+            Action::Reference { name, .. } => format!("reference {}", name),
+            Action::Stash { oid } => format!("stash {}", oid),
+            Action::Head { previous } => format!("HEAD to {}", previous),
+        });
+        match action {
+            Action::FileWritten { path, original } => {
+                let r = match original {
+                    Some(content) => write_file_atomic(path, content),
+                    None => fs::remove_file(path),
+                };
+                r.map_err(|e| Error::io(format!("Could not restore file '{}'", path.display()), e))
+            }
+            Action::Commit { ref_name, previous, worktree } => {
+                let is_head = repo.head().ok().and_then(|h| h.name().map(|n| n == ref_name)).unwrap_or(false);
+                if is_head {
+                    // reset the index as well, the working tree is restored by the file backups
+                    // unless it was updated together with the reference
+                    let obj = repo.find_object(*previous, None)
+                        .map_err(|e| Error::git(format!("Could not find commit {}", previous), e))?;
+                    let reset_type = if *worktree { ResetType::Hard } else { ResetType::Mixed };
+                    return repo.reset(&obj, reset_type, None)
+                        .map_err(|e| Error::git(format!("Could not reset '{}' to {}", ref_name, previous), e));
+                }
+                let mut reference = repo.find_reference(ref_name)
+                    .map_err(|e| Error::git(format!("Could not find reference '{}'", ref_name), e))?;
+                reference.set_target(*previous, "cgvs: rollback release commit")
+                    .map(|_| ())
+                    .map_err(|e| Error::git(format!("Could not reset '{}' to {}", ref_name, previous), e))
+            }
+            Action::Tag { name } => {
+                repo.tag_delete(name).map_err(|e| Error::git(format!("Could not delete tag '{}'", name), e))
+            }
+            Action::Reference { name, previous } => {
+                match (repo.find_reference(name), previous) {
+                    (Ok(mut reference), Some(previous)) => reference.set_target(*previous, "cgvs: rollback release").map(|_| ()),
+                    (Ok(mut reference), None) => reference.delete(),
+                    // the reference was deleted, e.g. the tag removed by undo
+                    (Err(_), Some(previous)) => repo.reference(name, *previous, false, "cgvs: rollback release").map(|_| ()),
+                    (Err(e), None) => Err(e),
+                }.map_err(|e| Error::git(format!("Could not restore reference '{}'", name), e))
+            }
+            Action::Stash { oid } => crate::utils::pop_stash(repo, *oid),
+            Action::Head { previous } => match Oid::from_str(previous) {
+                Ok(oid) if !previous.starts_with("refs/") => repo.set_head_detached(oid),
+                _ => repo.set_head(previous),
+            }.map_err(|e| Error::git(format!("Could not restore HEAD to '{}'", previous), e)),
+        }
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_transaction {
+    use super::*;
+    use std::path::Path;
+    use git2::Signature;
+
+    fn init_repo(dir: &Path) -> (Repository, Oid) {
+        let repo = Repository::init(dir).unwrap();
+        fs::write(dir.join("Cargo.toml"), "version = \"0.1.0\"\n").unwrap();
+        let oid = {
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("Cargo.toml")).unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::now("test", "test@example.com").unwrap();
+            repo.commit(Some("HEAD"), &sig, &sig, "initial", &tree, &[]).unwrap()
+        };
+        (repo, oid)
+    }
+
+    #[test]
+    fn test_rollback_restores_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, _) = init_repo(dir.path());
+        let existing = dir.path().join("Cargo.toml");
+        let created = dir.path().join("Cargo.lock");
+
+        let mut t = Transaction::new();
+        t.backup_file(&existing).unwrap();
+        t.backup_file(&created).unwrap();
+        fs::write(&existing, "version = \"0.2.0\"\n").unwrap();
+        fs::write(&created, "lock").unwrap();
+
+        assert!(t.rollback(&repo).is_empty());
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "version = \"0.1.0\"\n");
+        assert!(!created.exists());
+    }
+    #[test]
+    fn test_rollback_commit_and_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, initial) = init_repo(dir.path());
+
+        let mut t = Transaction::new();
+        let head = repo.head().unwrap();
+        let ref_name = head.name().unwrap().to_string();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.find_commit(initial).unwrap();
+        let new = repo.commit(Some("HEAD"), &sig, &sig, "bump", &parent.tree().unwrap(), &[&parent]).unwrap();
+        t.record_commit(&ref_name, initial);
+        repo.tag("v0.2.0", &repo.find_object(new, None).unwrap(), &sig, "msg", false).unwrap();
+        t.record_tag("v0.2.0");
+        repo.reference("refs/heads/release", new, false, "test").unwrap();
+        t.record_reference("refs/heads/release", None);
+
+        assert!(t.rollback(&repo).is_empty());
+        assert_eq!(repo.head().unwrap().target(), Some(initial));
+        assert!(repo.tag_names(Some("v*")).unwrap().is_empty());
+        assert!(repo.find_reference("refs/heads/release").is_err());
+    }
+    #[test]
+    fn test_rollback_pops_stash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, _) = init_repo(dir.path());
+        let file = dir.path().join("Cargo.toml");
+        fs::write(&file, "version = \"0.1.0\"\nwip = true\n").unwrap();
+
+        let mut t = Transaction::new();
+        let stash = crate::utils::stash_changes(&repo, "test").unwrap().unwrap();
+        t.record_stash(stash);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version = \"0.1.0\"\n");
+        t.backup_file(&file).unwrap();
+        fs::write(&file, "version = \"0.2.0\"\n").unwrap();
+
+        assert!(t.rollback(&repo).is_empty());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version = \"0.1.0\"\nwip = true\n");
+        assert!(crate::utils::stash_changes(&repo, "test").unwrap().is_some());
+    }
+    #[test]
+    fn test_keep_pops_stash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (repo, _) = init_repo(dir.path());
+        let file = dir.path().join("Cargo.toml");
+        let lock = dir.path().join("Cargo.lock");
+        let stash_count = |repo: &mut Repository| { let mut n = 0; repo.stash_foreach(|_, _, _| { n += 1; true }).unwrap(); n };
+
+        // the kept release modified another file: the changes are restored on top of it
+        fs::write(&file, "version = \"0.1.0\"\nwip = true\n").unwrap();
+        let mut t = Transaction::new();
+        t.record_stash(crate::utils::stash_changes(&repo, "test").unwrap().unwrap());
+        t.backup_file(&lock).unwrap();
+        fs::write(&lock, "lock").unwrap();
+        assert!(t.keep(&repo).unwrap());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version = \"0.1.0\"\nwip = true\n");
+        assert_eq!(fs::read_to_string(&lock).unwrap(), "lock");
+        assert_eq!(stash_count(&mut Repository::open(dir.path()).unwrap()), 0);
+
+        // the kept release modified the stashed file: the stash stays and the error names it
+        fs::remove_file(&lock).unwrap();
+        let mut t = Transaction::new();
+        let stash = crate::utils::stash_changes(&repo, "test").unwrap().unwrap();
+        t.record_stash(stash);
+        fs::write(&file, "version = \"0.2.0\"\n").unwrap();
+        let e = t.keep(&repo).unwrap_err();
+        assert!(e.to_string().contains(&stash.to_string()), "{}", e);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version = \"0.2.0\"\n");
+        assert_eq!(stash_count(&mut Repository::open(dir.path()).unwrap()), 1);
+        assert!(!Transaction::new().keep(&repo).unwrap());
+    }
+}
@@ -0,0 +1,183 @@
+use git2::{Remote, Repository, ResetType};
+use crate::error::{Error, Result};
+use crate::transaction::Transaction;
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// Returns the most recent release of this tool: the version tag with the newest tagger time (the
+/// commit time for a lightweight tag) among the tags whose commit is a version commit. This is not
+/// necessarily the highest version, e.g. after a release on a maintenance branch.
+pub fn last_release_tag(repo: &Repository, tag_format: &TagFormat) -> Result<Option<(String, Version)>> {
+    let mut last: Option<(i64, Version, String)> = None;
+    for tn in list_version_tags(repo, tag_format)? {
+        let Some(version) = tag_format.version_part(&tn).and_then(|v| Version::try_from(v.to_string()).ok()) else { continue; };
+        let obj = repo.revparse_single(&format!("refs/tags/{}", tn))
+            .map_err(|e| Error::git(format!("Could not resolve tag '{}'", tn), e))?;
+        let Ok(commit) = obj.peel_to_commit() else { continue; };
+        if !commit.message().unwrap_or("").starts_with(COMMIT_MESSAGE_PREFIX) { continue; }
+        let time = obj.as_tag().and_then(|t| t.tagger()).map_or(commit.time(), |s| s.when()).seconds();
+        if last.as_ref().is_none_or(|(t, v, _)| (time, &version) > (*t, v)) { last = Some((time, version, tn)); }
+    }
+    Ok(last.map(|(_, version, tn)| (tn, version)))
+}
+
+/// Removes the most recent version tag and the version commit it points to.
+///
+/// The commit is dropped if it is HEAD, otherwise (or if `revert` is set) a revert commit is
+/// created. If a remote is given, the tag is deleted there and the branch is pushed; in this
+/// case the commit is always reverted, so the push does not need to be forced. Both remote
+/// updates are only done if the remote still has the tag and branch as known locally. The local
+/// steps are recorded in the transaction and the push comes last, so a failed push can be rolled
+/// back completely and nothing fails once the remote was updated.
+pub fn undo_last_release(repo: &Repository, tag_format: &TagFormat, revert: bool,
+                         remote: Option<&mut Remote>, transaction: &mut Transaction) -> Result<()> {
+    let (tag_name, version) = last_release_tag(repo, tag_format)?
+        .ok_or_else(|| Error::Repository(format!("No version tag matching '{}' on a version commit of this tool found", tag_format)))?;
+    let commit = repo.revparse_single(&format!("refs/tags/{}", tag_name)).and_then(|o| o.peel_to_commit())
+        .map_err(|e| Error::git(format!("Could not resolve tag '{}'", tag_name), e))?;
+    progress!("{INDENT}Latest release: {} (tag '{}', commit {})", version, tag_name, commit.id());
+
+    let change_count = count_uncommitted_changes(repo)?;
+    if change_count > 0 { return Err(Error::DirtyTree(change_count)); }
+
+    let tag_oid = repo.refname_to_id(&format!("refs/tags/{}", tag_name))
+        .map_err(|e| Error::git(format!("Could not resolve tag '{}'", tag_name), e))?;
+
+    let head = repo.head().and_then(|h| h.peel_to_commit()).map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let head_ref_name = repo.head().ok().and_then(|h| h.name().map(|n| n.to_string()))
+        .ok_or(Error::Repository("HEAD is not a valid utf-8 reference".into()))?;
+    // a hard reset to the previous HEAD also restores the working tree and clears a pending revert
+    transaction.record_checkout(&head_ref_name, head.id());
+    if head.id() == commit.id() && !revert && remote.is_none() {
+        let parent = commit.parent(0).map_err(|e| Error::git(format!("Could not find parent of {}", commit.id()), e))?;
+        repo.reset(parent.as_object(), ResetType::Hard, None)
+            .map_err(|e| Error::git(format!("Could not reset to {}", parent.id()), e))?;
+        progress!("{INDENT}Dropped version commit, HEAD is now at {}", parent.id());
+    }
+    else {
+        repo.revert(&commit, None).map_err(|e| Error::git(format!("Could not revert {}", commit.id()), e))?;
+        let mut index = repo.index().map_err(|e| Error::git("Could not open git index", e))?;
+        if index.has_conflicts() {
+            return Err(Error::Repository(format!("Reverting {} results in conflicts, please resolve manually", commit.id())));
+        }
+        let tree = repo.find_tree(index.write_tree().map_err(|e| Error::git("Could not write git tree", e))?)
+            .map_err(|e| Error::git("Could not find git tree", e))?;
+        let (author, committer) = (author_signature(repo)?, committer_signature(repo)?);
+        let message = format!("Revert \"{}\"", commit.summary().unwrap_or(""));
+        let oid = repo.commit(Some("HEAD"), &author, &committer, &message, &tree, &[&head])
+            .map_err(|e| Error::git("Could not commit", e))?;
+        repo.cleanup_state().map_err(|e| Error::git("Could not cleanup repository state", e))?;
+        progress!("{INDENT}Reverted version commit (id: {})", oid);
+    }
+
+    transaction.record_reference(&format!("refs/tags/{}", tag_name), Some(tag_oid));
+    repo.tag_delete(&tag_name).map_err(|e| Error::git(format!("Could not delete tag '{}'", tag_name), e))?;
+    progress!("{INDENT}Deleted local tag '{}'", tag_name);
+
+    if let Some(git_remote) = remote {
+        let tag_ref = format!(":refs/tags/{}", tag_name);
+        let leases = vec![(head_ref_name.clone(), head.id()), (format!("refs/tags/{}", tag_name), tag_oid)];
+        progress!("{INDENT}pushing to remote '{}' with '{}' and '{}'", git_remote.name().unwrap_or(""), head_ref_name, tag_ref);
+        push_refs(repo, git_remote, &[head_ref_name.clone(), tag_ref], leases)?;
+    }
+    Ok(())
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_undo {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use git2::RepositoryState;
+
+    fn commit_manifest(repo: &Repository, dir: &Path, content: &str, message: &str) -> git2::Oid {
+        fs::write(dir.join("Cargo.toml"), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Cargo.toml")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let parents: Vec<_> = repo.head().ok().and_then(|h| h.peel_to_commit().ok()).into_iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    }
+
+    fn init_repo(dir: &Path) -> Repository {
+        let repo = Repository::init(dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        repo
+    }
+
+    #[test]
+    fn test_last_release_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path());
+        commit_manifest(&repo, dir.path(), "version = \"1.0.0\"\n", "initial");
+        let tag_at = |name: &str, oid: git2::Oid, time: i64| {
+            let sig = git2::Signature::new("test", "test@example.com", &git2::Time::new(time, 0)).unwrap();
+            repo.tag(name, &repo.find_object(oid, None).unwrap(), &sig, name, false).unwrap();
+        };
+        let minor = commit_manifest(&repo, dir.path(), "version = \"1.1.0\"\n", &format!("{} 1.1.0", COMMIT_MESSAGE_PREFIX));
+        tag_at("v1.1.0", minor, 1700000100);
+        // later release of the maintenance line, lower than 1.1.0
+        let fix = commit_manifest(&repo, dir.path(), "version = \"1.0.1\"\n", &format!("{} 1.0.1", COMMIT_MESSAGE_PREFIX));
+        tag_at("v1.0.1", fix, 1700000200);
+        // highest and newest tag, but not created by the tool
+        let manual = commit_manifest(&repo, dir.path(), "version = \"2.0.0\"\n", "manual release");
+        tag_at("v2.0.0", manual, 1700000300);
+
+        let (tag, version) = last_release_tag(&repo, &TagFormat::from_prefix("v")).unwrap().unwrap();
+        assert_eq!((tag.as_str(), version.to_string()), ("v1.0.1", "1.0.1".to_string()));
+        assert_eq!(last_release_tag(&repo, &TagFormat::from_prefix("release-")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_undo_conflicting_revert_keeps_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path());
+        commit_manifest(&repo, dir.path(), "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n", "initial");
+        let release = commit_manifest(&repo, dir.path(), "[package]\nname = \"demo\"\nversion = \"0.2.0\"\n",
+                                      &format!("{} 0.2.0", COMMIT_MESSAGE_PREFIX));
+        let sig = repo.signature().unwrap();
+        repo.tag("v0.2.0", &repo.find_object(release, None).unwrap(), &sig, "Version 0.2.0", false).unwrap();
+        // a later change of the same line makes the revert conflict
+        let head = commit_manifest(&repo, dir.path(), "[package]\nname = \"demo\"\nversion = \"0.3.0-dev\"\n", "dev version");
+
+        let mut transaction = Transaction::new();
+        let r = undo_last_release(&repo, &TagFormat::from_prefix("v"), true, None, &mut transaction);
+        assert!(matches!(r, Err(Error::Repository(_))));
+        assert!(repo.find_reference("refs/tags/v0.2.0").is_ok());
+
+        assert!(transaction.rollback(&repo).is_empty());
+        assert_eq!(repo.head().unwrap().target(), Some(head));
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        assert_eq!(count_uncommitted_changes(&repo).unwrap(), 0);
+        assert!(fs::read_to_string(dir.path().join("Cargo.toml")).unwrap().contains("0.3.0-dev"));
+    }
+
+    #[test]
+    fn test_undo_failed_push_restores_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = init_repo(dir.path());
+        commit_manifest(&repo, dir.path(), "[package]\nname = \"demo\"\nversion = \"0.1.0\"\n", "initial");
+        let release = commit_manifest(&repo, dir.path(), "[package]\nname = \"demo\"\nversion = \"0.2.0\"\n",
+                                      &format!("{} 0.2.0", COMMIT_MESSAGE_PREFIX));
+        let sig = repo.signature().unwrap();
+        let tag = repo.tag("v0.2.0", &repo.find_object(release, None).unwrap(), &sig, "Version 0.2.0", false).unwrap();
+        let missing = dir.path().join("missing-remote.git");
+        let mut remote = repo.remote("origin", missing.to_str().unwrap()).unwrap();
+
+        let mut transaction = Transaction::new();
+        assert!(undo_last_release(&repo, &TagFormat::from_prefix("v"), false, Some(&mut remote), &mut transaction).is_err());
+        assert!(repo.find_reference("refs/tags/v0.2.0").is_err());
+
+        assert!(transaction.rollback(&repo).is_empty());
+        assert_eq!(repo.head().unwrap().target(), Some(release));
+        assert_eq!(repo.refname_to_id("refs/tags/v0.2.0").unwrap(), tag);
+        assert_eq!(count_uncommitted_changes(&repo).unwrap(), 0);
+    }
+}
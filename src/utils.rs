@@ -1,12 +1,34 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::fs;
-use std::path::PathBuf;
-use std::process::exit;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use clap::builder::PossibleValue;
 use clap::ValueEnum;
 use console::{style, Emoji};
+use dialoguer::Confirm;
+use git2::{Config, Cred, CredentialType, FetchOptions, Oid, ProxyOptions, PushOptions, Remote, RemoteCallbacks, Repository, Signature, StatusOptions};
+use git2_credentials::CredentialHandler;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use regex::Regex;
 use toml_edit::DocumentMut;
+use crate::error::{Error, Result};
+use crate::json;
+
+/// Prints progress information unless quiet or the output is switched to a machine-readable format.
+#[macro_export]
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if $crate::utils::is_progress_enabled() { $crate::utils::progress_line(format!($($arg)*)); }
+        else if tracing::enabled!(target: $crate::logging::CONSOLE, tracing::Level::INFO) { $crate::utils::log_console(&format!($($arg)*)); }
+    };
+}
 
 // ********************************************************
 // ********************************************************
@@ -17,6 +39,10 @@ pub static PEN: Emoji<'_, '_> = Emoji("🖊️", "");
 pub static TAG: Emoji<'_, '_> = Emoji("🏷️", "");
 pub static CHECK: Emoji<'_, '_> = Emoji("✔ ", "");
 pub static INDENT: &str = "       ";
+/// Directories never scanned for Cargo.toml files
+pub static SKIPPED_DIRS: [&str; 4] = [".git", "target", "node_modules", "vendor"];
+/// Start of the message of all commits created by this tool
+pub static COMMIT_MESSAGE_PREFIX: &str = "Changed version in tomls to";
 
 
 // ********************************************************
@@ -28,10 +54,133 @@ pub enum CargoFile {
     /// Write version to base cargo file
     Base,
     /// Write version to all cargo files
-    All
+    All,
+    /// Write version to the members of the workspace of the base cargo file
+    Workspace,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum OutputFormat {
+    /// Human-readable progress output
+    #[default]
+    Text,
+    /// Single JSON document printed at the end
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SyncTarget {
+    /// The highest version of the selected cargo.toml files
+    #[default]
+    Highest,
+    /// The version of the highest version tag
+    Tag,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum IncrementSource {
+    /// Increment the version of the cargo.toml files
+    #[default]
+    Manifest,
+    /// Increment the highest existing version tag
+    Tag,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Debug, Default)]
+pub enum PushSelection {
+    /// Push only the branch with the version commit
+    Branch,
+    /// Push only the version tag, e.g. if the branch goes out via a pull request
+    Tags,
+    /// Push branch and tag
+    #[default]
+    Both,
+}
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+pub fn set_output_format(format: OutputFormat) {
+    JSON_OUTPUT.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+pub fn is_json_output() -> bool { JSON_OUTPUT.load(Ordering::Relaxed) }
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+pub fn set_quiet(quiet: bool) { QUIET.store(quiet, Ordering::Relaxed); }
+pub fn is_progress_enabled() -> bool { !is_json_output() && !QUIET.load(Ordering::Relaxed) }
+
+// spinner of the running step, only shown if stdout is a terminal
+static STEP_SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+// set while the spinner is suspended, as indicatif does not allow to draw or suspend it again meanwhile
+static STEP_SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+fn active_spinner() -> Option<ProgressBar> {
+    if STEP_SUSPENDED.load(Ordering::Relaxed) { return None; }
+    STEP_SPINNER.lock().unwrap().clone()
+}
+
+/// Writes a line of the console output without colors to the log file of `--log-file`.
+pub fn log_console(line: &str) {
+    let line = console::strip_ansi_codes(line);
+    if !line.trim().is_empty() { tracing::info!(target: crate::logging::CONSOLE, "{}", line.trim()); }
+}
+
+/// Prints a line of progress output, above the spinner if a step is running.
+pub fn progress_line(line: String) {
+    log_console(&line);
+    match active_spinner() {
+        Some(bar) => bar.println(line),
+        None => println!("{}", line),
+    }
+}
+
+/// Prints the line of a step like `[2/5] Writing ...`. In a terminal, a spinner with the elapsed
+/// time of the step runs below the output of the step until `finish_step`.
+pub fn start_step(line: String) {
+    log_console(&line);
+    if !is_progress_enabled() { return; }
+    end_step();
+    println!("{}", line);
+    if !std::io::stdout().is_terminal() { return; }
+    let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stdout());
+    bar.set_style(ProgressStyle::with_template(&format!("{INDENT}{{spinner}} {{elapsed}}")).unwrap());
+    bar.enable_steady_tick(Duration::from_millis(100));
+    *STEP_SPINNER.lock().unwrap() = Some(bar);
+}
+
+/// Prints the done line of a step like `✔ Writing ... done`. The spinner collapses into it, adding
+/// the duration of the step.
+pub fn finish_step(line: String) {
+    log_console(&line);
+    if !is_progress_enabled() { return; }
+    match STEP_SPINNER.lock().unwrap().take() {
+        Some(bar) => {
+            bar.finish_and_clear();
+            println!("{} ({:.1}s)", line, bar.elapsed().as_secs_f64());
+        },
+        None => println!("{}", line),
+    }
+}
+
+/// Removes the spinner of the running step without a done line, e.g. before the output of a
+/// command or an error.
+pub fn end_step() {
+    if STEP_SUSPENDED.load(Ordering::Relaxed) { return; }
+    if let Some(bar) = STEP_SPINNER.lock().unwrap().take() { bar.finish_and_clear(); }
+}
+
+/// Hides the spinner while `f` runs, e.g. for a prompt or the output of a hook.
+pub fn suspend_step<R>(f: impl FnOnce() -> R) -> R {
+    match active_spinner() {
+        Some(bar) => bar.suspend(|| {
+            STEP_SUSPENDED.store(true, Ordering::Relaxed);
+            let r = f();
+            STEP_SUSPENDED.store(false, Ordering::Relaxed);
+            r
+        }),
+        None => f(),
+    }
 }
 
-#[derive(ValueEnum, Clone, PartialEq, Debug)]
+#[derive(ValueEnum, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub enum IncrementVersionPart {
     /// Patch version when you make backward compatible bug fixes
     Patch,
@@ -51,70 +200,185 @@ impl Display for IncrementVersionPart {
     }
 }
 
-#[derive(PartialEq, Clone)]
+/// Version part to increment, either given directly or taken from the HEAD commit message
+#[derive(Clone, PartialEq, Debug)]
+pub enum IncrementSelector {
+    Part(IncrementVersionPart),
+    FromCommit,
+}
+impl ValueEnum for IncrementSelector {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[IncrementSelector::Part(IncrementVersionPart::Patch), IncrementSelector::Part(IncrementVersionPart::Minor),
+          IncrementSelector::Part(IncrementVersionPart::Major), IncrementSelector::FromCommit]
+    }
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            IncrementSelector::Part(part) => part.to_possible_value(),
+            IncrementSelector::FromCommit => Some(PossibleValue::new("from-commit")
+                .help("Part given by a directive like '[release: minor]' in the HEAD (merge) commit message")),
+        }
+    }
+}
+
+/// Version `MAJOR.MINOR.PATCH` with an optional suffix, a pre-release (`-rc.1`) and/or build
+/// metadata (`+g1a2b3c4`), which is kept verbatim.
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Version {
-    major: u16,
-    minor: u16,
-    patch: u16,
+    major: u64,
+    minor: u64,
+    patch: u64,
+    suffix: String,
 }
 impl Version {
-    pub fn increment(&mut self, part: &IncrementVersionPart) {
+    /// Pre-release part of the suffix without the leading `-`.
+    pub fn pre_release(&self) -> Option<&str> {
+        self.suffix.strip_prefix('-').map(|s| s.split_once('+').map_or(s, |(pre, _)| pre))
+    }
+
+    /// The version without build metadata, which does not take part in comparisons with tags.
+    pub fn without_build(&self) -> Version {
+        let suffix = self.pre_release().map(|p| format!("-{}", p)).unwrap_or_default();
+        Version { suffix, ..self.clone() }
+    }
+
+    /// Increments the part, resets the lower ones and drops the suffix. Fails if the part would overflow.
+    pub fn increment(&mut self, part: &IncrementVersionPart) -> Result<()> {
+        let overflow = || Error::Manifest(format!("Cannot increment the {} version of {}, it would overflow", part, self));
         match part {
             IncrementVersionPart::Patch => {
-                self.patch += 1 }
+                self.patch = self.patch.checked_add(1).ok_or_else(overflow)?; }
             IncrementVersionPart::Minor => {
-                self.minor += 1; self.patch = 0; }
+                self.minor = self.minor.checked_add(1).ok_or_else(overflow)?; self.patch = 0; }
             IncrementVersionPart::Major => {
-                self.major += 1; self.minor = 0; self.patch = 0;
+                self.major = self.major.checked_add(1).ok_or_else(overflow)?; self.minor = 0; self.patch = 0;
             }
         }
+        self.suffix.clear();
+        Ok(())
     }
-    pub fn increment_clone(&self, part: &IncrementVersionPart) -> Self {
+    pub fn increment_clone(&self, part: &IncrementVersionPart) -> Result<Self> {
         let mut n = self.clone();
-        n.increment(part);
-        n
+        n.increment(part)?;
+        Ok(n)
     }
 }
 impl TryFrom<String> for Version {
     type Error = &'static str;
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        let re = Regex::new(r"([0-9]+)\.([0-9]+)\.([0-9]+)").unwrap();
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        // semver 2.0: no leading zeros in numeric parts, non-empty dot-separated identifiers and at
+        // most one build metadata part
+        let num = r"(0|[1-9][0-9]*)";
+        let pre = r"(?:0|[1-9][0-9]*|[0-9]*[A-Za-z-][0-9A-Za-z-]*)";
+        let re = Regex::new(&format!(r"^{num}\.{num}\.{num}((?:-{pre}(?:\.{pre})*)?(?:\+[0-9A-Za-z-]+(?:\.[0-9A-Za-z-]+)*)?)$")).unwrap();
 
         let rea = match re.captures(value.as_str()) {
-            None => return Err("Invalid version string"), Some(v) => v
+            None => return Err("Invalid version string, expected MAJOR.MINOR.PATCH with an optional -pre-release or +build suffix"),
+            Some(v) => v
         };
 
-        let major = rea.get(1).unwrap().as_str().parse::<u16>().unwrap_or(0);
-        let minor = rea.get(2).unwrap().as_str().parse::<u16>().unwrap_or(0);
-        let patch = rea.get(3).unwrap().as_str().parse::<u16>().unwrap_or(0);
+        let part = |n: usize| rea.get(n).unwrap().as_str().parse::<u64>().map_err(|_| "Version component out of range");
+        let (major, minor, patch) = (part(1)?, part(2)?, part(3)?);
+        let suffix = rea.get(4).map_or("", |s| s.as_str()).to_string();
 
-        Ok(Self { major, minor, patch })
+        Ok(Self { major, minor, patch, suffix })
     }
 }
 impl Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", format!("{}.{}.{}", self.major, self.minor, self.patch))
+        write!(f, "{}.{}.{}{}", self.major, self.minor, self.patch, self.suffix)
     }
 }
+/// Semver precedence: a pre-release is lower than the release, its identifiers are compared
+/// numerically or else as text. Build metadata only decides between otherwise equal versions.
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use std::cmp::Ordering::*;
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre_release(), other.pre_release()) {
+                (None, None) => Equal,
+                (None, Some(_)) => Greater,
+                (Some(_), None) => Less,
+                (Some(a), Some(b)) => {
+                    let (mut a, mut b) = (a.split('.'), b.split('.'));
+                    loop {
+                        let ord = match (a.next(), b.next()) {
+                            (None, None) => break Equal,
+                            (None, Some(_)) => Less,
+                            (Some(_), None) => Greater,
+                            (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                                (Ok(x), Ok(y)) => x.cmp(&y),
+                                (Ok(_), Err(_)) => Less,
+                                (Err(_), Ok(_)) => Greater,
+                                (Err(_), Err(_)) => x.cmp(y),
+                            },
+                        };
+                        if ord != Equal { break ord; }
+                    }
+                },
+            })
+            .then_with(|| self.suffix.cmp(&other.suffix))
+    }
+}
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+}
 // ********************************************************
 // ********************************************************
-pub fn print_error(msg: String) -> ! {
-    println!("\n{} {}", style("Error:").bold().red(), msg);
-    exit(-1);
+/// Prints the error and exits with the exit code of its kind.
+pub fn print_error(e: Error) -> ! {
+    end_step();
+    tracing::error!(target: crate::logging::CONSOLE, "{}", e);
+    if is_json_output() {
+        println!("{}", json::document("error").with("kind", e.kind())
+            .with("exit_code", e.exit_code() as i64).with("message", e.to_string()));
+    }
+    else { println!("\n{} {}", style("Error:").bold().red(), e); }
+    crate::stats::finish(Some(&e));
+    exit(e.exit_code());
+}
+/// Finishes the run successfully and exits.
+pub fn exit_success() -> ! {
+    end_step();
+    crate::stats::finish(None);
+    exit(0);
 }
-#[allow(dead_code)]
 pub fn print_warn(msg: String) {
-    println!("\n{} {}", style("Warning:").bold().yellow(), msg);
+    if QUIET.load(Ordering::Relaxed) { tracing::warn!("{}", msg); }
+    else if is_json_output() { log_console(&format!("Warning: {}", msg)); eprintln!("Warning: {}", msg); }
+    else { progress_line(format!("\n{} {}", style("Warning:").bold().yellow(), msg)); }
+}
+
+/// Prints the rows of a summary as an aligned list below the title.
+pub fn print_summary(title: &str, rows: &[(&str, String)]) {
+    progress!("{INDENT}{}:", title);
+    rows.iter().for_each(|(k, v)| progress!("{INDENT} - {:<11} {}", format!("{}:", k), v));
+}
+
+/// Prints the summary and asks the user to confirm before anything is modified. Fails if the user
+/// declines or no terminal is attached, unless `assume_yes` is set.
+pub fn confirm(summary: &[(&str, String)], assume_yes: bool) -> Result<()> {
+    print_summary("Summary", summary);
+    if assume_yes { return Ok(()); }
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::Usage("Confirmation required but no terminal attached, use --yes to skip it".to_string()));
+    }
+    match suspend_step(|| Confirm::new().with_prompt(format!("{INDENT}Continue?")).default(false).interact()) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::Aborted),
+        Err(e) => Err(Error::Usage(format!("Could not read confirmation: {}", e))),
+    }
 }
 
 // ********************************************************
 // ********************************************************
-pub fn filter_cargo_tomls_by_selector(cargo_tomls: Vec<PathBuf>, cargo_file_selector: &Option<CargoFile>) -> Vec<PathBuf> {
-    match cargo_file_selector {
+pub fn filter_cargo_tomls_by_selector(cargo_tomls: Vec<PathBuf>, cargo_file_selector: &Option<CargoFile>) -> Result<Vec<PathBuf>> {
+    Ok(match cargo_file_selector {
         None => {
-            if cargo_tomls.len() > 1 { print_error("Multiple cargo files found but option cargo_file_selector not set".into()); }
-            else { cargo_tomls }
+            if cargo_tomls.len() > 1 {
+                return Err(Error::Usage("Multiple cargo files found but option cargo_file_selector not set".into()));
+            }
+            cargo_tomls
         },
         Some(cfs) => match cfs{
             CargoFile::Leaf => {
@@ -122,7 +386,6 @@ pub fn filter_cargo_tomls_by_selector(cargo_tomls: Vec<PathBuf>, cargo_file_sele
                     let cl = cpb.as_os_str().len();
                     if max_len < cl { (cl, cpb.clone()) } else { (max_len, pb) }
                 }).1;
-                println!("{INDENT}  -> using leaf: {}", cct.display());
                 vec![cct]
             },
             CargoFile::Base => {
@@ -130,30 +393,108 @@ pub fn filter_cargo_tomls_by_selector(cargo_tomls: Vec<PathBuf>, cargo_file_sele
                     let cl = cpb.as_os_str().len();
                     if max_len > cl { (cl, cpb.clone()) } else { (max_len, pb) }
                 }).1;
-                println!("{INDENT}  -> using base: {}", cct.display());
                 vec![cct]
             },
-            CargoFile::All => {
-                println!("{INDENT}  -> using all.");
-                cargo_tomls }
+            CargoFile::All => cargo_tomls,
+            CargoFile::Workspace => select_workspace_members(cargo_tomls)?,
+        }
+    })
+}
+
+/// Path relative to the base as string with `/` separators, e.g. for `glob_match`.
+fn slash_path(path: &Path, base: Option<&Path>) -> String {
+    let rel = base.and_then(|b| path.strip_prefix(b).ok()).unwrap_or(path);
+    rel.components().filter(|c| !matches!(c, std::path::Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+/// Selects the cargo.toml files listed by `[workspace].members` and not by `[workspace].exclude` of
+/// the uppermost manifest with a `[workspace]` table. The root itself is selected if it has a version.
+pub fn select_workspace_members(cargo_tomls: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut roots = vec![];
+    for ct in &cargo_tomls {
+        let content = fs::read_to_string(ct).map_err(|e| Error::io(format!("Could not read file '{}'", ct.display()), e))?;
+        let toml = content.parse::<DocumentMut>()
+            .map_err(|e| Error::Manifest(format!("Could not parse toml form file '{}': {}", ct.display(), e)))?;
+        if let Some(ws) = toml.get("workspace") {
+            let list = |key: &str| ws.get(key).and_then(|m| m.as_array())
+                .map(|a| a.iter().filter_map(|v| v.as_str().map(String::from)).collect::<Vec<_>>()).unwrap_or_default();
+            let has_version = !matches!(manifest_version(&toml), ManifestVersion::Virtual | ManifestVersion::Missing);
+            roots.push((ct.clone(), list("members"), list("exclude"), has_version));
+        }
+    }
+    let (root, members, exclude, root_has_version) = roots.into_iter().min_by_key(|(ct, ..)| ct.components().count())
+        .ok_or_else(|| Error::Discovery("No cargo.toml with a [workspace] found".to_string()))?;
+    let root_dir = root.parent().unwrap_or(Path::new(""));
+    tracing::debug!(target: crate::logging::DISCOVERY, "Workspace {} with members {:?}, exclude {:?}", root.display(), members, exclude);
+
+    // members and excludes are paths or globs relative to the workspace root
+    let matches = |patterns: &[String], dir: &str| patterns.iter()
+        .any(|p| glob_match(&format!("/{}", p.trim_start_matches("./").trim_end_matches('/')), dir));
+    Ok(cargo_tomls.into_iter().filter(|ct| {
+        if ct == &root { return root_has_version; }
+        let dir = slash_path(ct.parent().unwrap_or(Path::new("")), Some(root_dir));
+        ct.starts_with(root_dir) && matches(&members, &dir) && !matches(&exclude, &dir)
+    }).collect())
+}
+
+/// Returns the `[package].name` of the cargo.toml file, None for virtual manifests.
+pub fn package_name(cargo_toml: &Path) -> Result<Option<String>> {
+    let content = fs::read_to_string(cargo_toml).map_err(|e| Error::io(format!("Could not read file '{}'", cargo_toml.display()), e))?;
+    let toml = content.parse::<DocumentMut>()
+        .map_err(|e| Error::Manifest(format!("Could not parse toml form file '{}': {}", cargo_toml.display(), e)))?;
+    Ok(toml.get("package").and_then(|p| p.get("name")).and_then(|n| n.as_str()).map(String::from))
+}
+
+/// Selects the cargo.toml files of the given packages by their `[package].name`. Every package has
+/// to be found.
+pub fn select_cargo_tomls_by_package(cargo_tomls: Vec<PathBuf>, packages: &[String]) -> Result<Vec<PathBuf>> {
+    let mut selected = vec![];
+    for ct in cargo_tomls {
+        if let Some(name) = package_name(&ct)? && packages.contains(&name) && !selected.iter().any(|(n, _)| n == &name) {
+            tracing::debug!(target: crate::logging::DISCOVERY, "Package {} is {}", name, ct.display());
+            selected.push((name, ct));
         }
     }
+    if let Some(missing) = packages.iter().find(|p| !selected.iter().any(|(n, _)| n == *p)) {
+        return Err(Error::Discovery(format!("Package '{}' not found in the cargo.toml files", missing)));
+    }
+    Ok(selected.into_iter().map(|(_, ct)| ct).collect())
 }
 
-pub fn find_cargo_tomls_and_git_base(path: PathBuf, scan_subdirs: bool) -> (Vec<PathBuf>, Option<PathBuf>){
+pub fn find_cargo_tomls_and_git_base(path: PathBuf, scan_subdirs: bool, work_tree: Option<&Path>, git_dir: Option<&Path>)
+    -> Result<(Vec<PathBuf>, Option<PathBuf>)> {
     let mut ct: Vec<PathBuf> = vec![];
-    let mut cp = path.clone();
+    // with an explicit work tree, both are made absolute so the search can leave a relative path
+    let work_tree = match work_tree {
+        Some(w) => Some(fs::canonicalize(w).map_err(|e| Error::io(format!("Invalid work tree '{}'", w.display()), e))?),
+        None => None,
+    };
+    let mut cp = match work_tree {
+        Some(_) => fs::canonicalize(&path).map_err(|e| Error::io(format!("Invalid path '{}'", path.display()), e))?,
+        None => path.clone(),
+    };
 
     let git_base_dir = loop {
         cp.push("Cargo.toml");
         if cp.exists() && cp.is_file() {
+            tracing::debug!(target: crate::logging::DISCOVERY, "Found {}", cp.display());
             ct.push(cp.clone());
         }
         cp.pop();
 
+        // an explicit work tree ends the search instead of the .git directory
+        if let Some(work_tree) = &work_tree {
+            if &cp == work_tree { break Some(cp); }
+            cp = match cp.parent() { Some(parent) => parent.to_path_buf(), None => { break None; } };
+            continue;
+        }
+
+        // .git is a file in linked worktrees and submodules, pointing to the actual git directory
         cp.push(".git");
-        if cp.exists() && cp.is_dir() {
-            break Some(cp.parent().unwrap().to_path_buf());
+        if cp.is_dir() || cp.is_file() {
+            tracing::debug!(target: crate::logging::DISCOVERY, "Found git repository {}", cp.display());
+            break cp.parent().map(|p| p.to_path_buf());
         }
         cp.pop();
 
@@ -163,44 +504,1176 @@ pub fn find_cargo_tomls_and_git_base(path: PathBuf, scan_subdirs: bool) -> (Vec<
         }
     };
 
-    fn read_dir_cargos(dir: PathBuf, only_subdirs: bool) -> Vec<PathBuf> {
+    fn read_dir_cargos(dir: PathBuf, only_subdirs: bool, is_ignored: &dyn Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
         let mut cv = vec![];
-        for f in fs::read_dir(dir).unwrap() {
-            let p = f.unwrap().path();
+        let entries = fs::read_dir(&dir).map_err(|e| Error::io(format!("Could not read directory '{}'", dir.display()), e))?;
+        for f in entries {
+            let p = f.map_err(|e| Error::io(format!("Could not read directory '{}'", dir.display()), e))?.path();
+            if is_ignored(&p) {
+                tracing::trace!(target: crate::logging::DISCOVERY, "Skipping {}", p.display());
+                continue;
+            }
+            tracing::trace!(target: crate::logging::DISCOVERY, "Scanning {}", p.display());
             if p.is_dir()  {
-                let v = read_dir_cargos(p, true);
+                let v = read_dir_cargos(p, true, is_ignored)?;
                 cv.extend(v);
             }
-            else if p.file_name().unwrap().to_str().unwrap() == "Cargo.toml" && only_subdirs {
+            else if p.file_name().is_some_and(|f| f == "Cargo.toml") && only_subdirs {
                 cv.push(p);
             }
         }
-        cv
+        Ok(cv)
     }
     if scan_subdirs {
-        ct.extend(read_dir_cargos(path.clone(), false));
+        // build output, vendored crates and everything ignored by git are never part of the project
+        let repo = git_base_dir.as_deref().and_then(|b| open_repository(b, git_dir).ok());
+        let is_ignored = |p: &Path| {
+            p.file_name().is_some_and(|n| SKIPPED_DIRS.iter().any(|d| n == *d))
+                || repo.as_ref().zip(git_base_dir.as_deref()).and_then(|(r, base)| p.strip_prefix(base).ok()
+                    .and_then(|rel| r.is_path_ignored(rel).ok())).unwrap_or(false)
+        };
+        ct.extend(read_dir_cargos(path.clone(), false, &is_ignored)?);
     }
-    (ct, git_base_dir)
+    Ok((ct, git_base_dir))
 }
 
-pub fn read_version_tomls(cargo_tomls: &Vec<PathBuf>) -> HashMap<PathBuf, (Version, DocumentMut)> {
-    let mut cargo_content = HashMap::<PathBuf, (Version, DocumentMut)>::new();
-    for cct in cargo_tomls {
-        let cct_content = match fs::read(cct.clone()) {
-            Ok(content) => String::from_utf8(content).unwrap(),
-            Err(e) => { print_error(format!("Could not read file '{}': {:?}", cct.display(), e)); }
+/// Uses the given cargo.toml files (`--manifest-path`) instead of searching them. The git base path
+/// is searched from the directory of the first one and all of them have to be inside of it.
+pub fn manifest_paths_and_git_base(manifests: &[PathBuf], work_tree: Option<&Path>, git_dir: Option<&Path>)
+    -> Result<(Vec<PathBuf>, Option<PathBuf>)> {
+    let mut cargo_tomls: Vec<PathBuf> = vec![];
+    for manifest in manifests {
+        if manifest.file_name().is_none_or(|n| n != "Cargo.toml") {
+            return Err(Error::Usage(format!("The manifest path has to point to a Cargo.toml file ({})", manifest.display())));
+        }
+        if !manifest.is_file() {
+            return Err(Error::Discovery(format!("Manifest does not exist ({})", manifest.display())));
+        }
+        if !cargo_tomls.contains(manifest) { cargo_tomls.push(manifest.clone()); }
+    }
+    let absolute = |cts: Vec<PathBuf>| cts.into_iter().map(|ct| fs::canonicalize(&ct)
+        .map_err(|e| Error::io(format!("Invalid manifest path '{}'", ct.display()), e))).collect::<Result<Vec<_>>>();
+    let search = |cts: &[PathBuf]| -> Result<Option<PathBuf>> {
+        let Some(dir) = cts.first().and_then(|ct| ct.parent()) else { return Ok(None); };
+        let dir = if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir.to_path_buf() };
+        Ok(find_cargo_tomls_and_git_base(dir, false, work_tree, git_dir)?.1)
+    };
+    // the search of the git base path is absolute with an explicit work tree
+    if work_tree.is_some() { cargo_tomls = absolute(cargo_tomls)?; }
+    let mut git_base_path = search(&cargo_tomls)?;
+    // a relative path is not followed above the current directory, e.g. for the Cargo.toml of a member
+    if git_base_path.is_none() && work_tree.is_none() {
+        cargo_tomls = absolute(cargo_tomls)?;
+        git_base_path = search(&cargo_tomls)?;
+    }
+    let Some(first) = cargo_tomls.first() else { return Ok((cargo_tomls, None)); };
+    if let Some(base) = git_base_path.as_deref().and_then(|b| fs::canonicalize(b).ok()) {
+        let outside = cargo_tomls.iter().find(|ct| fs::canonicalize(ct).is_ok_and(|ct| !ct.starts_with(&base)));
+        if let Some(outside) = outside {
+            return Err(Error::Discovery(format!("Manifest '{}' is not in the git repository of '{}'", outside.display(), first.display())));
+        }
+    }
+    for ct in &cargo_tomls { tracing::debug!(target: crate::logging::DISCOVERY, "Using manifest {}", ct.display()); }
+    Ok((cargo_tomls, git_base_path))
+}
+
+/// Removes the cargo.toml files matching one of the exclude globs, see `glob_match`. A pattern
+/// matches the path of the file or of its directory relative to the git base path.
+pub fn exclude_cargo_tomls(cargo_tomls: Vec<PathBuf>, git_base_path: Option<&Path>, excludes: &[String]) -> Vec<PathBuf> {
+    if excludes.is_empty() { return cargo_tomls; }
+    let relative = |p: &Path| slash_path(p, git_base_path);
+    cargo_tomls.into_iter().filter(|ct| {
+        let (file, dir) = (relative(ct), relative(ct.parent().unwrap_or(Path::new(""))));
+        let excluded = excludes.iter().any(|e| glob_match(e, &file) || (!dir.is_empty() && glob_match(e, &dir)));
+        if excluded { tracing::info!(target: crate::logging::DISCOVERY, "Excluding {}", ct.display()); }
+        !excluded
+    }).collect()
+}
+
+/// Name of the version tags: the version between a prefix and a suffix, e.g. `v1.2.0` or
+/// `mycrate-v1.2.0`. Given by `--tag-format` or `--git-prefix-for-tag`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagFormat {
+    pub prefix: String,
+    pub suffix: String,
+}
+impl TagFormat {
+    pub fn from_prefix(prefix: &str) -> TagFormat {
+        TagFormat { prefix: prefix.to_string(), suffix: String::new() }
+    }
+
+    /// Parses a template like `release/{version}` or `{name}-v{version}`. `{name}` is replaced by
+    /// the package name, which has to be known if it is used.
+    pub fn parse(template: &str, name: Option<&str>) -> Result<TagFormat> {
+        let (prefix, suffix) = template.split_once("{version}")
+            .ok_or_else(|| Error::Usage(format!("Tag format '{}' has no {{version}} placeholder", template)))?;
+        let fill = |part: &str| -> Result<String> {
+            let part = match name {
+                Some(name) => part.replace("{name}", name),
+                None if part.contains("{name}") => return Err(Error::Usage(format!(
+                    "Tag format '{}' uses {{name}} but the selected cargo.toml files do not have a single package name", template))),
+                None => part.to_string(),
+            };
+            if part.contains(['{', '}']) {
+                return Err(Error::Usage(format!("Tag format '{}' has an unknown placeholder, only {{name}} and {{version}} are supported", template)));
+            }
+            Ok(part)
+        };
+        let format = TagFormat { prefix: fill(prefix)?, suffix: fill(suffix)? };
+        if !git2::Reference::is_valid_name(&format!("refs/tags/{}", format.tag("0.0.0"))) {
+            return Err(Error::Usage(format!("Tag format '{}' does not result in a valid tag name", template)));
+        }
+        Ok(format)
+    }
+
+    pub fn tag(&self, version: impl Display) -> String {
+        format!("{}{}{}", self.prefix, version, self.suffix)
+    }
+
+    /// Returns the part of the tag name between prefix and suffix if the tag has this format.
+    pub fn version_part<'a>(&self, tag: &'a str) -> Option<&'a str> {
+        tag.strip_prefix(&self.prefix)?.strip_suffix(&self.suffix)
+    }
+}
+impl Display for TagFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{{version}}{}", self.prefix, self.suffix)
+    }
+}
+
+/// Checks the syntax of `--tag-format` at startup, the package name is filled in later.
+pub fn validate_tag_format(template: &str) -> std::result::Result<String, String> {
+    TagFormat::parse(template, Some("name")).map(|_| template.to_string()).map_err(|e| e.to_string())
+}
+
+pub fn list_version_tags(repo: &Repository, tag_format: &TagFormat) -> Result<Vec<String>> {
+    let tns = crate::backend::open_backend(repo)?.tag_names()?;
+    Ok(tns.into_iter().filter(|tn| tag_format.version_part(tn).is_some()).collect())
+}
+
+pub fn find_git_remote(repo: &Repository, remote: Option<String>) -> Result<Remote<'_>> {
+    let git_remote_name = match remote {
+        None => {
+            tracing::info!(target: crate::logging::GIT, "Setting git remote to 'origin' as it was not specified");
+            "origin".to_string()
+        }
+        Some(r) => r
+    };
+
+    match repo.find_remote(&git_remote_name) {
+        Ok(r) => {
+            tracing::debug!(target: crate::logging::GIT, "Using remote '{}' ({})", git_remote_name, r.url().unwrap_or(""));
+            if let Some(host) = r.url().and_then(http_host) { let _ = RELEASE_REMOTE_HOST.set(host); }
+            Ok(r)
+        },
+        Err(e) => Err(Error::remote(format!("Failed to find git remote '{}'", git_remote_name), e)),
+    }
+}
+
+/// Returns the remote and the remote branch the branch tracks (`branch.<name>.remote` and
+/// `branch.<name>.merge`), i.e. where a plain `git push` would push to.
+pub fn branch_upstream(repo: &Repository, branch: &str) -> Result<(String, String)> {
+    let ref_name = format!("refs/heads/{}", branch);
+    let not_found = |e: git2::Error| if e.code() == git2::ErrorCode::NotFound {
+        Error::Usage(format!("Branch '{}' has no upstream (see git branch --set-upstream-to) - use --remote instead", branch))
+    } else { Error::git(format!("Could not read the upstream of branch '{}'", branch), e) };
+    let remote = repo.branch_upstream_remote(&ref_name).map_err(not_found)?;
+    let merge = repo.branch_upstream_merge(&ref_name).map_err(not_found)?;
+    let (Some(remote), Some(merge)) = (remote.as_str(), merge.as_str()) else {
+        return Err(Error::Repository(format!("Upstream of branch '{}' is not valid utf-8", branch)));
+    };
+    let merge = merge.strip_prefix("refs/heads/").ok_or_else(|| Error::Repository(format!(
+        "Upstream '{}' of branch '{}' is not a branch", merge, branch)))?;
+    Ok((remote.to_string(), merge.to_string()))
+}
+
+/// Identity of the commits and tags created by the tool. Without an override, `user.name` and
+/// `user.email` of the git config are used like by git.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    /// `--author` as name and email
+    pub author: Option<(String, String)>,
+    /// `--committer`, also used as tagger
+    pub committer: Option<(String, String)>,
+    /// `--commit-date` or `SOURCE_DATE_EPOCH` instead of the current time
+    pub time: Option<git2::Time>,
+}
+static IDENTITY: OnceLock<Identity> = OnceLock::new();
+pub fn set_identity(identity: Identity) { let _ = IDENTITY.set(identity); }
+
+/// Identity (name, email) of `--bot`, the GitHub Actions bot which GitHub shows with its avatar.
+pub const BOT_IDENTITY: (&str, &str) = ("github-actions[bot]", "41898282+github-actions[bot]@users.noreply.github.com");
+
+/// Parses an identity like `Name <email>`.
+pub fn parse_identity(s: &str) -> std::result::Result<(String, String), String> {
+    let re = Regex::new(r"^\s*([^<>]*[^<>\s])\s*<([^<>\s]+)>\s*$").unwrap();
+    let c = re.captures(s).ok_or_else(|| format!("'{}' is not an identity like 'Name <email>'", s))?;
+    Ok((c[1].to_string(), c[2].to_string()))
+}
+
+fn signature(repo: &Repository, identity: Option<&(String, String)>) -> Result<Signature<'static>> {
+    let time = IDENTITY.get().and_then(|i| i.time);
+    match (identity, time) {
+        (Some((name, email)), Some(time)) => Signature::new(name, email, &time),
+        (Some((name, email)), None) => Signature::now(name, email),
+        (None, Some(time)) => repo.signature().and_then(|s| Signature::new(s.name().unwrap_or(""), s.email().unwrap_or(""), &time)),
+        (None, None) => repo.signature(),
+    }.map_err(|e| Error::git("Could not determine git signature", e))
+}
+pub fn author_signature(repo: &Repository) -> Result<Signature<'static>> {
+    signature(repo, IDENTITY.get().and_then(|i| i.author.as_ref()))
+}
+pub fn committer_signature(repo: &Repository) -> Result<Signature<'static>> {
+    signature(repo, IDENTITY.get().and_then(|i| i.committer.as_ref()))
+}
+
+/// Private key for ssh remotes, used instead of the keys git2_credentials would try.
+#[derive(Clone, Debug)]
+pub struct SshKey {
+    pub private_key: PathBuf,
+    pub passphrase: Option<String>,
+}
+impl SshKey {
+    /// Checks the key file and reads the passphrase from the given environment variable.
+    pub fn new(private_key: PathBuf, passphrase_env: Option<&str>) -> Result<SshKey> {
+        if !private_key.is_file() {
+            return Err(Error::Usage(format!("SSH key '{}' does not exist", private_key.display())));
+        }
+        let passphrase = match passphrase_env {
+            None => None,
+            Some(var) => Some(std::env::var(var)
+                .map_err(|_| Error::Usage(format!("Environment variable '{}' for the ssh key passphrase is not set", var)))?),
         };
-        let toml = match cct_content.parse::<DocumentMut>() {
-            Ok(v) => v, Err(e) => {
-                print_error(format!("Could not parse toml form file '{}': {:?}", cct.display(), e)); }
+        Ok(SshKey { private_key, passphrase })
+    }
+}
+
+/// Host (with port) of an http(s) remote url, None for other urls.
+pub fn http_host(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    let host = rest.split('/').next()?.rsplit('@').next()?;
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+/// Returns the token for the http(s) remote url. `GIT_TOKEN` is only offered to the token host
+/// (`--token-host` or the host of the release remote) and `GITHUB_TOKEN` only to github.com or the
+/// `GITHUB_SERVER_URL` of GitHub Actions, so a token never reaches a foreign host.
+pub fn find_git_token(url: &str, token_host: Option<&str>, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let host = http_host(url)?;
+    let github_server = env("GITHUB_SERVER_URL").and_then(|u| http_host(&u));
+    let github = host == "github.com" || github_server.as_deref() == Some(host.as_str());
+    let token = |k: &str| env(k).filter(|t| !t.is_empty());
+    token_host.is_some_and(|h| h.eq_ignore_ascii_case(&host)).then(|| token("GIT_TOKEN")).flatten()
+        .or_else(|| github.then(|| token("GITHUB_TOKEN")).flatten())
+}
+
+/// Authentication settings given on the command line, used by all fetches and pushes.
+#[derive(Clone, Debug, Default)]
+pub struct AuthOptions {
+    pub ssh_key: Option<SshKey>,
+    /// Host `GIT_TOKEN` is offered to, the host of the release remote if not set
+    pub token_host: Option<String>,
+    /// User name for the token, the user of the remote url or `x-access-token` if not set
+    pub token_user: Option<String>,
+}
+static AUTH_OPTIONS: OnceLock<AuthOptions> = OnceLock::new();
+pub fn set_auth_options(options: AuthOptions) { let _ = AUTH_OPTIONS.set(options); }
+/// Host of the first remote found by [find_git_remote], the remote of the release.
+static RELEASE_REMOTE_HOST: OnceLock<String> = OnceLock::new();
+
+pub fn create_remote_callbacks<'a>(repo: &Repository) -> Result<RemoteCallbacks<'a>> {
+    let mut cb = RemoteCallbacks::new();
+    let git_config = repo.config().map_err(|e| Error::git("Could not read git config", e))?;
+    let mut ch = CredentialHandler::new(git_config);
+    let options = AUTH_OPTIONS.get().cloned().unwrap_or_default();
+    let (ssh_key, token_user) = (options.ssh_key, options.token_user);
+    let token_host = options.token_host.or_else(|| RELEASE_REMOTE_HOST.get().cloned());
+    let (mut ssh_key_tried, mut token_tried) = (false, false);
+    cb.credentials(move |url, username, allowed| {
+        tracing::trace!(target: crate::logging::AUTH, "Credentials requested for {} (user {:?}, allowed {:?})", url, username, allowed);
+        if let Some(key) = &ssh_key && allowed.contains(CredentialType::SSH_KEY) {
+            // libgit2 asks again if the key is rejected, only offer it once
+            if ssh_key_tried {
+                return Err(git2::Error::from_str(&format!("ssh key '{}' was rejected", key.private_key.display())));
+            }
+            ssh_key_tried = true;
+            tracing::debug!(target: crate::logging::AUTH, "Using ssh key {} for {}", key.private_key.display(), url);
+            let public_key = PathBuf::from(format!("{}.pub", key.private_key.display()));
+            return Cred::ssh_key(username.unwrap_or("git"), public_key.is_file().then_some(public_key.as_path()),
+                                 &key.private_key, key.passphrase.as_deref());
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && let Some(token) = find_git_token(url, token_host.as_deref(), |k| std::env::var(k).ok()) {
+            if token_tried {
+                return Err(git2::Error::from_str(&format!("token was rejected by {}", url)));
+            }
+            token_tried = true;
+            let user = token_user.as_deref().or(username).unwrap_or("x-access-token");
+            tracing::debug!(target: crate::logging::AUTH, "Using token of the environment as user {} for {}", user, url);
+            return Cred::userpass_plaintext(user, &token);
+        }
+        let r = ch.try_next_credential(url, username, allowed);
+        if let Err(e) = &r { tracing::debug!(target: crate::logging::AUTH, "No credentials for {}: {}", url, e); }
+        r
+    });
+    Ok(cb)
+}
+
+/// Determines the proxy for an http(s) remote like git does: `http.proxy` or `https.proxy` from the
+/// git config first, then the proxy environment variables unless the host is excluded by `no_proxy`.
+pub fn find_proxy(config: &Config, remote_url: &str, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let https = remote_url.starts_with("https://");
+    if !https && !remote_url.starts_with("http://") { return None; }
+    let config_keys: &[&str] = if https { &["https.proxy", "http.proxy"] } else { &["http.proxy"] };
+    if let Some(p) = config_keys.iter().filter_map(|k| config.get_string(k).ok()).find(|p| !p.is_empty()) {
+        return Some(p);
+    }
+
+    let host = remote_url.split("://").nth(1).unwrap_or("").split(['/', ':']).next().unwrap_or("")
+        .rsplit('@').next().unwrap_or("");
+    let no_proxy = env("no_proxy").or_else(|| env("NO_PROXY")).unwrap_or_default();
+    let excluded = no_proxy.split(',').map(|e| e.trim().trim_start_matches('.')).filter(|e| !e.is_empty())
+        .any(|e| e == "*" || host == e || host.ends_with(&format!(".{e}")));
+    if excluded { return None; }
+    let env_keys: &[&str] = if https { &["https_proxy", "HTTPS_PROXY", "all_proxy", "ALL_PROXY"] }
+        else { &["http_proxy", "all_proxy", "ALL_PROXY"] };
+    env_keys.iter().filter_map(|k| env(k)).find(|p| !p.is_empty())
+}
+
+fn create_proxy_options<'a>(repo: &Repository, remote_url: &str) -> Result<ProxyOptions<'a>> {
+    let config = repo.config().map_err(|e| Error::git("Could not read git config", e))?;
+    let mut po = ProxyOptions::new();
+    match find_proxy(&config, remote_url, |k| std::env::var(k).ok()) {
+        Some(proxy) => { tracing::debug!(target: crate::logging::AUTH, "Using proxy {} for {}", proxy, remote_url); po.url(&proxy); }
+        None => { po.auto(); }
+    }
+    Ok(po)
+}
+
+/// Error of a push or fetch. Connection failures mention the proxy in use, as an unset or wrong
+/// proxy is the usual cause in corporate networks.
+pub fn network_error(repo: &Repository, remote_url: &str, msg: impl Into<String>, e: git2::Error) -> Error {
+    let mut msg = msg.into();
+    if matches!(e.class(), git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssl | git2::ErrorClass::Os)
+        && let Ok(config) = repo.config() {
+        match find_proxy(&config, remote_url, |k| std::env::var(k).ok()) {
+            Some(proxy) => msg.push_str(&format!(" (using proxy {})", proxy)),
+            None if remote_url.starts_with("http") => msg.push_str(" (no proxy configured, see http.proxy and HTTPS_PROXY)"),
+            None => (),
+        }
+    }
+    Error::remote(msg, e)
+}
+
+static PUSH_VIA_CLI: AtomicBool = AtomicBool::new(false);
+pub fn set_push_via_cli(via_cli: bool) { PUSH_VIA_CLI.store(via_cli, Ordering::Relaxed); }
+
+/// Retries of a failed push and the time all attempts together may take.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PushRetry {
+    pub retries: u32,
+    pub timeout: Option<Duration>,
+}
+static PUSH_RETRY: OnceLock<PushRetry> = OnceLock::new();
+pub fn set_push_retry(retry: PushRetry) { let _ = PUSH_RETRY.set(retry); }
+
+/// Delay before the retry after the given number of failed attempts: 1s, 2s, 4s, ... up to 30s.
+pub fn push_backoff(failed_attempts: u32) -> Duration {
+    Duration::from_secs(1u64.checked_shl(failed_attempts.saturating_sub(1)).unwrap_or(u64::MAX).min(30))
+}
+
+/// Whether a failed push may succeed if retried: a network error of libgit2 or, as its output is
+/// not evaluated, any failure of `git push`.
+pub fn is_transient_push_error(e: &Error, via_cli: bool) -> bool {
+    match e {
+        Error::Remote { source, .. } => via_cli || matches!(source.class(),
+            git2::ErrorClass::Net | git2::ErrorClass::Http | git2::ErrorClass::Ssl | git2::ErrorClass::Os),
+        _ => false,
+    }
+}
+
+/// Why the remote rejected a reference, derived from its status and the messages of the remote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    ProtectedBranch,
+    HookDeclined,
+    NonFastForward,
+    Other,
+}
+impl Rejection {
+    pub fn classify(status: &str, remote_messages: &[String]) -> Rejection {
+        let text = format!("{} {}", status, remote_messages.join(" ")).to_lowercase();
+        if ["protected branch", "gh006", "gh013", "not allowed to push", "not allowed to force push"].iter().any(|p| text.contains(p)) {
+            Rejection::ProtectedBranch
+        } else if text.contains("hook declined") || text.contains("pre-receive") {
+            Rejection::HookDeclined
+        } else if ["non-fast-forward", "fetch first", "stale info", "not fast forward"].iter().any(|p| text.contains(p)) {
+            Rejection::NonFastForward
+        } else { Rejection::Other }
+    }
+
+    fn advice(&self) -> &'static str {
+        match self {
+            Rejection::ProtectedBranch => "The branch is protected on the remote and does not accept direct pushes. Release with \
+                --create-pr to open a pull request for the version bump, with --release-branch to push it to a new branch, \
+                or with --push tags if only the tag may be pushed.",
+            Rejection::HookDeclined => "A hook on the remote declined the push, see the messages of the remote for the reason.",
+            Rejection::NonFastForward => "The remote branch has commits which are not in the local branch. Pull them and release again.",
+            Rejection::Other => "See the messages of the remote for the reason.",
+        }
+    }
+}
+
+/// Outcome of a push per reference, together with the messages the remote sent (`remote: ...`).
+#[derive(Debug, Default)]
+pub struct PushReport {
+    pub accepted: Vec<String>,
+    pub rejected: Vec<(String, String)>,
+    pub remote_messages: Vec<String>,
+}
+impl PushReport {
+    /// Parses the output of `git push --porcelain`: a line per reference with a flag (`!` for a
+    /// rejected one), `<src>:<dst>` and a summary, separated by tabs.
+    pub fn from_porcelain(output: &str) -> PushReport {
+        let mut report = PushReport::default();
+        for line in output.lines() {
+            let mut fields = line.split('\t');
+            let (Some(flag), Some(refs), summary) = (fields.next(), fields.next(), fields.next().unwrap_or("")) else { continue; };
+            let Some(dst) = refs.split(':').nth(1) else { continue; };
+            match flag {
+                "!" => report.rejected.push((dst.to_string(), summary.trim_start_matches("[remote rejected]").trim_start_matches("[rejected]")
+                    .trim().trim_matches(|c| c == '(' || c == ')').to_string())),
+                _ => report.accepted.push(dst.to_string()),
+            }
+        }
+        report
+    }
+
+    /// Fails with an explanation if the remote rejected a reference. References the remote
+    /// accepted nonetheless are listed, they are not removed by the rollback.
+    pub fn into_result(self, remote_name: &str) -> Result<()> {
+        let Some((_, first_status)) = self.rejected.first() else { return Ok(()); };
+        let rejection = Rejection::classify(first_status, &self.remote_messages);
+        let mut msg = format!("Push to '{}' was rejected: {}", remote_name,
+                              self.rejected.iter().map(|(r, s)| format!("{} ({})", r, s)).collect::<Vec<_>>().join(", "));
+        self.remote_messages.iter().for_each(|m| msg.push_str(&format!("\n{INDENT}remote: {}", m)));
+        msg.push_str(&format!("\n{INDENT}{}", rejection.advice()));
+        if !self.accepted.is_empty() {
+            msg.push_str(&format!("\n{INDENT}The remote accepted {} nonetheless, delete with 'git push {} --delete {}' if needed.",
+                                  self.accepted.join(", "), remote_name, self.accepted.join(" ")));
+        }
+        Err(Error::PushRejected(msg))
+    }
+}
+
+/// Pushes the refspecs to the remote. Each lease only lets the push update its reference if the
+/// remote still has the expected object, see `create_push_options_with_lease`. With
+/// `--push-via-cli`, `git push` is run instead of libgit2, so the credential helpers, ssh config
+/// and proxies of the user apply. Transient failures are retried with exponential backoff as set
+/// with `set_push_retry`, until the retries are exhausted or the timeout is reached.
+pub fn push_refs(repo: &Repository, remote: &mut Remote, refspecs: &[String], leases: Vec<(String, Oid)>) -> Result<()> {
+    let retry = PUSH_RETRY.get().copied().unwrap_or_default();
+    let deadline = retry.timeout.map(|t| Instant::now() + t);
+    let via_cli = PUSH_VIA_CLI.load(Ordering::Relaxed);
+    let mut failed = 0;
+    loop {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        let e = match push_refs_once(repo, remote, refspecs, leases.clone(), remaining) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
         };
+        failed += 1;
+        let backoff = push_backoff(failed);
+        if failed > retry.retries || !is_transient_push_error(&e, via_cli) { return Err(e); }
+        if let Some(d) = deadline && Instant::now() + backoff >= d {
+            return Err(Error::remote(format!("Push timed out after {} attempt(s)", failed),
+                                     git2::Error::from_str(&e.to_string())));
+        }
+        print_warn(format!("{} - retrying in {}s ({}/{})", e, backoff.as_secs(), failed, retry.retries));
+        std::thread::sleep(backoff);
+    }
+}
+
+fn push_refs_once(repo: &Repository, remote: &mut Remote, refspecs: &[String], leases: Vec<(String, Oid)>,
+                  timeout: Option<Duration>) -> Result<()> {
+    let remote_url = remote.url().unwrap_or("").to_string();
+    let remote_name = remote.name().map(String::from).unwrap_or_else(|| remote_url.clone());
+    let _span = tracing::debug_span!(target: crate::logging::GIT, "push", remote = %remote_name).entered();
+    tracing::debug!(target: crate::logging::GIT, "git push {} {}", remote_name, refspecs.join(" "));
+    if !PUSH_VIA_CLI.load(Ordering::Relaxed) {
+        if let Some(timeout) = timeout {
+            // libgit2 has no overall timeout, but one for connecting and one for each read and write
+            let ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+            unsafe {
+                git2::opts::set_server_connect_timeout_in_milliseconds(ms)
+                    .and_then(|_| git2::opts::set_server_timeout_in_milliseconds(ms))
+                    .map_err(|e| Error::git("Could not set the push timeout", e))?;
+            }
+        }
+        let bar = push_progress_bar();
+        let report = Rc::new(RefCell::new(PushReport::default()));
+        let mut po = push_options(repo, &remote_url, leases, Some(&bar), Some(&report))?;
+        // the step spinner would be drawn over the progress bar
+        let r = suspend_step(|| remote.push(refspecs, Some(&mut po)));
+        bar.finish_and_clear();
+        drop(po);
+        r.map_err(|e| network_error(repo, &remote_url, "Error pushing to git remote", e))?;
+        // libgit2 only reports the references rejected by the remote through the callback
+        return Rc::try_unwrap(report).map(RefCell::into_inner).unwrap_or_default().into_result(&remote_name);
+    }
+
+    // an empty expected value lets git check that the reference does not exist on the remote
+    let lease_args = leases.iter().map(|(name, oid)|
+        format!("--force-with-lease={}:{}", name, if oid.is_zero() { String::new() } else { oid.to_string() }));
+    // a '+' would force the update regardless of the lease, which already allows a forced update
+    let refspecs = refspecs.iter().map(|rs| match rs.strip_prefix('+') {
+        Some(spec) if leases.iter().any(|(name, _)| spec.rsplit(':').next() == Some(name.as_str())) => spec,
+        _ => rs.as_str(),
+    });
+    // the porcelain output tells which references the remote rejected and why
+    let mut child = Command::new("git").arg("--git-dir").arg(repo.path()).args(["push", "--atomic", "--porcelain"]).args(lease_args)
+        .arg(&remote_name).args(refspecs).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        .map_err(|e| Error::io("Could not run git push", e))?;
+    let porcelain = child.stdout.take().map(|mut out| std::thread::spawn(move || {
+        let mut s = String::new();
+        std::io::Read::read_to_string(&mut out, &mut s).map(|_| s).unwrap_or_default()
+    }));
+    // the progress and the messages of the remote are still shown, but kept to explain a rejection
+    let messages = child.stderr.take().map(|mut err| std::thread::spawn(move || {
+        let (mut buf, mut all) = ([0u8; 4096], vec![]);
+        while let Ok(n @ 1..) = std::io::Read::read(&mut err, &mut buf) {
+            if is_progress_enabled() { let _ = std::io::Write::write_all(&mut std::io::stderr(), &buf[..n]); }
+            all.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&all).lines().filter_map(|l| l.strip_prefix("remote:")).map(str::trim)
+            .filter(|l| !l.is_empty() && !l.contains("% (")).map(String::from).collect::<Vec<_>>()
+    }));
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let status = suspend_step(|| loop {
+        if let Some(status) = child.try_wait()? { return Ok(status); }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            child.kill()?;
+            child.wait()?;
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "git push timed out"));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    });
+    let status = match status {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut =>
+            return Err(Error::remote("Error pushing to git remote", git2::Error::from_str("git push timed out"))),
+        Err(e) => return Err(Error::io("Could not wait for git push", e)),
+    };
+    let mut report = PushReport::from_porcelain(&porcelain.and_then(|h| h.join().ok()).unwrap_or_default());
+    report.remote_messages = messages.and_then(|h| h.join().ok()).unwrap_or_default();
+    if !status.success() {
+        if !report.rejected.is_empty() { return report.into_result(&remote_name); }
+        return Err(Error::remote("Error pushing to git remote", git2::Error::from_str(&format!("git push failed ({})", status))));
+    }
+    Ok(())
+}
+
+pub fn create_push_options<'a>(repo: &Repository, remote_url: &str) -> Result<PushOptions<'a>> {
+    push_options(repo, remote_url, vec![], None, None)
+}
+
+/// Creates push options which only update a remote reference if it still points to the expected
+/// object, like `git push --force-with-lease=<ref>:<expected>`. A zero oid expects the reference
+/// to not exist on the remote. Concurrent changes on the remote are detected instead of overwritten.
+pub fn create_push_options_with_lease<'a>(repo: &Repository, remote_url: &str, leases: Vec<(String, Oid)>) -> Result<PushOptions<'a>> {
+    push_options(repo, remote_url, leases, None, None)
+}
+
+fn push_options<'a>(repo: &Repository, remote_url: &str, leases: Vec<(String, Oid)>, progress: Option<&ProgressBar>,
+                    report: Option<&Rc<RefCell<PushReport>>>) -> Result<PushOptions<'a>> {
+    let mut cb = create_remote_callbacks(repo)?;
+    if let Some(report) = report {
+        let updates = report.clone();
+        cb.push_update_reference(move |name, status| {
+            let mut report = updates.borrow_mut();
+            match status {
+                Some(status) => report.rejected.push((name.to_string(), status.to_string())),
+                None => report.accepted.push(name.to_string()),
+            }
+            Ok(())
+        });
+        let messages = report.clone();
+        cb.sideband_progress(move |data| {
+            let text = String::from_utf8_lossy(data);
+            messages.borrow_mut().remote_messages.extend(text.split(['\n', '\r']).map(str::trim)
+                .filter(|l| !l.is_empty() && !l.contains("% (")).map(String::from));
+            true
+        });
+    }
+    if !leases.is_empty() {
+        cb.push_negotiation(move |updates| {
+            for u in updates {
+                let Some(name) = u.dst_refname() else { continue; };
+                if let Some((_, expected)) = leases.iter().find(|(r, _)| r == name)
+                    && u.src() != *expected {
+                    return Err(git2::Error::from_str(&format!(
+                        "stale info, remote '{}' is at {} instead of the expected {}", name, u.src(), expected)));
+                }
+            }
+            Ok(())
+        });
+    }
+    if let Some(bar) = progress {
+        let packing = bar.clone();
+        cb.pack_progress(move |_, current, total| {
+            packing.set_length(total.max(current) as u64);
+            packing.set_position(current as u64);
+            packing.set_message("packing objects");
+        });
+        let transfer = bar.clone();
+        cb.push_transfer_progress(move |current, total, bytes| {
+            transfer.set_length(total.max(current) as u64);
+            transfer.set_position(current as u64);
+            transfer.set_message(format!("written, {}", HumanBytes(bytes as u64)));
+        });
+    }
+    let mut po = PushOptions::new();
+    po.remote_callbacks(cb);
+    po.proxy_options(create_proxy_options(repo, remote_url)?);
+    Ok(po)
+}
 
-        match Version::try_from(toml["package"]["version"].clone().to_string()) {
+/// Progress bar of a push showing the objects and bytes written. It is drawn to stderr and hidden
+/// if the progress output is disabled or stderr is not a terminal.
+fn push_progress_bar() -> ProgressBar {
+    if !is_progress_enabled() { return ProgressBar::hidden(); }
+    let bar = ProgressBar::new(0);
+    bar.set_style(ProgressStyle::with_template(&format!("{INDENT}[{{bar:30}}] {{pos}}/{{len}} objects {{msg}}"))
+        .unwrap().progress_chars("=> "));
+    bar
+}
+
+pub fn create_fetch_options<'a>(repo: &Repository, remote_url: &str) -> Result<FetchOptions<'a>> {
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(create_remote_callbacks(repo)?);
+    fo.proxy_options(create_proxy_options(repo, remote_url)?);
+    Ok(fo)
+}
+
+/// Opens the repository of the git base path. With `git_dir`, the git directory lives elsewhere
+/// (e.g. `git clone --separate-git-dir` or a CI cache) and the git base path is used as work tree.
+pub fn open_repository(git_base_path: &Path, git_dir: Option<&Path>) -> Result<Repository> {
+    tracing::debug!(target: crate::logging::DISCOVERY, "Opening git repository at {} (git dir {})", git_base_path.display(),
+        git_dir.map(|d| d.display().to_string()).unwrap_or_else(|| "discovered".to_string()));
+    let repo = match git_dir {
+        None => Repository::open(git_base_path),
+        Some(git_dir) => Repository::open(git_dir).and_then(|r| r.set_workdir(git_base_path, false).map(|_| r)),
+    }.map_err(|e| Error::Discovery(format!("Failed to open git repo: {}", e)))?;
+    if repo.is_bare() {
+        return Err(Error::Discovery("Cannot use bare repository".to_string()));
+    }
+    Ok(repo)
+}
+
+/// Fetches the branch from the remote into its remote-tracking reference and returns the fetched
+/// commit, or None if the remote does not have the branch.
+pub fn fetch_branch(repo: &Repository, remote: &mut Remote, branch: &str) -> Result<Option<Oid>> {
+    let remote_name = remote.name().unwrap_or("").to_string();
+    let tracking_ref = format!("refs/remotes/{}/{}", remote_name, branch);
+    let refspec = format!("+refs/heads/{}:{}", branch, tracking_ref);
+    let _span = tracing::debug_span!(target: crate::logging::GIT, "fetch", remote = %remote_name).entered();
+    tracing::debug!(target: crate::logging::GIT, "git fetch {} {}", remote_name, refspec);
+    // pruning removes a stale tracking reference, so it exists exactly if the remote has the branch,
+    // remote tags are not created locally, see `fetch_remote_tags`
+    let mut fetch_options = create_fetch_options(repo, remote.url().unwrap_or(""))?;
+    fetch_options.prune(git2::FetchPrune::On).download_tags(git2::AutotagOption::None);
+    remote.fetch(&[&refspec], Some(&mut fetch_options), None)
+        .map_err(|e| network_error(repo, remote.url().unwrap_or(""), format!("Could not fetch '{}' from '{}'", branch, remote_name), e))?;
+    match repo.refname_to_id(&tracking_ref) {
+        Ok(oid) => Ok(Some(oid)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(Error::git(format!("Could not resolve '{}'", tracking_ref), e)),
+    }
+}
+
+/// Fetches the tags of the remote (like `git ls-remote --tags`) and returns their names and
+/// targets. The tags are mirrored to `refs/cgvs/remote-tags/<remote>/` so local tags are untouched.
+pub fn fetch_remote_tags(repo: &Repository, remote: &mut Remote) -> Result<HashMap<String, Oid>> {
+    let remote_name = remote.name().unwrap_or("").to_string();
+    let namespace = format!("refs/cgvs/remote-tags/{}/", remote_name);
+    let refspec = format!("+refs/tags/*:{}*", namespace);
+    let _span = tracing::debug_span!(target: crate::logging::GIT, "fetch", remote = %remote_name).entered();
+    tracing::debug!(target: crate::logging::GIT, "git fetch {} {}", remote_name, refspec);
+    let mut fetch_options = create_fetch_options(repo, remote.url().unwrap_or(""))?;
+    fetch_options.prune(git2::FetchPrune::On).download_tags(git2::AutotagOption::None);
+    remote.fetch(&[&refspec], Some(&mut fetch_options), None)
+        .map_err(|e| network_error(repo, remote.url().unwrap_or(""), format!("Could not fetch tags from '{}'", remote_name), e))?;
+    let references = repo.references_glob(&format!("{}*", namespace))
+        .map_err(|e| Error::git("Could not read fetched tags", e))?;
+    Ok(references.flatten()
+        .filter_map(|r| Some((r.name()?.strip_prefix(&namespace)?.to_string(), r.target()?)))
+        .collect())
+}
+
+/// Returns the branch the release commit is pushed to: the checked out branch or, with a detached
+/// HEAD (e.g. a CI checkout), the branch given with `--branch`.
+pub fn release_branch(repo: &Repository, branch_option: Option<&str>) -> Result<Option<String>> {
+    let head = repo.head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    if !head.is_branch() {
+        if let Some(branch) = branch_option && !git2::Branch::name_is_valid(branch).unwrap_or(false) {
+            return Err(Error::Usage(format!("'{}' is not a valid branch name", branch)));
+        }
+        return Ok(branch_option.map(String::from));
+    }
+    let branch = head.shorthand().ok_or(Error::Repository("HEAD is not a valid utf-8 reference".into()))?;
+    if let Some(b) = branch_option && b != branch {
+        print_warn(format!("--branch {} is ignored as HEAD is on branch '{}'", b, branch));
+    }
+    Ok(Some(branch.to_string()))
+}
+
+/// Fetches the release branch and fails if HEAD is behind the remote (or only warns if
+/// `allow_behind` is set), so the release commit would not be rejected by the push. With
+/// `force_with_lease` (e.g. after a rebase) the remote commits are replaced instead. Returns the
+/// commit of the remote branch, which is the expected value of the lease.
+pub fn check_up_to_date(repo: &Repository, remote: &mut Remote, branch: &str, allow_behind: bool,
+                        force_with_lease: bool) -> Result<Option<Oid>> {
+    let head = repo.head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let local = head.target().ok_or(Error::Repository("HEAD does not point to a commit".into()))?;
+    let remote_name = remote.name().unwrap_or("").to_string();
+    let Some(upstream) = fetch_branch(repo, remote, branch)? else {
+        progress!("{INDENT}Branch '{}' does not exist on '{}' yet", branch, remote_name);
+        return Ok(None);
+    };
+    let (ahead, behind) = repo.graph_ahead_behind(local, upstream)
+        .map_err(|e| Error::git(format!("Could not compare '{}' with '{}'", branch, remote_name), e))?;
+    progress!("{INDENT}Branch '{}' is {} commit(s) ahead and {} behind '{}'", branch, ahead, behind, remote_name);
+    if behind > 0 && force_with_lease {
+        print_warn(format!("{} commit(s) of '{}/{}' are replaced (--force-with-lease at {})", behind, remote_name, branch, upstream));
+    } else if behind > 0 {
+        let msg = format!("Branch '{}' is {} commit(s) behind '{}/{}' - pull before releasing", branch, behind, remote_name, branch);
+        if !allow_behind { return Err(Error::Repository(format!("{} or use --allow-behind", msg))); }
+        print_warn(msg);
+    }
+    Ok(Some(upstream))
+}
+
+/// Fails if the release branch does not match one of the patterns (e.g. `release/*`). Patterns
+/// always match the full branch name. No patterns allow every branch.
+pub fn check_allowed_branch(branch: Option<&str>, patterns: &[String]) -> Result<()> {
+    if patterns.is_empty() { return Ok(()); }
+    if let Some(branch) = branch && patterns.iter().any(|p| glob_match(&format!("/{}", p.trim_start_matches('/')), branch)) {
+        return Ok(());
+    }
+    let current = match branch {
+        Some(branch) => format!("the release branch is '{}'", branch),
+        None => "HEAD is detached".to_string(),
+    };
+    Err(Error::BranchPolicy(format!("Releases are only allowed from the branches {} - {}", patterns.join(", "), current)))
+}
+
+/// Matches a path relative to the git base path against a gitignore-like glob: `*` and `?` do not
+/// match `/`, `**` matches across directories. A pattern without `/` matches the file name in any
+/// directory, a leading `/` anchors the pattern to the git base path.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let (anchored, pattern) = match pattern.strip_prefix('/') {
+        Some(p) => (true, p),
+        None => (pattern.contains('/'), pattern),
+    };
+    let mut re = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') { chars.next(); re.push_str("(?:.*/)?"); } else { re.push_str(".*"); }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).is_ok_and(|r| r.is_match(path))
+}
+
+/// Writes the file via a temporary file in the same directory which is synced to disk and renamed
+/// afterward, so a crash or a full disk never leaves a truncated file. The permissions of an
+/// existing file are kept.
+pub fn write_file_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".cgvs-tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(content)?;
+        if let Ok(meta) = fs::metadata(path) { file.set_permissions(meta.permissions())?; }
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    };
+    write().inspect_err(|_| { let _ = fs::remove_file(&tmp); })?;
+    // the rename itself is only durable once the directory is synced, which is not possible on all platforms
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let _ = fs::File::open(dir).and_then(|d| d.sync_all());
+    Ok(())
+}
+
+/// Preflight check that the files written by a release, their directories and the git directory
+/// are writable, so a read-only mount or a wrong user is reported before anything is modified.
+/// Files which do not exist yet only need a writable directory. In a linked worktree the objects
+/// and refs live in the common git directory, which is checked as well.
+pub fn check_writable(files: &[PathBuf], repo: &Repository) -> Result<()> {
+    let git_dir = repo.path();
+    for file in files.iter().filter(|f| f.exists()) {
+        fs::OpenOptions::new().append(true).open(file)
+            .map_err(|e| Error::io(format!("No write permission for file '{}'", file.display()), e))?;
+    }
+    let index = git_dir.join("index");
+    if index.exists() {
+        fs::OpenOptions::new().append(true).open(&index)
+            .map_err(|e| Error::io(format!("No write permission for git index '{}'", index.display()), e))?;
+    }
+
+    let mut dirs: Vec<PathBuf> = files.iter().filter_map(|f| f.parent())
+        .map(|d| if d.as_os_str().is_empty() { PathBuf::from(".") } else { d.to_path_buf() }).collect();
+    for git_dir in [git_dir, repo.commondir()] {
+        dirs.extend(["", "objects", "refs/heads", "refs/tags"].iter().map(|d| git_dir.join(d)).filter(|d| d.is_dir()));
+    }
+    dirs.sort();
+    dirs.dedup();
+    for dir in dirs {
+        let probe = dir.join(format!(".cgvs-preflight-{}", std::process::id()));
+        fs::OpenOptions::new().write(true).create_new(true).open(&probe)
+            .map_err(|e| Error::io(format!("Cannot create files in directory '{}'", dir.display()), e))?;
+        let _ = fs::remove_file(&probe);
+    }
+    Ok(())
+}
+
+/// Counts modified files in the working tree, untracked files are ignored.
+pub fn count_uncommitted_changes(repo: &Repository) -> Result<usize> {
+    let mut so = StatusOptions::new();
+    so.include_untracked(false); so.exclude_submodules(true);
+    so.recurse_ignored_dirs(false);
+    match repo.statuses(Some(&mut so)) {
+        Ok(statuses) => Ok(statuses.iter().count()),
+        Err(e) => Err(Error::git("Could not read git status", e)),
+    }
+}
+
+/// Returns the paths of tracked files with uncommitted changes, relative to the git base path.
+fn uncommitted_files(repo: &Repository) -> Result<Vec<String>> {
+    let mut so = StatusOptions::new();
+    so.include_untracked(false); so.exclude_submodules(true);
+    let statuses = repo.statuses(Some(&mut so)).map_err(|e| Error::git("Could not read git status", e))?;
+    Ok(statuses.iter().filter_map(|e| e.path().map(String::from)).collect())
+}
+
+/// Fails if one of the `written` files has uncommitted changes, as they would end up in the release
+/// commit (`--allow-dirty`) or conflict with the stashed changes (`--autostash`).
+pub fn check_written_files_unchanged(repo: &Repository, git_base_path: &Path, written: &[PathBuf], option: &str) -> Result<()> {
+    let written: Vec<String> = written.iter().map(|f| slash_path(f, Some(git_base_path))).collect();
+    match uncommitted_files(repo)?.into_iter().find(|p| written.contains(p)) {
+        Some(path) => Err(Error::Usage(format!("'{}' has uncommitted changes and is written by the release, which {} does not support", path, option))),
+        None => Ok(()),
+    }
+}
+
+/// Fails with the number of uncommitted changes unless they are tolerated by `--allow-dirty`,
+/// restricted to the paths matching one of the `--dirty-ignore` patterns if given. Changes of
+/// `written` files are never tolerated, changes of `extra` files (`--extra-file`) always, as they
+/// are committed with the release.
+pub fn check_uncommitted_changes(repo: &Repository, git_base_path: &Path, allow_dirty: bool, ignore: &[String],
+                                 written: &[PathBuf], extra: &[PathBuf]) -> Result<()> {
+    let extra: Vec<String> = extra.iter().map(|f| slash_path(f, Some(git_base_path))).collect();
+    let changes: Vec<String> = uncommitted_files(repo)?.into_iter().filter(|p| !extra.contains(p)).collect();
+    if !allow_dirty { return match changes.len() { 0 => Ok(()), n => Err(Error::DirtyTree(n)) }; }
+    check_written_files_unchanged(repo, git_base_path, written, "--allow-dirty")?;
+    let mut not_tolerated = 0;
+    for path in changes {
+        if ignore.is_empty() || ignore.iter().any(|p| glob_match(p, &path)) {
+            print_warn(format!("Ignoring uncommitted changes of '{}' (--allow-dirty)", path));
+        } else {
+            tracing::info!("Uncommitted changes of '{}' are not covered by --dirty-ignore", path);
+            not_tolerated += 1;
+        }
+    }
+    if not_tolerated > 0 { return Err(Error::DirtyTree(not_tolerated)); }
+    Ok(())
+}
+
+/// libgit2 needs a mutable repository for stashing, so a second handle of the same repository is used.
+fn reopen_repository(repo: &Repository) -> Result<Repository> {
+    let reopened = Repository::open(repo.path()).map_err(|e| Error::git("Could not open git repository", e))?;
+    if let Some(workdir) = repo.workdir() {
+        reopened.set_workdir(workdir, false).map_err(|e| Error::git("Could not open git repository", e))?;
+    }
+    Ok(reopened)
+}
+
+/// Stashes the uncommitted changes of tracked files (`--autostash`), untracked files are kept.
+/// Returns None if there was nothing to stash.
+pub fn stash_changes(repo: &Repository, message: &str) -> Result<Option<Oid>> {
+    let _span = tracing::debug_span!(target: crate::logging::GIT, "stash").entered();
+    let mut repo = reopen_repository(repo)?;
+    let sig = repo.signature().or_else(|_| git2::Signature::now("cgvs", "cgvs"))
+        .map_err(|e| Error::git("Could not determine git signature", e))?;
+    tracing::debug!(target: crate::logging::GIT, "git stash push -m '{}'", message);
+    match repo.stash_save(&sig, message, None) {
+        Ok(oid) => Ok(Some(oid)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(Error::git("Could not stash uncommitted changes", e)),
+    }
+}
+
+/// Applies and drops the stash created by `stash_changes`. On conflicts the stash is kept.
+pub fn pop_stash(repo: &Repository, stash: Oid) -> Result<()> {
+    let _span = tracing::debug_span!(target: crate::logging::GIT, "stash_pop", %stash).entered();
+    let mut repo = reopen_repository(repo)?;
+    let mut index = None;
+    repo.stash_foreach(|i, _, oid| { if *oid == stash { index = Some(i); } index.is_none() })
+        .map_err(|e| Error::git("Could not read stashes", e))?;
+    let index = index.ok_or_else(|| Error::Repository(format!("Stash {} not found", stash)))?;
+    tracing::debug!(target: crate::logging::GIT, "git stash pop stash@{{{}}}", index);
+    repo.stash_pop(index, None).map_err(|e| Error::git(
+        format!("Applying the autostash failed, the changes are safe in stash@{{{}}} ({})", index, stash), e))
+}
+
+/// Returns the tag with the highest version among all tags with the prefix.
+pub fn latest_version_tag(repo: &Repository, tag_format: &TagFormat) -> Result<Option<(String, Version)>> {
+    Ok(list_version_tags(repo, tag_format)?.into_iter()
+        .filter_map(|tn| Version::try_from(tag_format.version_part(&tn)?.to_string()).ok().map(|v| (tn, v)))
+        .max_by(|(_, a), (_, b)| a.cmp(b)))
+}
+
+/// Where the version of a cargo.toml is defined.
+#[derive(Debug, PartialEq)]
+pub enum ManifestVersion {
+    /// `package.version`
+    Package(String),
+    /// `workspace.package.version` of a virtual workspace root
+    Workspace(String),
+    /// `version.workspace = true`, the version of the workspace root applies
+    Inherited,
+    /// Virtual workspace root without `workspace.package.version`
+    Virtual,
+    /// A version which is no string, e.g. `version = 1`, with the type found
+    NotAString(&'static str),
+    Missing,
+}
+
+/// Looks up the version. The tables may be written as `[package]`, as dotted keys like
+/// `package.version = "1.0.0"` or as inline tables, the value is the string without quotes.
+pub fn manifest_version(toml: &DocumentMut) -> ManifestVersion {
+    let string_or = |v: &toml_edit::Item, f: fn(String) -> ManifestVersion| match v.as_str() {
+        Some(s) => f(s.to_string()),
+        None => ManifestVersion::NotAString(v.type_name()),
+    };
+    match toml.get("package") {
+        Some(package) => match package.get("version") {
+            Some(v) if v.as_table_like().and_then(|t| t.get("workspace")).and_then(|w| w.as_bool()) == Some(true) => ManifestVersion::Inherited,
+            Some(v) => string_or(v, ManifestVersion::Package),
+            None => ManifestVersion::Missing,
+        },
+        None => match toml.get("workspace").map(|w| w.get("package").and_then(|p| p.get("version"))) {
+            Some(Some(v)) => string_or(v, ManifestVersion::Workspace),
+            Some(None) => ManifestVersion::Virtual,
+            None => ManifestVersion::Missing,
+        },
+    }
+}
+
+/// Returns the cargo.toml of the workspace a member belongs to: the closest one with a
+/// `[workspace]` table in the parent directories.
+pub fn find_workspace_root(cargo_toml: &Path) -> Option<PathBuf> {
+    cargo_toml.parent()?.ancestors().skip(1).map(|d| d.join("Cargo.toml"))
+        .find(|ct| fs::read_to_string(ct).ok().and_then(|c| c.parse::<DocumentMut>().ok()).is_some_and(|t| t.contains_key("workspace")))
+}
+
+/// Replaces the string of the value, keeping its quote style and the whitespace and comments
+/// around it. Values which are no string are replaced by a basic string.
+pub fn set_string_value(value: &mut toml_edit::Value, new: &str) {
+    let raw = match value {
+        toml_edit::Value::String(s) => s.as_repr().and_then(|r| r.as_raw().as_str())
+            .map(|raw| raw.replacen(s.value().as_str(), new, 1)),
+        _ => None,
+    };
+    // a version has no characters which need escaping, so it can be put into the old quotes as is
+    let mut new_value = raw.and_then(|r| r.parse::<toml_edit::Value>().ok())
+        .filter(|v| v.as_str() == Some(new)).unwrap_or_else(|| new.into());
+    *new_value.decor_mut() = value.decor().clone();
+    *value = new_value;
+}
+
+/// Converts the line endings of the rewritten content to the ones of the original file (CRLF if its
+/// first line ends with it) and keeps whether the file ends with a newline, so a rewrite of a
+/// Windows checkout only changes the version line.
+pub fn keep_line_endings(original: &str, content: &str) -> String {
+    let crlf = original.split_inclusive('\n').next().is_some_and(|l| l.ends_with("\r\n"));
+    let eol = if crlf { "\r\n" } else { "\n" };
+    let mut content = content.replace("\r\n", "\n");
+    if crlf { content = content.replace('\n', eol); }
+    if original.is_empty() { return content; }
+    match (original.ends_with('\n'), content.ends_with('\n')) {
+        (true, false) => content.push_str(eol),
+        (false, true) => content.truncate(content.trim_end_matches(['\r', '\n']).len()),
+        _ => {},
+    }
+    content
+}
+
+/// Writes the toml to the file with the line endings of the file, see [keep_line_endings].
+pub fn write_toml(path: &Path, toml: &DocumentMut) -> std::io::Result<()> {
+    let original = fs::read_to_string(path).unwrap_or_default();
+    write_file_atomic(path, keep_line_endings(&original, &toml.to_string()).as_bytes())
+}
+
+/// Sets the item to the string, see [set_string_value]. A missing item is added.
+pub fn set_string_item(item: &mut toml_edit::Item, new: &str) {
+    match item.as_value_mut() {
+        Some(value) => set_string_value(value, new),
+        None => *item = toml_edit::value(new),
+    }
+}
+
+/// Updates the version requirements of the given packages in `[workspace.dependencies]`, keeping
+/// the operator, e.g. `=0.1.0` becomes `=0.2.0`. Only path dependencies (the workspace members) are
+/// changed. Returns whether anything was changed.
+pub fn update_workspace_dependencies(toml: &mut DocumentMut, packages: &[String], new_version: &str) -> bool {
+    let Some(deps) = toml.get_mut("workspace").and_then(|w| w.get_mut("dependencies")).and_then(|d| d.as_table_like_mut()) else {
+        return false;
+    };
+    let mut changed = false;
+    for (key, dep) in deps.iter_mut() {
+        let Some(dep) = dep.as_table_like_mut() else { continue; };
+        let name = dep.get("package").and_then(|p| p.as_str()).unwrap_or(key.get()).to_string();
+        if !packages.contains(&name) || !dep.contains_key("path") { continue; }
+        let Some(version) = dep.get_mut("version").and_then(|v| v.as_value_mut()) else { continue; };
+        let Some(req) = version.as_str().filter(|r| !r.contains(',')) else { continue; };
+        let op: String = req.chars().take_while(|c| "=^~<>".contains(*c) || c.is_whitespace()).collect();
+        set_string_value(version, &format!("{}{}", op, new_version));
+        tracing::debug!(target: crate::logging::DISCOVERY, "Updated workspace dependency {} to {}", name, version);
+        changed = true;
+    }
+    changed
+}
+
+/// Reads the version of the cargo.toml files. Virtual workspace roots without
+/// `workspace.package.version` are skipped with a warning, members inheriting the version get the
+/// version of their workspace root.
+pub fn read_version_tomls(cargo_tomls: &Vec<PathBuf>) -> Result<HashMap<PathBuf, (Version, DocumentMut)>> {
+    let mut cargo_content = HashMap::<PathBuf, (Version, DocumentMut)>::new();
+    let read = |cct: &Path| -> Result<DocumentMut> {
+        let cct_content = fs::read_to_string(cct)
+            .map_err(|e| Error::io(format!("Could not read file '{}'", cct.display()), e))?;
+        cct_content.parse::<DocumentMut>()
+            .map_err(|e| Error::Manifest(format!("Could not parse toml form file '{}': {}", cct.display(), e)))
+    };
+    for cct in cargo_tomls {
+        let toml = read(cct)?;
+        let version = match manifest_version(&toml) {
+            ManifestVersion::Package(v) | ManifestVersion::Workspace(v) => v,
+            ManifestVersion::Inherited => {
+                let root = find_workspace_root(cct).ok_or_else(|| Error::Manifest(format!(
+                    "'{}' inherits the version but no workspace root was found", cct.display())))?;
+                if !cargo_tomls.contains(&root) {
+                    return Err(Error::Manifest(format!("'{}' inherits the version of '{}', which has to be selected as well",
+                        cct.display(), root.display())));
+                }
+                match manifest_version(&read(&root)?) {
+                    ManifestVersion::Workspace(v) => v,
+                    _ => return Err(Error::Manifest(format!("'{}' inherits the version but '{}' has no workspace.package.version",
+                        cct.display(), root.display()))),
+                }
+            },
+            ManifestVersion::Virtual => {
+                print_warn(format!("Skipping virtual manifest '{}' without workspace.package.version", cct.display()));
+                continue;
+            },
+            ManifestVersion::NotAString(found) => return Err(Error::Manifest(format!(
+                "The version in toml file '{}' has to be a string like \"1.2.3\", found {}", cct.display(), found))),
+            ManifestVersion::Missing => return Err(Error::Manifest(format!("No package.version in toml file '{}'", cct.display()))),
+        };
+        match Version::try_from(version) {
             Ok(v) => { cargo_content.insert(cct.clone(), (v, toml)); },
-            Err(e) => { print_error(format!("Could not parse version from toml file '{}': {:?}", cct.display(), e)); }
+            Err(e) => return Err(Error::Manifest(format!("Could not parse version from toml file '{}': {}", cct.display(), e))),
         }
     }
-    cargo_content
+    if cargo_content.is_empty() && !cargo_tomls.is_empty() {
+        return Err(Error::Manifest("Only virtual manifests without a version selected, select the member crates instead".to_string()));
+    }
+    Ok(cargo_content)
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_version {
+    use super::*;
+
+    #[test]
+    fn test_version_increment() {
+        let v = Version::try_from("2024.10103.0".to_string()).unwrap();
+        assert_eq!(v.to_string(), "2024.10103.0");
+        assert_eq!(v.increment_clone(&IncrementVersionPart::Minor).unwrap().to_string(), "2024.10104.0");
+        let max = Version::try_from(format!("1.{}.3", u64::MAX)).unwrap();
+        assert_eq!(max.increment_clone(&IncrementVersionPart::Minor).unwrap_err().exit_code(), 4);
+        assert_eq!(max.increment_clone(&IncrementVersionPart::Major).unwrap().to_string(), "2.0.0");
+        assert!(Version::try_from("1.99999999999999999999.0".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_version_parse_strict() {
+        let v = |s: &str| Version::try_from(s.to_string());
+        assert!(v("foo1.2.3bar").is_err());
+        assert!(v("1.2.3bar").is_err());
+        assert!(v("\"1.2.3\"").is_err());
+        assert!(v("v1.2.3").is_err());
+        // leading zeros would be dropped when writing the version, so they are rejected
+        for invalid in ["01.2.3", "1.02.3", "1.2.03", "1.2.3-01", "1.2.3-rc.01"] { assert!(v(invalid).is_err(), "{}", invalid); }
+        // empty identifiers and repeated build metadata
+        for invalid in ["1.2.3-", "1.2.3-a..b", "1.2.3-a.", "1.2.3+", "1.2.3+a..b", "1.2.3+a+b", "1.2.3-rc+a+b"] {
+            assert!(v(invalid).is_err(), "{}", invalid);
+        }
+        for valid in ["0.0.0", "1.2.3-0", "1.2.3-0a.rc-1", "1.2.3+001.b-2", "1.2.3-rc.1+001"] {
+            assert_eq!(v(valid).unwrap().to_string(), valid);
+        }
+        assert_eq!(v("1.2.3-rc.1+build.5").unwrap().to_string(), "1.2.3-rc.1+build.5");
+        assert_eq!(v("1.2.3-rc.1+build.5").unwrap().pre_release(), Some("rc.1"));
+        assert_eq!(v("1.2.3+g10513fb").unwrap().without_build(), v("1.2.3").unwrap());
+        assert_eq!(v("1.2.3-rc.1").unwrap().increment_clone(&IncrementVersionPart::Patch).unwrap().to_string(), "1.2.4");
+
+        let mut sorted = ["1.2.3", "1.2.3-rc.10", "1.2.3-alpha", "1.2.3-rc.2", "1.2.2+b", "1.2.3-rc.2.1"].map(|s| v(s).unwrap());
+        sorted.sort();
+        assert_eq!(sorted.map(|v| v.to_string()), ["1.2.2+b", "1.2.3-alpha", "1.2.3-rc.2", "1.2.3-rc.2.1", "1.2.3-rc.10", "1.2.3"]);
+    }
+}
+
+#[cfg(test)]
+mod tests_glob {
+    use super::*;
+
+    #[test]
+    fn test_tag_format() {
+        let f = TagFormat::parse("{name}-v{version}", Some("demo")).unwrap();
+        assert_eq!(f.tag("1.2.0"), "demo-v1.2.0");
+        assert_eq!(f.version_part("demo-v1.2.0"), Some("1.2.0"));
+        assert_eq!(f.version_part("other-v1.2.0"), None);
+        let f = TagFormat::parse("release/{version}/final", None).unwrap();
+        assert_eq!((f.prefix.as_str(), f.suffix.as_str()), ("release/", "/final"));
+        assert_eq!(f.to_string(), "release/{version}/final");
+        assert!(TagFormat::parse("{name}-{version}", None).is_err());
+        assert!(TagFormat::parse("v{major}", None).is_err());
+        assert!(TagFormat::parse("v {version}", None).is_err());
+        assert!(validate_tag_format("{name}@{version}").is_ok());
+    }
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.md", "docs/api/README.md"));
+        assert!(!glob_match("/*.md", "docs/README.md"));
+        assert!(glob_match("docs/*.md", "docs/README.md"));
+        assert!(!glob_match("docs/*.md", "docs/api/README.md"));
+        assert!(glob_match("docs/**", "docs/api/README.md"));
+        assert!(glob_match("**/Cargo.lock", "Cargo.lock"));
+        assert!(glob_match("release/*", "release/1.2"));
+        assert!(glob_match("VERSION?", "VERSION2") && !glob_match("VERSION?", "VERSION"));
+        assert!(!glob_match("/main", "feature/main"));
+    }
 }
 
 // ********************************************************
@@ -209,11 +1682,85 @@ pub fn read_version_tomls(cargo_tomls: &Vec<PathBuf>) -> HashMap<PathBuf, (Versi
 mod tests_filter {
     use super::*;
 
+    #[test]
+    fn test_select_workspace_members() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"crates/*\", \"tool\"]\nexclude = [\"crates/old\"]\n").unwrap();
+        for d in ["crates/a", "crates/old", "tool", "examples/x"] {
+            fs::create_dir_all(root.join(d)).unwrap();
+            fs::write(root.join(d).join("Cargo.toml"), "[package]\nname = \"x\"\n").unwrap();
+        }
+        let cargo_tomls = ["Cargo.toml", "crates/a/Cargo.toml", "crates/old/Cargo.toml", "tool/Cargo.toml", "examples/x/Cargo.toml"]
+            .map(|p| root.join(p)).to_vec();
+        assert_eq!(select_workspace_members(cargo_tomls.clone()).unwrap(), vec![cargo_tomls[1].clone(), cargo_tomls[3].clone()]);
+        assert!(select_workspace_members(cargo_tomls[1..].to_vec()).is_err());
+    }
+    #[test]
+    fn test_update_workspace_dependencies() {
+        let mut toml: DocumentMut = "[workspace.dependencies]\na = { path = \"crates/a\", version = \"=0.1.0\" }\n\
+            b-renamed = { package = \"b\", path = \"crates/b\", version = \"0.1\" }\nserde = \"1.0\"\n\
+            ext = { version = \"0.1.0\" }\n".parse().unwrap();
+        let packages = ["a".to_string(), "b".to_string(), "ext".to_string()];
+        assert!(update_workspace_dependencies(&mut toml, &packages, "0.2.0"));
+        assert_eq!(toml.to_string(), "[workspace.dependencies]\na = { path = \"crates/a\", version = \"=0.2.0\" }\n\
+            b-renamed = { package = \"b\", path = \"crates/b\", version = \"0.2.0\" }\nserde = \"1.0\"\n\
+            ext = { version = \"0.1.0\" }\n");
+    }
+    #[test]
+    fn test_set_string_value() {
+        let mut toml: DocumentMut = "a = '0.1.0'   # keep\nb =  \"\"\"0.1.0\"\"\"\nc = 1 # number\n".parse().unwrap();
+        for key in ["a", "b", "c"] { set_string_item(&mut toml[key], "0.2.0"); }
+        set_string_item(&mut toml["d"], "0.2.0");
+        assert_eq!(toml.to_string(), "a = '0.2.0'   # keep\nb =  \"\"\"0.2.0\"\"\"\nc = \"0.2.0\" # number\nd = \"0.2.0\"\n");
+    }
+    #[test]
+    fn test_read_virtual_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, member, other) = (dir.path().join("Cargo.toml"), dir.path().join("a/Cargo.toml"), dir.path().join("b/Cargo.toml"));
+        fs::create_dir_all(dir.path().join("a")).unwrap();
+        fs::create_dir_all(dir.path().join("b")).unwrap();
+        fs::write(&root, "[workspace]\nmembers = [\"a\", \"b\"]\n[workspace.package]\nversion = \"1.2.0\"\n").unwrap();
+        fs::write(&member, "[package]\nname = \"a\"\nversion.workspace = true\n").unwrap();
+        fs::write(&other, "[package]\nname = \"b\"\nversion = \"0.3.0\"\n").unwrap();
+
+        let content = read_version_tomls(&vec![root.clone(), member.clone(), other.clone()]).unwrap();
+        assert_eq!(content[&member].0.to_string(), "1.2.0");
+        assert_eq!(content[&other].0.to_string(), "0.3.0");
+        assert!(read_version_tomls(&vec![member]).is_err());
+
+        fs::write(&root, "[workspace]\nmembers = [\"b\"]\n").unwrap();
+        assert_eq!(manifest_version(&fs::read_to_string(&root).unwrap().parse().unwrap()), ManifestVersion::Virtual);
+        assert_eq!(read_version_tomls(&vec![root.clone(), other]).unwrap().len(), 1);
+        assert!(read_version_tomls(&vec![root]).is_err());
+    }
+    #[test]
+    fn test_manifest_version_representations() {
+        let version = |s: &str| manifest_version(&s.parse().unwrap());
+        let package = ManifestVersion::Package("1.2.3".to_string());
+        assert_eq!(version("[package]\nname = \"a\"\nversion = '1.2.3' # c\n"), package);
+        assert_eq!(version("package.name = \"a\"\npackage.version = \"1.2.3\"\n"), package);
+        assert_eq!(version("package = { name = \"a\", version = \"1.2.3\" }\n"), package);
+        assert_eq!(version("[package]\nversion.workspace = true\n"), ManifestVersion::Inherited);
+        assert_eq!(version("[package]\nversion = { workspace = true }\n"), ManifestVersion::Inherited);
+        assert_eq!(version("[workspace]\npackage.version = \"1.2.3\"\n"), ManifestVersion::Workspace("1.2.3".to_string()));
+        assert_eq!(version("[package]\nversion = 1\n"), ManifestVersion::NotAString("integer"));
+    }
+    #[test]
+    fn test_select_cargo_tomls_by_package() {
+        let cargo_tomls = find_cargo_tomls_and_git_base(PathBuf::from("./test_data"), true, None, None).unwrap().0;
+        let names: Vec<String> = cargo_tomls.iter().filter_map(|ct| fs::read_to_string(ct).ok()?.parse::<DocumentMut>().ok()?
+            .get("package")?.get("name")?.as_str().map(String::from)).collect();
+        let selected = select_cargo_tomls_by_package(cargo_tomls, &names[..1]).unwrap();
+        assert_eq!(selected.len(), 1);
+        let e = select_cargo_tomls_by_package(selected, &["no-such-crate".to_string()]).unwrap_err();
+        assert_eq!(e.to_string(), "Package 'no-such-crate' not found in the cargo.toml files");
+    }
     #[test]
     fn test_filter_cargo_tomls_by_selector_all() {
         let tomls_simu = vec![PathBuf::from("base"), PathBuf::from("middle"), PathBuf::from("longlonglong")];
 
-        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::All));
+        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::All)).unwrap();
         assert_eq!(r.len(), 3);
         assert_eq!(r[0], tomls_simu[0].clone());
         assert_eq!(r[1], tomls_simu[1].clone());
@@ -223,7 +1770,7 @@ mod tests_filter {
     fn test_filter_cargo_tomls_by_selector_base() {
         let tomls_simu = vec![PathBuf::from("base"), PathBuf::from("middle"), PathBuf::from("longlonglong")];
 
-        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::Base));
+        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::Base)).unwrap();
         assert_eq!(r.len(), 1);
         assert_eq!(r[0], tomls_simu[0].clone());
     }
@@ -231,7 +1778,7 @@ mod tests_filter {
     fn test_filter_cargo_tomls_by_selector_leaf() {
         let tomls_simu = vec![PathBuf::from("base"), PathBuf::from("middle"), PathBuf::from("longlonglong")];
 
-        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::Leaf));
+        let r = filter_cargo_tomls_by_selector(tomls_simu.clone(), &Some(CargoFile::Leaf)).unwrap();
         assert_eq!(r.len(), 1);
         assert_eq!(r[0], tomls_simu[2].clone());
     }
@@ -247,7 +1794,7 @@ mod tests_find {
             panic!("Test needs to be exeecuted in base dir");
         }
 
-        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(PathBuf::from("./test_data"), false);
+        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(PathBuf::from("./test_data"), false, None, None).unwrap();
         assert_eq!(git_base_path, Some(PathBuf::from("./")));
         assert_eq!(cargo_tomls.len(), 1);
         assert_eq!(cargo_tomls[0], PathBuf::from("./Cargo.toml"));
@@ -258,7 +1805,7 @@ mod tests_find {
             panic!("Test needs to be exeecuted in base dir");
         }
 
-        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(PathBuf::from("./test_data"), true);
+        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(PathBuf::from("./test_data"), true, None, None).unwrap();
         let mut cargo_tomls_sorted = cargo_tomls.clone();
         cargo_tomls_sorted.sort_by(|a, b| {
             let a_s = a.display().to_string();
@@ -272,4 +1819,293 @@ mod tests_find {
         assert_eq!(cargo_tomls_sorted[1], PathBuf::from("./test_data/with_different_ver/Cargo.toml"));
         assert_eq!(cargo_tomls_sorted[2], PathBuf::from("./test_data/with_same_ver/Cargo.toml"));
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_find_git_base_of_worktree() {
+        let dir = tempfile::tempdir().unwrap();
+        let crate_dir = dir.path().join("wt/sub");
+        fs::create_dir_all(&crate_dir).unwrap();
+        fs::write(dir.path().join("wt/.git"), "gitdir: /repo/.git/worktrees/wt\n").unwrap();
+        fs::write(crate_dir.join("Cargo.toml"), "").unwrap();
+
+        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(crate_dir.clone(), false, None, None).unwrap();
+        assert_eq!(git_base_path, Some(dir.path().join("wt")));
+        assert_eq!(cargo_tomls, vec![crate_dir.join("Cargo.toml")]);
+
+    }
+    #[test]
+    fn test_exclude_cargo_tomls() {
+        let tomls = ["./Cargo.toml", "./crates/a/Cargo.toml", "./examples/demo/Cargo.toml", "./fuzz/Cargo.toml"]
+            .map(PathBuf::from).to_vec();
+        let excludes = ["examples/**".to_string(), "fuzz".to_string()];
+        assert_eq!(exclude_cargo_tomls(tomls.clone(), Some(Path::new(".")), &excludes), tomls[..2].to_vec());
+        assert_eq!(exclude_cargo_tomls(tomls.clone(), None, &["/crates/*".to_string()]).len(), 3);
+    }
+    #[test]
+    fn test_scan_skips_ignored_and_build_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init(dir.path()).unwrap();
+        fs::write(dir.path().join(".gitignore"), "generated/\n").unwrap();
+        for d in ["crate_a", "target/package/crate_a-0.1.0", "node_modules/x", "vendor/serde", "generated"] {
+            fs::create_dir_all(dir.path().join(d)).unwrap();
+            fs::write(dir.path().join(d).join("Cargo.toml"), "").unwrap();
+        }
+        let (cargo_tomls, _) = find_cargo_tomls_and_git_base(dir.path().to_path_buf(), true, None, None).unwrap();
+        assert_eq!(cargo_tomls, vec![dir.path().join("crate_a/Cargo.toml")]);
+    }
+    #[test]
+    fn test_find_cargo_tomls_up_to_work_tree() {
+        // the work tree of a separate git dir has no .git, the search stops at it
+        let dir = tempfile::tempdir().unwrap();
+        let work_tree = fs::canonicalize(dir.path()).unwrap().join("work");
+        fs::create_dir_all(work_tree.join("crate")).unwrap();
+        fs::write(work_tree.join("Cargo.toml"), "").unwrap();
+        fs::write(work_tree.join("crate/Cargo.toml"), "").unwrap();
+
+        let (cargo_tomls, git_base_path) = find_cargo_tomls_and_git_base(work_tree.join("crate"), false, Some(&work_tree), None).unwrap();
+        assert_eq!(git_base_path, Some(work_tree.clone()));
+        assert_eq!(cargo_tomls, vec![work_tree.join("crate/Cargo.toml"), work_tree.join("Cargo.toml")]);
+    }
+    #[test]
+    fn test_manifest_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = fs::canonicalize(dir.path()).unwrap();
+        Repository::init(&base).unwrap();
+        for d in ["", "crate_a", "crate_b"] { fs::create_dir_all(base.join(d)).unwrap(); fs::write(base.join(d).join("Cargo.toml"), "").unwrap(); }
+        // only the given ones, neither the root nor crate_b is searched
+        let manifests = [base.join("crate_a/Cargo.toml"), base.join("crate_a/Cargo.toml")];
+        let (cargo_tomls, git_base_path) = manifest_paths_and_git_base(&manifests, None, None).unwrap();
+        assert_eq!((cargo_tomls, git_base_path), (vec![base.join("crate_a/Cargo.toml")], Some(base.clone())));
+
+        assert_eq!(manifest_paths_and_git_base(&[base.join("crate_c/Cargo.toml")], None, None).unwrap_err().exit_code(), 3);
+        assert_eq!(manifest_paths_and_git_base(&[base.join("crate_a")], None, None).unwrap_err().exit_code(), 2);
+        let other = tempfile::tempdir().unwrap();
+        fs::write(other.path().join("Cargo.toml"), "").unwrap();
+        let e = manifest_paths_and_git_base(&[base.join("Cargo.toml"), other.path().join("Cargo.toml")], None, None).unwrap_err();
+        assert!(e.to_string().contains("is not in the git repository"));
+    }
+}
+#[cfg(test)]
+mod tests_writable {
+    use super::*;
+
+    #[test]
+    fn test_check_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let file = dir.path().join("Cargo.toml");
+        fs::write(&file, "").unwrap();
+        check_writable(&[file, dir.path().join("CHANGELOG.md")], &repo).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let e = check_writable(&[dir.path().join("missing/CHANGELOG.md")], &repo).unwrap_err();
+        assert!(e.to_string().starts_with(&format!("Cannot create files in directory '{}'", dir.path().join("missing").display())));
+    }
+    #[test]
+    fn test_write_file_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Cargo.toml");
+        fs::write(&file, "version = \"0.1.0\"\n").unwrap();
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions).unwrap();
+        write_file_atomic(&file, b"version = \"0.2.0\"\n").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "version = \"0.2.0\"\n");
+        assert!(fs::metadata(&file).unwrap().permissions().readonly());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(write_file_atomic(&dir.path().join("missing/Cargo.toml"), b"").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+    #[test]
+    fn test_check_uncommitted_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        fs::create_dir(dir.path().join("gen")).unwrap();
+        for f in ["Cargo.toml", "README", "gen/out.txt"] { fs::write(dir.path().join(f), "a").unwrap(); }
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let written = [dir.path().join("Cargo.toml")];
+        let ignore = ["gen/**".to_string()];
+
+        fs::write(dir.path().join("gen/out.txt"), "b").unwrap();
+        assert_eq!(check_uncommitted_changes(&repo, dir.path(), false, &[], &written, &[]).unwrap_err().exit_code(), 7);
+        check_uncommitted_changes(&repo, dir.path(), true, &ignore, &written, &[]).unwrap();
+        fs::write(dir.path().join("README"), "b").unwrap();
+        assert_eq!(check_uncommitted_changes(&repo, dir.path(), true, &ignore, &written, &[]).unwrap_err().to_string(),
+                   Error::DirtyTree(1).to_string());
+        check_uncommitted_changes(&repo, dir.path(), true, &[], &written, &[]).unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "b").unwrap();
+        assert_eq!(check_uncommitted_changes(&repo, dir.path(), true, &[], &written, &[]).unwrap_err().exit_code(), 2);
+        // extra files are committed with the release, so their changes are fine without --allow-dirty
+        let extra = [dir.path().join("Cargo.toml"), dir.path().join("README"), dir.path().join("gen/out.txt")];
+        check_uncommitted_changes(&repo, dir.path(), false, &[], &[], &extra).unwrap();
+        assert_eq!(check_uncommitted_changes(&repo, dir.path(), false, &[], &[], &extra[1..]).unwrap_err().to_string(),
+                   Error::DirtyTree(1).to_string());
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_lease {
+    use super::*;
+    use git2::Signature;
+
+    #[test]
+    fn test_push_with_lease_detects_remote_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init_bare(dir.path().join("remote.git")).unwrap();
+        let repo = Repository::init(dir.path().join("work")).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("refs/heads/main"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let parent = repo.find_commit(first).unwrap();
+        let second = repo.commit(Some("refs/heads/main"), &sig, &sig, "second", &tree, &[&parent]).unwrap();
+        let mut remote = repo.remote("origin", dir.path().join("remote.git").to_str().unwrap()).unwrap();
+        remote.push(&["refs/heads/main"], None).unwrap();
+
+        // remote is at the second commit, a lease on the first one has to fail
+        repo.reference("refs/heads/main", first, true, "reset").unwrap();
+        let mut po = create_push_options_with_lease(&repo, "", vec![("refs/heads/main".into(), first)]).unwrap();
+        assert!(remote.push(&["+refs/heads/main"], Some(&mut po)).is_err());
+        let mut po = create_push_options_with_lease(&repo, "", vec![("refs/heads/main".into(), second)]).unwrap();
+        assert!(remote.push(&["+refs/heads/main"], Some(&mut po)).is_ok());
+        let remote_repo = Repository::open_bare(dir.path().join("remote.git")).unwrap();
+        assert_eq!(remote_repo.refname_to_id("refs/heads/main").unwrap(), first);
+    }
+    #[test]
+    fn test_fetch_branch_of_empty_remote_and_deleted_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init_bare(dir.path().join("remote.git")).unwrap();
+        let repo = Repository::init(dir.path().join("work")).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("refs/heads/main"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let mut remote = repo.remote("origin", dir.path().join("remote.git").to_str().unwrap()).unwrap();
+        assert_eq!(fetch_branch(&repo, &mut remote, "main").unwrap(), None);
+
+        remote.push(&["refs/heads/main"], None).unwrap();
+        assert_eq!(fetch_branch(&repo, &mut remote, "main").unwrap(), Some(first));
+        remote.push(&[":refs/heads/main"], None).unwrap();
+        assert_eq!(fetch_branch(&repo, &mut remote, "main").unwrap(), None);
+    }
+    #[test]
+    fn test_branch_upstream() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        assert_eq!(branch_upstream(&repo, "feature").unwrap_err().exit_code(), 2);
+        config.set_str("remote.upstream.url", "../remote.git").unwrap();
+        config.set_str("branch.feature.remote", "upstream").unwrap();
+        config.set_str("branch.feature.merge", "refs/heads/main").unwrap();
+        assert_eq!(branch_upstream(&repo, "feature").unwrap(), ("upstream".to_string(), "main".to_string()));
+    }
+    #[test]
+    fn test_fetch_remote_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        Repository::init_bare(dir.path().join("remote.git")).unwrap();
+        let repo = Repository::init(dir.path().join("work")).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = repo.commit(Some("refs/heads/main"), &sig, &sig, "first", &tree, &[]).unwrap();
+        let mut remote = repo.remote("origin", dir.path().join("remote.git").to_str().unwrap()).unwrap();
+        assert!(fetch_remote_tags(&repo, &mut remote).unwrap().is_empty());
+
+        repo.reference("refs/tags/v0.1.0", first, false, "tag").unwrap();
+        remote.push(&["refs/heads/main", "refs/tags/v0.1.0"], None).unwrap();
+        repo.find_reference("refs/tags/v0.1.0").unwrap().delete().unwrap();
+        assert_eq!(fetch_remote_tags(&repo, &mut remote).unwrap(), HashMap::from([("v0.1.0".to_string(), first)]));
+        assert!(repo.find_reference("refs/tags/v0.1.0").is_err());
+        remote.push(&[":refs/tags/v0.1.0"], None).unwrap();
+        assert!(fetch_remote_tags(&repo, &mut remote).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_push_rejection_report() {
+        let output = "To ../remote.git\n!\trefs/heads/main:refs/heads/main\t[remote rejected] (pre-receive hook declined)\n\
+                      *\trefs/tags/v1.0.0:refs/tags/v1.0.0\t[new tag]\nDone\n";
+        let mut report = PushReport::from_porcelain(output);
+        assert_eq!(report.rejected, vec![("refs/heads/main".to_string(), "pre-receive hook declined".to_string())]);
+        assert_eq!(report.accepted, vec!["refs/tags/v1.0.0".to_string()]);
+        assert_eq!(Rejection::classify("pre-receive hook declined", &report.remote_messages), Rejection::HookDeclined);
+        report.remote_messages.push("GitLab: You are not allowed to push code to protected branches on this project.".to_string());
+        assert_eq!(Rejection::classify("pre-receive hook declined", &report.remote_messages), Rejection::ProtectedBranch);
+        assert_eq!(Rejection::classify("rejected (fetch first)", &[]), Rejection::NonFastForward);
+
+        let e = report.into_result("origin").unwrap_err();
+        assert_eq!(e.exit_code(), 16);
+        assert!(e.to_string().contains("--create-pr") && e.to_string().contains("git push origin --delete refs/tags/v1.0.0"));
+        assert!(PushReport::from_porcelain("To ../remote.git\n=\trefs/heads/main:refs/heads/main\t[up to date]\nDone\n").into_result("origin").is_ok());
+    }
+    #[test]
+    fn test_push_retry() {
+        assert_eq!([1, 2, 3, 6, 40].map(|n| push_backoff(n).as_secs()), [1, 2, 4, 30, 30]);
+        let net = Error::remote("push", git2::Error::new(git2::ErrorCode::GenericError, git2::ErrorClass::Net, "connection reset"));
+        let rejected = Error::remote("push", git2::Error::from_str("stale info"));
+        assert!(is_transient_push_error(&net, false));
+        assert!(!is_transient_push_error(&rejected, false));
+        assert!(is_transient_push_error(&rejected, true));
+        assert!(!is_transient_push_error(&Error::Usage("x".to_string()), true));
+    }
+}
+
+#[cfg(test)]
+mod tests_proxy {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_find_proxy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::open(&dir.path().join("config")).unwrap();
+        let env: HashMap<&str, &str> = HashMap::from([("https_proxy", "http://env:8080"), ("no_proxy", "internal.example.com")]);
+        let env = |k: &str| env.get(k).map(|v| v.to_string());
+
+        assert_eq!(find_proxy(&config, "https://github.com/a/b.git", env), Some("http://env:8080".to_string()));
+        assert_eq!(find_proxy(&config, "https://git.internal.example.com/a/b.git", env), None);
+        assert_eq!(find_proxy(&config, "git@github.com:a/b.git", env), None);
+        config.set_str("http.proxy", "http://config:3128").unwrap();
+        assert_eq!(find_proxy(&config, "https://user@github.com/a/b.git", env), Some("http://config:3128".to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests_auth {
+    use super::*;
+
+    #[test]
+    fn test_ssh_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("deploy_key");
+        assert_eq!(SshKey::new(key.clone(), None).unwrap_err().exit_code(), 2);
+        fs::write(&key, "").unwrap();
+        assert!(SshKey::new(key.clone(), None).unwrap().passphrase.is_none());
+        assert!(SshKey::new(key, Some("CGVS_TEST_UNSET_PASSPHRASE")).is_err());
+    }
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(parse_identity("Release Bot <bot@example.com>"), Ok(("Release Bot".to_string(), "bot@example.com".to_string())));
+        assert!(parse_identity("bot@example.com").is_err());
+        assert!(parse_identity(" <bot@example.com>").is_err());
+    }
+    #[test]
+    fn test_find_git_token() {
+        let env = |k: &str| match k { "GIT_TOKEN" => Some(String::new()), "GITHUB_TOKEN" => Some("gh".to_string()), _ => None };
+        assert_eq!(find_git_token("https://github.com/a/b.git", None, env), Some("gh".to_string()));
+        let git_token = |k: &str| (k == "GIT_TOKEN").then(|| "git".to_string());
+        assert_eq!(find_git_token("https://git.example.com/a/b.git", Some("git.example.com"), git_token), Some("git".to_string()));
+        assert_eq!(find_git_token("git@git.example.com:a/b.git", Some("git.example.com"), git_token), None);
+        assert_eq!(find_git_token("https://github.com/a/b.git", None, |_| None), None);
+
+        // a foreign host gets no token: neither the GitHub token nor the one of another host
+        let all = |k: &str| match k { "GIT_TOKEN" => Some("git".to_string()), "GITHUB_TOKEN" => Some("gh".to_string()), _ => None };
+        assert_eq!(find_git_token("https://gitlab.com/a/b.git", Some("git.example.com"), all), None);
+        assert_eq!(find_git_token("https://gitlab.com/a/b.git", None, all), None);
+        assert_eq!(find_git_token("https://github.com/a/b.git", Some("git.example.com"), all), Some("gh".to_string()));
+        let ghes = |k: &str| match k { "GITHUB_SERVER_URL" => Some("https://ghes.example.com/".to_string()), _ => all(k) };
+        assert_eq!(find_git_token("https://ghes.example.com/a/b.git", None, ghes), Some("gh".to_string()));
+    }
+}
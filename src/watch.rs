@@ -0,0 +1,158 @@
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use git2::{Repository, ResetType};
+use crate::error::{Error, Result};
+use crate::notes::{commits_since, find_release_directive, CommitInfo};
+use crate::utils::*;
+
+// ********************************************************
+// ********************************************************
+/// Conditions which trigger a release in watch mode.
+pub struct WatchConditions {
+    /// Release if at least this many commits were made since the last version tag
+    pub min_commits: Option<usize>,
+    /// Version part incremented for releases triggered by `min_commits`
+    pub vtype: IncrementVersionPart,
+    /// Release if a merge commit since the last version tag contains a `[release: <part>]` directive
+    pub release_label: bool,
+}
+impl WatchConditions {
+    /// Returns the version part to increment if a release is due.
+    pub fn evaluate(&self, commits: &[CommitInfo]) -> Option<IncrementVersionPart> {
+        // commits created by this tool (e.g. the release commit itself) do not count
+        let commits: Vec<_> = commits.iter().filter(|c| !c.message.starts_with(COMMIT_MESSAGE_PREFIX)).collect();
+        if self.release_label {
+            let labeled = commits.iter().filter(|c| c.is_merge)
+                .filter_map(|c| find_release_directive(&c.message)).max();
+            if labeled.is_some() { return labeled; }
+        }
+        match self.min_commits {
+            Some(n) if commits.len() >= n && !commits.is_empty() => Some(self.vtype.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Fetches the branch checked out in HEAD from the remote and fast-forwards it if possible.
+fn update_branch(repo: &Repository, remote_name: &str) -> Result<()> {
+    let head = repo.head().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    let branch = head.shorthand().ok_or(Error::Repository("HEAD is not a valid utf-8 reference".into()))?.to_string();
+    let mut remote = repo.find_remote(remote_name)
+        .map_err(|e| Error::remote(format!("Failed to find git remote '{}'", remote_name), e))?;
+    let Some(upstream) = fetch_branch(repo, &mut remote, &branch)? else { return Ok(()); };
+    let fetch_head = repo.find_commit(upstream).map_err(|e| Error::git(format!("Could not find commit {}", upstream), e))?;
+    let local = head.peel_to_commit().map_err(|e| Error::git("Could not resolve HEAD", e))?;
+    if fetch_head.id() == local.id() || repo.graph_descendant_of(local.id(), fetch_head.id()).unwrap_or(false) {
+        return Ok(());
+    }
+    if !repo.graph_descendant_of(fetch_head.id(), local.id()).unwrap_or(false) {
+        return Err(Error::Repository(format!("Branch '{}' has diverged from '{}', cannot fast-forward", branch, remote_name)));
+    }
+    let change_count = count_uncommitted_changes(repo)?;
+    if change_count > 0 { return Err(Error::DirtyTree(change_count)); }
+    repo.reset(fetch_head.as_object(), ResetType::Hard, None)
+        .map_err(|e| Error::git(format!("Could not fast-forward '{}'", branch), e))?;
+    progress!("{INDENT}Fast-forwarded '{}' to {}", branch, fetch_head.id());
+    Ok(())
+}
+
+/// Returns the global options of the command line `args` (without the program name), i.e. everything
+/// before the subcommand. The arguments are walked with the options of `cmd`, so an option value like
+/// `-t watch` or `--remote watch` is not mistaken for the subcommand.
+pub fn global_args<'a>(cmd: &clap::Command, args: &'a [String]) -> &'a [String] {
+    let takes_value = |arg: Option<&clap::Arg>| arg.is_some_and(|a| a.get_action().takes_values());
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "-" || arg == "--" || !arg.starts_with('-') { break; }
+        if let Some(long) = arg.strip_prefix("--") {
+            let value_follows = !long.contains('=')
+                && takes_value(cmd.get_arguments().find(|a| a.get_long() == Some(long) || a.get_all_aliases().is_some_and(|al| al.contains(&long))));
+            if value_follows { i += 1; }
+        } else {
+            // cluster of short flags like -vvs, an option ends it and takes the rest or the next argument as value
+            let shorts: Vec<char> = arg[1..].chars().collect();
+            if let Some(n) = shorts.iter().position(|c| takes_value(cmd.get_arguments().find(|a| a.get_short() == Some(*c))))
+                && n + 1 == shorts.len() { i += 1; }
+        }
+        i += 1;
+    }
+    &args[..i]
+}
+
+/// Polls the repository and cuts a release (by running this binary with `--yes increment <part>` and
+/// the given global arguments) whenever the conditions are met. Runs forever unless `once` is set.
+pub fn watch(repo: &Repository, tag_format: &TagFormat, remote_name: Option<&str>, conditions: &WatchConditions,
+             interval: Duration, once: bool, release_args: &[String]) -> Result<()> {
+    let exe = std::env::current_exe().map_err(|e| Error::io("Could not determine executable", e))?;
+    loop {
+        if let Some(remote_name) = remote_name
+            && let Err(e) = update_branch(repo, remote_name) {
+            print_warn(e.to_string());
+        }
+
+        let last_tag = latest_version_tag(repo, tag_format)?.map(|(tn, _)| tn);
+        let commits = commits_since(repo, last_tag.as_deref())?;
+        match conditions.evaluate(&commits) {
+            None => progress!("{INDENT}{} commit(s) since {}, no release due", commits.len(), last_tag.as_deref().unwrap_or("start")),
+            Some(vtype) => {
+                progress!("{INDENT}Release due, incrementing {}", vtype);
+                let status = Command::new(&exe).args(release_args).arg("--yes").arg("increment").arg(vtype.to_string()).status()
+                    .map_err(|e| Error::io("Could not run release", e))?;
+                if !status.success() { print_warn(format!("Release failed ({})", status)); }
+            }
+        }
+
+        if once { return Ok(()); }
+        thread::sleep(interval);
+    }
+}
+
+// ********************************************************
+// ********************************************************
+#[cfg(test)]
+mod tests_watch {
+    use super::*;
+    use git2::Oid;
+    use crate::notes::CommitKind;
+
+    fn commit(message: &str, is_merge: bool) -> CommitInfo {
+        CommitInfo { id: Oid::zero(), kind: CommitKind::Other, scope: None, description: message.to_string(),
+            message: message.to_string(), is_merge }
+    }
+
+    #[test]
+    fn test_evaluate_conditions() {
+        let c = WatchConditions { min_commits: Some(2), vtype: IncrementVersionPart::Patch, release_label: true };
+        assert_eq!(c.evaluate(&[commit("fix: a", false)]), None);
+        assert_eq!(c.evaluate(&[commit("fix: a", false), commit("fix: b", false)]), Some(IncrementVersionPart::Patch));
+        assert_eq!(c.evaluate(&[commit("Merge [release: minor]", true)]), Some(IncrementVersionPart::Minor));
+        // directives outside of merge commits and the tool's own commits are ignored
+        assert_eq!(c.evaluate(&[commit("feat: [release: major]", false)]), None);
+        assert_eq!(c.evaluate(&[commit(&format!("{COMMIT_MESSAGE_PREFIX} '0.2.0'"), false), commit("fix: a", false)]), None);
+    }
+
+    #[test]
+    fn test_global_args() {
+        use clap::{Arg, ArgAction};
+        let cmd = clap::Command::new("cgvs")
+            .arg(Arg::new("tag").short('t').long("tag-message"))
+            .arg(Arg::new("remote").long("remote").alias("git-remote"))
+            .arg(Arg::new("verbose").short('v').action(ArgAction::Count))
+            .arg(Arg::new("scan").short('s').action(ArgAction::SetTrue))
+            .subcommand(clap::Command::new("watch"));
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        let cases = [
+            ("-t watch --remote watch watch --once", "-t watch --remote watch"),
+            ("-vvst watch watch", "-vvst watch"),
+            ("-twatch --git-remote=watch -v watch", "-twatch --git-remote=watch -v"),
+            ("--git-remote watch watch", "--git-remote watch"),
+            ("watch -t x", ""),
+        ];
+        for (line, expected) in cases {
+            let args = args(line);
+            assert_eq!(global_args(&cmd, &args).join(" "), expected, "{}", line);
+        }
+    }
+}